@@ -0,0 +1,132 @@
+use crate::error::DownloadError;
+use crate::sidecar;
+use md5::Md5;
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::Path;
+use tokio::task;
+
+/// A hash a checksum sidecar can hold, named by the sidecar's extension.
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum Algorithm {
+    Sha256,
+    Sha1,
+    Md5,
+}
+
+impl Algorithm {
+    /// In the order sidecars are looked for.
+    pub(crate) const ALL: [Algorithm; 3] = [Algorithm::Sha256, Algorithm::Sha1, Algorithm::Md5];
+
+    pub(crate) fn extension(self) -> &'static str {
+        match self {
+            Algorithm::Sha256 => "sha256",
+            Algorithm::Sha1 => "sha1",
+            Algorithm::Md5 => "md5",
+        }
+    }
+
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            Algorithm::Sha256 => "SHA-256",
+            Algorithm::Sha1 => "SHA-1",
+            Algorithm::Md5 => "MD5",
+        }
+    }
+
+    fn hex_len(self) -> usize {
+        match self {
+            Algorithm::Sha256 => 64,
+            Algorithm::Sha1 => 40,
+            Algorithm::Md5 => 32,
+        }
+    }
+
+    /// The lowercase hex digest of the file at `path`.
+    pub(crate) fn hash_file(self, path: &Path) -> io::Result<String> {
+        fn hex<D: Digest>(mut hasher: D, path: &Path) -> io::Result<String> {
+            hash_file(&mut hasher, path)?;
+            Ok(hasher.finalize().iter().map(|b| format!("{:02x}", b)).collect())
+        }
+        match self {
+            Algorithm::Sha256 => hex(Sha256::new(), path),
+            Algorithm::Sha1 => hex(Sha1::new(), path),
+            Algorithm::Md5 => hex(Md5::new(), path),
+        }
+    }
+}
+
+/// The digest for `name` in a checksum file, lowercased.
+///
+/// Lines are `<hash>  <name>` (GNU, with `*` marking binary mode),
+/// `ALGO (<name>) = <hash>` (BSD) or a bare `<hash>`. With a single
+/// entry the name doesn't have to match, since sidecars often list the
+/// file under a build path or not at all.
+pub(crate) fn parse_sidecar(text: &str, name: &str, algorithm: Algorithm) -> Option<String> {
+    let is_hash = |s: &str| s.len() == algorithm.hex_len() && s.bytes().all(|b| b.is_ascii_hexdigit());
+    let entries: Vec<(String, Option<&str>)> = text
+        .lines()
+        .filter_map(|line| {
+            let line = line.trim();
+            if let Some((left, hash)) = line.rsplit_once(") = ") {
+                let (_, file) = left.split_once(" (")?;
+                return Some((hash.trim(), Some(file)));
+            }
+            let mut parts = line.splitn(2, char::is_whitespace);
+            let hash = parts.next()?;
+            let file = parts.next().map(|f| f.trim_start().trim_start_matches('*'));
+            Some((hash, file))
+        })
+        .filter(|(hash, _)| is_hash(hash))
+        .map(|(hash, file)| (hash.to_ascii_lowercase(), file))
+        .collect();
+
+    let base = |file: &str| file.rsplit(['/', '\\']).next().unwrap_or(file).to_string();
+    match entries.as_slice() {
+        [(hash, _)] => Some(hash.clone()),
+        entries => entries
+            .iter()
+            .find(|(_, file)| file.is_some_and(|f| base(f) == name))
+            .map(|(hash, _)| hash.clone()),
+    }
+}
+
+/// Feeds bytes already on disk (from an earlier partial download) into the hasher.
+pub(crate) fn hash_file<D: Digest>(hasher: &mut D, path: &Path) -> io::Result<()> {
+    let mut file = File::open(path)?;
+    let mut buf = [0u8; 64 * 1024];
+    loop {
+        let n = file.read(&mut buf)?;
+        if n == 0 {
+            return Ok(());
+        }
+        hasher.update(&buf[..n]);
+    }
+}
+
+/// [`hash_file`] on the blocking pool, since a whole file can take a
+/// while to read, handing the hasher back.
+pub(crate) async fn hash_part<D: Digest + Send + 'static>(mut hasher: D, path: &Path) -> io::Result<D> {
+    let path = path.to_path_buf();
+    task::spawn_blocking(move || hash_file(&mut hasher, &path).map(|()| hasher)).await.map_err(io::Error::other)?
+}
+
+/// Compares the digest against `expected`, deleting the .part `path` and
+/// its sidecar on mismatch.
+pub(crate) fn verify(hasher: Sha256, expected: &str, url: &str, path: &Path) -> Result<(), DownloadError> {
+    let actual = format!("{:x}", hasher.finalize());
+    if actual == expected {
+        return Ok(());
+    }
+
+    fs::remove_file(path)?;
+    sidecar::remove(path)?;
+    Err(DownloadError::ChecksumMismatch {
+        url: url.to_string(),
+        algorithm: "SHA-256",
+        expected: expected.to_string(),
+        actual,
+    })
+}
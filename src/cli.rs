@@ -0,0 +1,938 @@
+use crate::config::Config;
+use crate::glob;
+use crate::input;
+use crate::metalink;
+use reqwest::header::{HeaderName, HeaderValue, AUTHORIZATION, ORIGIN, REFERER, USER_AGENT};
+use reqwest::Url;
+use rs_downloader::{check_name, parse_date, parse_rate, session_urls, IpFamily, OutputTemplate, ProgressMode, Referer, SegmentStrategy, Units, DEFAULT_PROGRESS_INTERVAL, MIN_PROGRESS_INTERVAL};
+use std::collections::HashMap;
+use std::path::Path;
+use std::str::FromStr;
+use std::time::{Duration, SystemTime};
+
+const USAGE: &str = "\
+Usage: {program} [options] [--] <url1> [url2] [url3] ...
+
+Options:
+  --config FILE          Read defaults for these options from a TOML file
+  --input-file FILE|-    Read URLs from FILE, one per line (- for stdin),
+                         each optionally preceded by a priority and given
+                         a file name as URL<tab>NAME or NAME = URL. A .meta4
+                         or .metalink FILE gives each file's name, mirrors,
+                         size and SHA-256 instead
+  --priority N           Download the URLs after this before lower-priority
+                         ones (default 0; higher goes first)
+  --no-glob              Take URLs literally instead of expanding [001-200],
+                         [a-z], [1-100:10] and {a,b,c} into one URL each
+  --max-concurrent N     Download at most N files at once (default 4)
+  --max-per-host N       ...and at most N of them from the same host
+                         (default unlimited)
+  --retries N            Retry transient failures up to N times (default 0)
+  --retry-on-status LIST Retry only these statuses, e.g. 429,503 or 500-599
+                         (default 408,429,500,502,503,504)
+  --retry-failed         Once every URL has been tried, try the failed ones
+                         once more, resuming where they stopped
+  --output-dir DIR       Save files into DIR, creating it if needed
+  --preserve-path        Save into HOST/PATH/ subdirectories following each URL
+  --output-template PATTERN
+                         Save each file at PATTERN inside the output
+                         directory, e.g. '{host}/{date}/{filename}', from
+                         {filename}, {stem}, {ext}, {host}, {path}, {index}
+                         (the URL's place in the batch) and {date} (UTC);
+                         overrides --preserve-path
+  -o NAME                Save the single URL as NAME
+  -O -, -o -             Write the single URL's body to stdout, showing no
+                         progress
+  --connections N        Split each file across up to N ranged requests
+  --segment-strategy seek|temp-files
+                         Write the pieces at their offsets in one file (seek,
+                         the default), or each to its own file, joined at the
+                         end, for filesystems where seeking is unreliable
+  --force                Re-download files that already exist
+  -c, --continue         List the URLs with a .part file from an earlier run
+                         before resuming them; the rest start fresh
+  --if-changed           Re-check existing files, skipping ones the server
+                         reports unchanged since they were saved
+  -N, --newer-only       Re-check existing files, downloading one again only
+                         if the server's Last-Modified is newer than its
+                         modification time
+  --if-modified-since DATE
+                         Skip URLs the server says haven't changed since DATE,
+                         given as RFC 2822 or ISO 8601 (UTC unless it has a
+                         zone), whether or not they exist here
+  --buffer-size SIZE     Buffer SIZE bytes before writing, e.g. 256k (default 64k)
+  --max-open-files N     Keep at most N downloaded files open at once, closing
+                         and reopening them between writes when they're all
+                         taken (default 256)
+  --preallocate          Reserve each file's full size before downloading
+  --no-timestamping      Don't set file times from Last-Modified
+  --decompress           Accept gzip-compressed responses and save them
+                         decompressed
+  --no-decompress        Save responses exactly as sent (the default)
+  --decompress-gzip      Save gzip files decompressed, without the .gz in
+                         their names; progress counts the compressed bytes
+  --max-size SIZE        Reject files bigger than SIZE, e.g. 500m or 2g
+  --fail-on-empty        Fail, and retry, downloads that finish with no
+                         bytes instead of saving an empty file
+  --expect-content-type TYPE
+                         Fail downloads whose Content-Type isn't TYPE, e.g.
+                         application/zip or application/* (comma-separated)
+  --limit-rate RATE      Cap total speed, e.g. 500k or 2m (bytes per second)
+  --limit-rate-per-file RATE
+                         Cap each download's own speed, within --limit-rate
+  --timeout SECS         Give up on a request after SECS in total
+  --per-file-timeout SECS
+                         Give up on an attempt at a file after SECS, however
+                         fast it is going (retried like other timeouts)
+  --max-total-time SECS  Cancel whatever is still running SECS after the start,
+                         keeping partial files, and exit with status 124
+  --max-total-bytes SIZE Stop once more than SIZE has been received this run,
+                         e.g. 2g, keeping partial files, and exit with status 124
+  --idle-timeout SECS    Abort a download when no data arrives for SECS
+  --min-speed RATE       Abort a download that stays slower than RATE...
+  --min-speed-time SECS  ...for SECS (default 30)
+  --wait SECS            Wait SECS after a download starts or finishes before
+                         starting the next
+  --random-wait          Vary each --wait between 0.5 and 1.5 times SECS
+  --max-redirects N      Follow at most N redirects per request (default 10)
+  --no-redirect          Fail on a redirect instead of following it
+  --header 'NAME: VALUE' Add a request header (repeatable)
+  --user-agent STRING    Send STRING as the User-Agent (default rs-downloader/VERSION)
+  --referer URL          Send URL as the Referer, kept across redirects; 'auto'
+                         sends each download's own origin
+  --origin URL           Send URL's scheme, host and port as the Origin
+  --user USER[:PASS]     Use HTTP Basic authentication
+  --digest               Use --user for HTTP Digest authentication instead
+  --bearer TOKEN         Send an Authorization: Bearer header
+  --cookie 'NAME=VALUE'  Send a cookie with every request (repeatable). Cookies
+                         responses set are kept and sent back for the run
+  --session FILE.json    Record each URL's status and progress in FILE, so a
+                         later run with it skips what finished and resumes the
+                         rest where they were being saved; with no URLs it
+                         picks up the session's
+  --cookie-jar FILE      Start with the cookies in Netscape-format FILE and
+                         save them back to it at the end
+  --cacert FILE          Also trust the CA certificates in PEM FILE (repeatable)
+  --cert FILE            Present the PEM client certificate in FILE...
+  --key FILE             ...with the PKCS#8 PEM private key in FILE
+  --insecure             Skip certificate checks. Unsafe: anyone in between
+                         can read and change what you download
+  -4, --ipv4             Connect over IPv4 only
+  -6, --ipv6             Connect over IPv6 only
+  --ip-fallback          With -4 or -6, use the other IP version for hosts
+                         that can't be reached over the chosen one
+  --pool-max-idle-per-host N
+                         Keep up to N idle connections per host for reuse
+                         (default 10; 0 closes each after its request). With
+                         more running on a host, as --max-per-host times
+                         --connections allows, the extra ones are remade
+  --pool-idle-timeout SECS
+                         Close kept connections idle for SECS (default 90)
+  --tcp-keepalive SECS   Send TCP keepalives on connections idle for SECS
+  --proxy URL            Use an http, https or socks5 proxy for every request
+  --mirror URL           Try URL if the preceding URL fails (repeatable); with
+                         the same [1-10] pattern, each URL gets its own
+  --sha256 [URL=]HEX     Verify the SHA-256 of every file, or just URL's
+  --verify-sidecar       Check each file against URL.sha256, URL.sha1 or URL.md5
+                         on the server, warning if there isn't one
+  --require-checksum     Like --verify-sidecar, but fail without a sidecar
+  --units si|binary      Show sizes in powers of 1000 (default) or 1024
+  --chmod MODE           Set each downloaded file's permissions to the octal
+                         MODE, e.g. 755 (Unix only)
+  --exec 'CMD {}'        Run CMD on each downloaded file, {} being its path
+  --report FILE.csv      Write a CSV row per URL with its outcome, size and time
+  --head-first           Check every URL with a HEAD request before downloading
+                         any, reporting the unreachable ones and counting the
+                         sizes found in the total progress from the start
+  --fail-fast            With --head-first, download nothing if any URL fails
+  --spider               Check that every URL answers with a 2xx status, without
+                         downloading anything, and list the broken ones
+  --dry-run              Show where each URL would be saved and its size, then
+                         exit without downloading
+  --notify               Show a desktop notification when the downloads finish
+                         (in builds with the notify feature)
+  --metrics-addr HOST:PORT
+                         Serve Prometheus metrics at http://HOST:PORT/metrics
+                         while downloading: bytes received, active, completed,
+                         skipped and failed downloads, and the current speed
+                         (in builds with the metrics feature)
+  -v, --verbose          Log requests, responses and retries to stderr, and
+                         show each download's speed under the total;
+                         -vv also logs every header. RUST_LOG overrides this
+  --timing               Print each download's DNS, header and first-byte
+                         times and the address it came from
+  --progress-interval MS Redraw progress every MS milliseconds (default 500,
+                         at least 50); 0 redraws only as downloads start
+                         and finish
+  --progress MODE        interactive redraws progress in place, plain prints
+                         a line per update; auto (the default) redraws only
+                         when stdout is a terminal
+  --tui                  Show every download with its own progress bar on a
+                         full-screen display (terminals only)
+  -q, --quiet            Don't show progress, only the summary of failures
+  --json                 Print newline-delimited JSON events instead of
+                         progress and the summary
+
+Progress is redrawn in place on a terminal and printed as plain lines
+otherwise, unless --progress says which. Colour is disabled when NO_COLOR
+is set. Without --proxy, the HTTP_PROXY, HTTPS_PROXY and ALL_PROXY
+environment variables are used.
+
+A --config file uses the long option names as keys, plus a [headers] table:
+
+  max-concurrent = 8
+  limit-rate = \"2m\"
+  output-dir = \"downloads\"
+
+  [headers]
+  Accept = \"*/*\"";
+
+pub struct Options {
+    pub max_concurrent: usize,
+    pub max_per_host: Option<usize>,
+    pub retries: u32,
+    pub retry_statuses: Option<Vec<u16>>,
+    pub retry_failed: bool,
+    pub output_dir: Option<String>,
+    pub output_name: Option<String>,
+    /// `(url, name)` pairs from input files.
+    pub file_names: Vec<(String, String)>,
+    pub preserve_path: bool,
+    pub output_template: Option<OutputTemplate>,
+    /// Stream the body to stdout, from `-O -` or `-o -`.
+    pub to_stdout: bool,
+    pub connections: usize,
+    pub segment_strategy: SegmentStrategy,
+    pub buffer_size: Option<usize>,
+    pub max_open_files: Option<usize>,
+    pub force: bool,
+    pub continue_partial: bool,
+    pub if_changed: bool,
+    pub newer_only: bool,
+    pub if_modified_since: Option<SystemTime>,
+    pub preallocate: bool,
+    pub timestamping: bool,
+    pub decompress: bool,
+    pub decompress_gzip: bool,
+    pub max_size: Option<u64>,
+    pub fail_on_empty: bool,
+    pub expect_content_type: Option<String>,
+    pub limit_rate: Option<u64>,
+    pub limit_rate_per_file: Option<u64>,
+    pub timeout: Option<Duration>,
+    pub idle_timeout: Option<Duration>,
+    pub per_file_timeout: Option<Duration>,
+    pub max_total_time: Option<Duration>,
+    pub max_total_bytes: Option<u64>,
+    pub wait: Option<Duration>,
+    pub random_wait: bool,
+    pub max_redirects: Option<usize>,
+    pub min_speed: Option<u64>,
+    pub min_speed_time: Duration,
+    pub headers: Vec<(HeaderName, HeaderValue)>,
+    pub user_agent: Option<String>,
+    pub referer: Option<Referer>,
+    pub origin: Option<String>,
+    pub user: Option<(String, Option<String>)>,
+    /// Use `user` for Digest rather than Basic authentication.
+    pub digest: bool,
+    pub bearer: Option<String>,
+    pub cookies: Vec<(String, String)>,
+    pub cookie_jar: Option<String>,
+    pub session: Option<String>,
+    pub proxy: Option<String>,
+    pub cacerts: Vec<String>,
+    pub cert: Option<String>,
+    pub key: Option<String>,
+    pub insecure: bool,
+    pub ip_family: Option<IpFamily>,
+    pub ip_fallback: bool,
+    pub pool_max_idle_per_host: Option<usize>,
+    pub pool_idle_timeout: Option<Duration>,
+    pub tcp_keepalive: Option<Duration>,
+    pub sha256: Option<String>,
+    pub verify_sidecar: bool,
+    pub require_checksum: bool,
+    pub checksums: Vec<(String, String)>,
+    /// Expected sizes from metalinks.
+    pub sizes: Vec<(String, u64)>,
+    /// Priorities for URLs given after `--priority` or with one in an input file.
+    pub priorities: Vec<(String, i32)>,
+    /// `(url, mirror)` pairs, in the order given.
+    pub mirrors: Vec<(String, String)>,
+    pub chmod: Option<u32>,
+    pub exec: Option<String>,
+    pub report: Option<String>,
+    pub dry_run: bool,
+    pub spider: bool,
+    pub head_first: bool,
+    pub fail_fast: bool,
+    pub notify: bool,
+    pub metrics_addr: Option<String>,
+    /// How many times -v was given.
+    pub verbose: u8,
+    pub timing: bool,
+    pub tui: bool,
+    /// `None` to redraw only on start and finish events.
+    pub progress_interval: Option<Duration>,
+    /// `None` to go by whether stdout is a terminal.
+    pub progress_mode: Option<ProgressMode>,
+    pub quiet: bool,
+    pub json: bool,
+    pub units: Units,
+    /// Expand `[1-10]` and `{a,b}` patterns in command-line URLs.
+    pub glob: bool,
+    pub urls: Vec<String>,
+}
+
+/// Parses the command line, exiting with a message on invalid input.
+pub fn parse_args(args: impl Iterator<Item = String>) -> Options {
+    let args: Vec<String> = args.collect();
+    let program = args.first().cloned().unwrap_or_else(|| "rs-downloader".to_string());
+    let mut args = args.into_iter().skip(1);
+
+    let mut options = Options {
+        max_concurrent: 4,
+        max_per_host: None,
+        retries: 0,
+        retry_statuses: None,
+        retry_failed: false,
+        output_dir: None,
+        output_name: None,
+        file_names: vec![],
+        preserve_path: false,
+        output_template: None,
+        to_stdout: false,
+        connections: 1,
+        segment_strategy: SegmentStrategy::Seek,
+        buffer_size: None,
+        max_open_files: None,
+        force: false,
+        continue_partial: false,
+        if_changed: false,
+        newer_only: false,
+        if_modified_since: None,
+        preallocate: false,
+        timestamping: true,
+        decompress: false,
+        decompress_gzip: false,
+        max_size: None,
+        fail_on_empty: false,
+        expect_content_type: None,
+        limit_rate: None,
+        limit_rate_per_file: None,
+        timeout: None,
+        idle_timeout: None,
+        per_file_timeout: None,
+        max_total_time: None,
+        max_total_bytes: None,
+        wait: None,
+        random_wait: false,
+        max_redirects: None,
+        min_speed: None,
+        min_speed_time: Duration::from_secs(30),
+        headers: vec![],
+        user_agent: None,
+        referer: None,
+        origin: None,
+        user: None,
+        digest: false,
+        bearer: None,
+        cookies: vec![],
+        cookie_jar: None,
+        session: None,
+        proxy: None,
+        cacerts: vec![],
+        cert: None,
+        key: None,
+        insecure: false,
+        ip_family: None,
+        ip_fallback: false,
+        pool_max_idle_per_host: None,
+        pool_idle_timeout: None,
+        tcp_keepalive: None,
+        sha256: None,
+        verify_sidecar: false,
+        require_checksum: false,
+        checksums: vec![],
+        sizes: vec![],
+        priorities: vec![],
+        mirrors: vec![],
+        chmod: None,
+        exec: None,
+        report: None,
+        dry_run: false,
+        spider: false,
+        head_first: false,
+        fail_fast: false,
+        notify: false,
+        metrics_addr: None,
+        verbose: 0,
+        timing: false,
+        tui: false,
+        progress_interval: Some(DEFAULT_PROGRESS_INTERVAL),
+        progress_mode: None,
+        quiet: false,
+        json: false,
+        units: Units::Si,
+        glob: true,
+        urls: vec![],
+    };
+    let mut input_files = vec![];
+    let mut priority = None;
+    // Expanded once --no-glob has had its chance to appear
+    let mut patterns = vec![];
+
+    // Applied first so that any flag, wherever it appears, overrides the file
+    let config_path = config_path(args.clone());
+    let mut config_headers = vec![];
+    if let Some(path) = &config_path {
+        let config = Config::load(path).unwrap_or_else(|e| fail(&e));
+        config_headers = config.apply(path, &mut options).unwrap_or_else(|e| fail(&e));
+    }
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-h" | "--help" => {
+                println!("{}", USAGE.replace("{program}", &program));
+                std::process::exit(0);
+            }
+            "--config" => {
+                expect_value(&mut args, &arg, "a path");
+            }
+            "--max-concurrent" => {
+                options.max_concurrent = parse_value(&mut args, &arg, "a positive integer");
+                if options.max_concurrent == 0 {
+                    usage_error(&arg, "a positive integer");
+                }
+            }
+            "--max-per-host" => {
+                let n = parse_value(&mut args, &arg, "a positive integer");
+                if n == 0 {
+                    usage_error(&arg, "a positive integer");
+                }
+                options.max_per_host = Some(n);
+            }
+            "--retries" => options.retries = parse_value(&mut args, &arg, "a non-negative integer"),
+            "--retry-on-status" => {
+                let value = expect_value(&mut args, &arg, "a list like 429,503 or 500-599");
+                options.retry_statuses =
+                    Some(parse_statuses(&value).unwrap_or_else(|| usage_error(&arg, "a list like 429,503 or 500-599")));
+            }
+            "--retry-failed" => options.retry_failed = true,
+            "--output-dir" => options.output_dir = Some(expect_value(&mut args, &arg, "a path")),
+            "--preserve-path" => options.preserve_path = true,
+            "--output-template" => {
+                let pattern = expect_value(&mut args, &arg, "a pattern like '{index}-{filename}'");
+                options.output_template = Some(pattern.parse().unwrap_or_else(|e| fail(&format!("{}: {}", arg, e))));
+            }
+            "-o" => match expect_value(&mut args, &arg, "a file name") {
+                name if name == "-" => options.to_stdout = true,
+                name => options.output_name = Some(name),
+            },
+            "-O" => {
+                if expect_value(&mut args, &arg, "-") != "-" {
+                    fail("-O expects - (for stdout); use -o NAME to choose a file name");
+                }
+                options.to_stdout = true;
+            }
+            "--input-file" => input_files.push((expect_value(&mut args, &arg, "a path or -"), priority)),
+            "--priority" => priority = Some(parse_value(&mut args, &arg, "an integer")),
+            "--no-glob" => options.glob = false,
+            "--segment-strategy" => {
+                let value = expect_value(&mut args, &arg, "seek or temp-files");
+                options.segment_strategy = parse_segment_strategy(&value).unwrap_or_else(|| usage_error(&arg, "seek or temp-files"));
+            }
+            "--connections" => {
+                options.connections = parse_value(&mut args, &arg, "a positive integer");
+                if options.connections == 0 {
+                    usage_error(&arg, "a positive integer");
+                }
+            }
+            "--buffer-size" => {
+                let value = expect_value(&mut args, &arg, "a size like 64k or 1m");
+                let size = parse_rate(&value).filter(|&size| size > 0);
+                options.buffer_size = Some(size.unwrap_or_else(|| usage_error(&arg, "a size like 64k or 1m")) as usize);
+            }
+            "--max-open-files" => {
+                let n = parse_value(&mut args, &arg, "a positive integer");
+                if n == 0 {
+                    usage_error(&arg, "a positive integer");
+                }
+                options.max_open_files = Some(n);
+            }
+            "--force" => options.force = true,
+            "-c" | "--continue" => options.continue_partial = true,
+            "--if-changed" => options.if_changed = true,
+            "-N" | "--newer-only" => options.newer_only = true,
+            "--if-modified-since" => {
+                let expected = "an RFC 2822 or ISO 8601 date";
+                let value = expect_value(&mut args, &arg, expected);
+                options.if_modified_since = Some(parse_date(&value).unwrap_or_else(|| usage_error(&arg, expected)));
+            }
+            "--preallocate" => options.preallocate = true,
+            "--no-timestamping" => options.timestamping = false,
+            "--decompress" => options.decompress = true,
+            "--no-decompress" => options.decompress = false,
+            "--decompress-gzip" => options.decompress_gzip = true,
+            "--expect-content-type" => {
+                options.expect_content_type = Some(expect_value(&mut args, &arg, "a media type like application/zip"));
+            }
+            "--fail-on-empty" => options.fail_on_empty = true,
+            "--max-size" => {
+                let value = expect_value(&mut args, &arg, "a size like 500m or 2g");
+                options.max_size = Some(parse_rate(&value).unwrap_or_else(|| usage_error(&arg, "a size like 500m or 2g")));
+            }
+            "--limit-rate" => {
+                let value = expect_value(&mut args, &arg, "a rate like 500k or 2m");
+                options.limit_rate = Some(parse_rate(&value).unwrap_or_else(|| usage_error(&arg, "a rate like 500k or 2m")));
+            }
+            "--limit-rate-per-file" => {
+                let value = expect_value(&mut args, &arg, "a rate like 500k or 2m");
+                options.limit_rate_per_file =
+                    Some(parse_rate(&value).unwrap_or_else(|| usage_error(&arg, "a rate like 500k or 2m")));
+            }
+            "--timeout" => options.timeout = Some(parse_duration(&mut args, &arg)),
+            "--idle-timeout" => options.idle_timeout = Some(parse_duration(&mut args, &arg)),
+            "--per-file-timeout" => options.per_file_timeout = Some(parse_duration(&mut args, &arg)),
+            "--max-total-time" => options.max_total_time = Some(parse_duration(&mut args, &arg)),
+            "--max-total-bytes" => {
+                let value = expect_value(&mut args, &arg, "a size like 500m or 2g");
+                options.max_total_bytes = Some(parse_rate(&value).unwrap_or_else(|| usage_error(&arg, "a size like 500m or 2g")));
+            }
+            "--wait" => options.wait = Some(parse_duration(&mut args, &arg)),
+            "--random-wait" => options.random_wait = true,
+            "--max-redirects" => options.max_redirects = Some(parse_value(&mut args, &arg, "a non-negative integer")),
+            "--no-redirect" => options.max_redirects = Some(0),
+            "--min-speed" => {
+                let value = expect_value(&mut args, &arg, "a rate like 500k or 2m");
+                options.min_speed = Some(parse_rate(&value).unwrap_or_else(|| usage_error(&arg, "a rate like 500k or 2m")));
+            }
+            "--min-speed-time" => options.min_speed_time = parse_duration(&mut args, &arg),
+            "--header" => {
+                let value = expect_value(&mut args, &arg, "'Name: Value'");
+                match parse_header(&value) {
+                    Ok(header) => options.headers.push(header),
+                    Err(e) => fail(&format!("--header {:?}: {}", value, e)),
+                }
+            }
+            "--user-agent" => options.user_agent = Some(expect_value(&mut args, &arg, "a string")),
+            "--referer" => {
+                let value = expect_value(&mut args, &arg, "a URL or auto");
+                options.referer = Some(parse_referer(&value).unwrap_or_else(|| usage_error(&arg, "a URL or auto")));
+            }
+            "--origin" => {
+                let value = expect_value(&mut args, &arg, "an http or https URL");
+                options.origin = Some(parse_origin(&value).unwrap_or_else(|| usage_error(&arg, "an http or https URL")));
+            }
+            "--user" => {
+                let value = expect_value(&mut args, &arg, "USER[:PASS]");
+                options.user = Some(match value.split_once(':') {
+                    Some((user, password)) => (user.to_string(), Some(password.to_string())),
+                    None => (value, None),
+                });
+            }
+            "--digest" => options.digest = true,
+            "--bearer" => options.bearer = Some(expect_value(&mut args, &arg, "a token")),
+            "--cookie" => {
+                let value = expect_value(&mut args, &arg, "'NAME=VALUE'");
+                match parse_cookies(&value) {
+                    Ok(cookies) => options.cookies.extend(cookies),
+                    Err(e) => fail(&format!("--cookie {:?}: {}", value, e)),
+                }
+            }
+            "--cookie-jar" => options.cookie_jar = Some(expect_value(&mut args, &arg, "a path")),
+            "--session" => options.session = Some(expect_value(&mut args, &arg, "a path")),
+            "--cacert" => options.cacerts.push(expect_value(&mut args, &arg, "a path")),
+            "--cert" => options.cert = Some(expect_value(&mut args, &arg, "a path")),
+            "--key" => options.key = Some(expect_value(&mut args, &arg, "a path")),
+            "--insecure" => options.insecure = true,
+            "-4" | "--ipv4" | "-6" | "--ipv6" => {
+                let family = if arg.contains('4') { IpFamily::V4 } else { IpFamily::V6 };
+                if options.ip_family.is_some_and(|f| f != family) {
+                    fail("--ipv4 and --ipv6 can't be used together");
+                }
+                options.ip_family = Some(family);
+            }
+            "--ip-fallback" => options.ip_fallback = true,
+            "--pool-max-idle-per-host" => options.pool_max_idle_per_host = Some(parse_value(&mut args, &arg, "a number of connections")),
+            "--pool-idle-timeout" => options.pool_idle_timeout = Some(parse_duration(&mut args, &arg)),
+            "--tcp-keepalive" => options.tcp_keepalive = Some(parse_duration(&mut args, &arg)),
+            "--proxy" => options.proxy = Some(expect_value(&mut args, &arg, "a proxy URL")),
+            "--verify-sidecar" => options.verify_sidecar = true,
+            "--require-checksum" => options.require_checksum = true,
+            "--sha256" => {
+                // Either a bare hash for every URL, or `<url>=<hash>` for one of them
+                let value = expect_value(&mut args, &arg, "a hex digest or <url>=<digest>");
+                match value.rsplit_once('=') {
+                    Some((url, hex)) => options.checksums.push((url.to_string(), hex.to_string())),
+                    None => options.sha256 = Some(value),
+                }
+            }
+            "--chmod" => {
+                let mode = expect_value(&mut args, &arg, "an octal mode like 755");
+                options.chmod = Some(parse_mode(&mode).unwrap_or_else(|| usage_error(&arg, "an octal mode like 755")));
+            }
+            "--exec" => {
+                let command = expect_value(&mut args, &arg, "a command");
+                if command.split_whitespace().next().is_none() {
+                    usage_error(&arg, "a command");
+                }
+                options.exec = Some(command);
+            }
+            "--dry-run" => options.dry_run = true,
+            "--spider" => options.spider = true,
+            "--head-first" => options.head_first = true,
+            "--fail-fast" => options.fail_fast = true,
+            "--mirror" => {
+                let mirror = expect_value(&mut args, &arg, "a URL");
+                match patterns.last_mut() {
+                    Some(Pattern { mirrors, .. }) => mirrors.push(mirror),
+                    None => fail("--mirror must follow the URL it is a mirror of"),
+                }
+            }
+            "-v" | "--verbose" => options.verbose += 1,
+            "-vv" => options.verbose += 2,
+            "--timing" => options.timing = true,
+            "--tui" => options.tui = true,
+            "--notify" => options.notify = true,
+            "--metrics-addr" => options.metrics_addr = Some(expect_value(&mut args, &arg, "a host:port")),
+            "--report" => options.report = Some(expect_value(&mut args, &arg, "a path")),
+            "--progress-interval" => {
+                let ms: u64 = parse_value(&mut args, &arg, "a number of milliseconds");
+                options.progress_interval = match Duration::from_millis(ms) {
+                    interval if interval.is_zero() => None,
+                    interval if interval < MIN_PROGRESS_INTERVAL => {
+                        usage_error(&arg, "0, or at least 50 milliseconds")
+                    }
+                    interval => Some(interval),
+                };
+            }
+            "--progress" => {
+                options.progress_mode = match expect_value(&mut args, &arg, "auto, interactive or plain").as_str() {
+                    "auto" => None,
+                    "interactive" => Some(ProgressMode::Interactive),
+                    "plain" => Some(ProgressMode::Plain),
+                    _ => usage_error(&arg, "auto, interactive or plain"),
+                };
+            }
+            "-q" | "--quiet" => options.quiet = true,
+            "--json" => options.json = true,
+            "--units" => {
+                options.units = match expect_value(&mut args, &arg, "si or binary").as_str() {
+                    "si" => Units::Si,
+                    "binary" => Units::Binary,
+                    _ => usage_error(&arg, "si or binary"),
+                };
+            }
+            // Everything after `--` is a URL, even if it starts with `-`
+            "--" => patterns.extend(args.by_ref().map(|url| Pattern { url, priority, mirrors: vec![] })),
+            _ if arg.starts_with('-') && arg != "-" => fail(&format!("Unknown option {} (see --help)", arg)),
+            _ => patterns.push(Pattern { url: arg, priority, mirrors: vec![] }),
+        }
+    }
+
+    let expand = |pattern: String| {
+        if options.glob {
+            glob::expand(&pattern).unwrap_or_else(|e| fail(&format!("{} (--no-glob takes URLs literally)", e)))
+        } else {
+            vec![pattern]
+        }
+    };
+    for pattern in patterns {
+        let urls = expand(pattern.url);
+        // A mirror with the same pattern pairs up URL by URL; a plain one serves them all
+        let mirrors: Vec<Vec<String>> = pattern.mirrors.into_iter().map(|mirror| {
+            let expanded = expand(mirror.clone());
+            match expanded.len() {
+                1 => vec![expanded[0].clone(); urls.len()],
+                n if n == urls.len() => expanded,
+                n => fail(&format!("--mirror {} expands to {} URLs, but the URL it follows to {}", mirror, n, urls.len())),
+            }
+        }).collect();
+        for (index, url) in urls.into_iter().enumerate() {
+            if let Some(priority) = pattern.priority {
+                options.priorities.push((url.clone(), priority));
+            }
+            for mirror in &mirrors {
+                options.mirrors.push((url.clone(), mirror[index].clone()));
+            }
+            options.urls.push(url);
+        }
+    }
+
+    config_headers.retain(|(name, _)| !options.headers.iter().any(|(n, _)| n == name));
+    options.headers.splice(0..0, config_headers);
+
+    // Names given in input files, lowercased since some filesystems ignore case
+    let mut names: HashMap<String, (String, String)> = HashMap::new();
+    for (path, default_priority) in &input_files {
+        if is_metalink(path) {
+            for file in metalink::read(path).unwrap_or_else(|e| fail(&e)) {
+                let here = format!("{} ({})", path, file.name);
+                check_name(&file.name).unwrap_or_else(|e| fail(&format!("{}: can't save as {:?}: {}", path, file.name, e)));
+                let url = file.urls[0].clone();
+                if let Some(priority) = *default_priority {
+                    options.priorities.push((url.clone(), priority));
+                }
+                add_name(&mut options, &mut names, &url, file.name.clone(), here);
+                for mirror in &file.urls[1..] {
+                    options.mirrors.push((url.clone(), mirror.clone()));
+                }
+                if let Some(size) = file.size {
+                    options.sizes.push((url.clone(), size));
+                }
+                match file.sha256 {
+                    Some(hex) => options.checksums.push((url.clone(), hex)),
+                    None if file.other_hashes.is_empty() => {
+                        eprintln!("Warning: {} gives no hash for {}, so it won't be verified", path, file.name);
+                    }
+                    None => eprintln!(
+                        "Warning: {} only gives {} for {}, which can't be checked, so it won't be verified",
+                        path,
+                        file.other_hashes.join(", "),
+                        file.name
+                    ),
+                }
+                options.urls.push(url);
+            }
+            continue;
+        }
+        let entries = input::read_urls(path).unwrap_or_else(|e| fail(&e));
+        for entry in entries {
+            if let Some(priority) = entry.priority.or(*default_priority) {
+                options.priorities.push((entry.url.clone(), priority));
+            }
+            if let Some(name) = entry.name {
+                add_name(&mut options, &mut names, &entry.url, name, format!("{}:{}", path, entry.line));
+            }
+            options.urls.push(entry.url);
+        }
+    }
+
+    if let (true, Some(path)) = (options.urls.is_empty(), &options.session) {
+        options.urls = session_urls(Path::new(path)).unwrap_or_else(|e| fail(&e));
+    }
+    if options.urls.is_empty() {
+        eprintln!("{}", USAGE.replace("{program}", &program));
+        std::process::exit(1);
+    }
+
+    if options.quiet && options.json {
+        fail("--quiet and --json can't be used together");
+    }
+
+    if options.ip_fallback && options.ip_family.is_none() {
+        fail("--ip-fallback needs --ipv4 or --ipv6");
+    }
+
+    if options.cert.is_some() != options.key.is_some() {
+        fail("--cert and --key must be given together");
+    }
+
+    if options.tui && (options.quiet || options.json) {
+        fail("--tui can't be used with --quiet or --json");
+    }
+
+    if options.user.is_some() && options.bearer.is_some() {
+        fail("--user and --bearer can't be used together");
+    }
+    if options.digest && options.user.is_none() {
+        fail("--digest needs --user");
+    }
+    if options.spider && (options.dry_run || options.to_stdout) {
+        fail("--spider can't be used with --dry-run or -O -");
+    }
+    if options.random_wait && options.wait.is_none() {
+        fail("--random-wait needs --wait");
+    }
+    if options.newer_only && options.force {
+        fail("--newer-only and --force can't be used together");
+    }
+    if options.if_modified_since.is_some() && (options.newer_only || options.if_changed) {
+        fail("--if-modified-since can't be used with --newer-only or --if-changed");
+    }
+    if options.fail_fast && !options.head_first {
+        fail("--fail-fast needs --head-first");
+    }
+
+    let explicit_auth = options.headers.iter().any(|(name, _)| name == AUTHORIZATION);
+    if explicit_auth && (options.user.is_some() || options.bearer.is_some()) {
+        fail("--header 'Authorization: ...' conflicts with --user/--bearer");
+    }
+
+    let explicit_agent = options.headers.iter().any(|(name, _)| name == USER_AGENT);
+    if explicit_agent && options.user_agent.is_some() {
+        // Both would otherwise be sent, with the header silently winning
+        fail("--header 'User-Agent: ...' conflicts with --user-agent; pass the user agent once");
+    }
+
+    let dedicated = [(REFERER, "Referer", "--referer", options.referer.is_some()), (ORIGIN, "Origin", "--origin", options.origin.is_some())];
+    for (header, name, flag, set) in dedicated {
+        if set && options.headers.iter().any(|(explicit, _)| *explicit == header) {
+            fail(&format!("--header '{}: ...' conflicts with {}; pass it once", name, flag));
+        }
+    }
+
+    if options.output_name.is_some() && options.urls.len() > 1 {
+        fail("-o can only be used with a single URL");
+    }
+
+    if options.to_stdout {
+        if options.urls.len() > 1 {
+            fail("Only one URL can be written to stdout");
+        }
+        if options.output_name.is_some() || options.json || options.tui || options.exec.is_some() {
+            fail("-O - can't be combined with -o NAME, --json, --tui or --exec");
+        }
+    }
+
+    options
+}
+
+/// A URL from the command line, before `[1-10]` and `{a,b}` are expanded.
+struct Pattern {
+    url: String,
+    /// The `--priority` in effect where it was given.
+    priority: Option<i32>,
+    /// The `--mirror`s given after it.
+    mirrors: Vec<String>,
+}
+
+/// Saves `url` as `name`, given at `here`, failing if either already has
+/// another name or URL in `names`.
+fn add_name(options: &mut Options, names: &mut HashMap<String, (String, String)>, url: &str, name: String, here: String) {
+    if let Some((_, other)) = options.file_names.iter().find(|(u, other)| u == url && *other != name) {
+        fail(&format!("{}: {} is already to be saved as {}", here, url, other));
+    }
+    match names.get(&name.to_lowercase()) {
+        Some((u, _)) if u == url => {}
+        Some((_, there)) => fail(&format!("{}: {} is already the name given on {}", here, name, there)),
+        None => {
+            names.insert(name.to_lowercase(), (url.to_string(), here));
+        }
+    }
+    options.file_names.push((url.to_string(), name));
+}
+
+/// Whether `path` names a metalink rather than a list of URLs.
+fn is_metalink(path: &str) -> bool {
+    let path = path.to_ascii_lowercase();
+    path.ends_with(".meta4") || path.ends_with(".metalink")
+}
+
+/// The last `--config` value, if any.
+fn config_path(mut args: impl Iterator<Item = String>) -> Option<String> {
+    let mut path = None;
+    while let Some(arg) = args.next() {
+        if arg == "--" {
+            break;
+        }
+        if arg == "--config" {
+            path = Some(expect_value(&mut args, &arg, "a path"));
+        }
+    }
+    path
+}
+
+/// A comma-separated list of HTTP statuses and `FROM-TO` ranges.
+pub fn parse_statuses(value: &str) -> Option<Vec<u16>> {
+    let status = |s: &str| s.trim().parse::<u16>().ok().filter(|code| (100..=599).contains(code));
+    let mut statuses = vec![];
+    for part in value.split(',') {
+        match part.split_once('-') {
+            Some((from, to)) => {
+                let (from, to) = (status(from)?, status(to)?);
+                if from > to {
+                    return None;
+                }
+                statuses.extend(from..=to);
+            }
+            None => statuses.push(status(part)?),
+        }
+    }
+    Some(statuses)
+}
+
+fn parse_header(value: &str) -> Result<(HeaderName, HeaderValue), String> {
+    let (name, value) = value
+        .split_once(':')
+        .ok_or_else(|| "expected 'Name: Value'".to_string())?;
+    let name = HeaderName::from_str(name.trim()).map_err(|e| e.to_string())?;
+    let value = HeaderValue::from_str(value.trim()).map_err(|e| e.to_string())?;
+    Ok((name, value))
+}
+
+/// `seek` or `temp-files`, for `--segment-strategy`.
+pub fn parse_segment_strategy(value: &str) -> Option<SegmentStrategy> {
+    match value {
+        "seek" => Some(SegmentStrategy::Seek),
+        "temp-files" => Some(SegmentStrategy::TempFiles),
+        _ => None,
+    }
+}
+
+/// `auto`, or an absolute URL to send as the `Referer`.
+pub fn parse_referer(value: &str) -> Option<Referer> {
+    if value == "auto" {
+        return Some(Referer::Auto);
+    }
+    Url::parse(value).ok().map(|url| Referer::Url(url.to_string()))
+}
+
+/// An http or https URL, whose origin is sent as the `Origin`.
+pub fn parse_origin(value: &str) -> Option<String> {
+    Url::parse(value).ok().filter(|url| matches!(url.scheme(), "http" | "https")).map(|url| url.to_string())
+}
+
+/// Splits `NAME=VALUE`, or several separated by `;` as in a `Cookie` header.
+fn parse_cookies(value: &str) -> Result<Vec<(String, String)>, String> {
+    let mut cookies = vec![];
+    for pair in value.split(';').map(str::trim).filter(|pair| !pair.is_empty()) {
+        let (name, value) = pair.split_once('=').ok_or_else(|| "expected 'NAME=VALUE'".to_string())?;
+        let (name, value) = (name.trim(), value.trim());
+        if name.is_empty() || name.contains(|c: char| c.is_whitespace() || c.is_control() || matches!(c, ',' | '=')) {
+            return Err(format!("{:?} isn't a valid cookie name", name));
+        }
+        if value.contains(|c: char| c.is_control() || matches!(c, ',' | '"' | '\\')) || !value.is_ascii() {
+            return Err(format!("{:?} isn't a valid cookie value", value));
+        }
+        cookies.push((name.to_string(), value.to_string()));
+    }
+    if cookies.is_empty() {
+        return Err("expected 'NAME=VALUE'".to_string());
+    }
+    Ok(cookies)
+}
+
+fn fail(message: &str) -> ! {
+    eprintln!("{}", message);
+    std::process::exit(1);
+}
+
+fn usage_error(flag: &str, expected: &str) -> ! {
+    fail(&format!("{} expects {}", flag, expected))
+}
+
+fn expect_value(args: &mut impl Iterator<Item = String>, flag: &str, expected: &str) -> String {
+    args.next().unwrap_or_else(|| usage_error(flag, expected))
+}
+
+/// An octal permission mode like `755` or `0644`.
+pub fn parse_mode(value: &str) -> Option<u32> {
+    u32::from_str_radix(value, 8).ok().filter(|&mode| mode <= 0o7777)
+}
+
+fn parse_value<T: FromStr>(args: &mut impl Iterator<Item = String>, flag: &str, expected: &str) -> T {
+    expect_value(args, flag, expected)
+        .parse()
+        .unwrap_or_else(|_| usage_error(flag, expected))
+}
+
+fn parse_duration(args: &mut impl Iterator<Item = String>, flag: &str) -> Duration {
+    let secs: f64 = parse_value(args, flag, "a number of seconds");
+    Duration::try_from_secs_f64(secs)
+        .ok()
+        .filter(|d| !d.is_zero())
+        .unwrap_or_else(|| usage_error(flag, "a positive number of seconds"))
+}
@@ -0,0 +1,298 @@
+use crate::cli::{parse_mode, parse_origin, parse_referer, parse_segment_strategy, parse_statuses, Options};
+use reqwest::header::{HeaderName, HeaderValue};
+use rs_downloader::{parse_date, parse_rate, ProgressMode, Units, MIN_PROGRESS_INTERVAL};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::str::FromStr;
+use std::time::Duration;
+
+/// Defaults read from a `--config` TOML file. Keys are named after the
+/// command-line flags, and any flag given on the command line wins.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Config {
+    max_concurrent: Option<usize>,
+    max_per_host: Option<usize>,
+    retries: Option<u32>,
+    /// Same syntax as `--retry-on-status`.
+    retry_on_status: Option<String>,
+    retry_failed: Option<bool>,
+    output_dir: Option<String>,
+    preserve_path: Option<bool>,
+    output_template: Option<String>,
+    connections: Option<usize>,
+    segment_strategy: Option<String>,
+    buffer_size: Option<Size>,
+    max_open_files: Option<usize>,
+    preallocate: Option<bool>,
+    timestamping: Option<bool>,
+    glob: Option<bool>,
+    newer_only: Option<bool>,
+    if_modified_since: Option<String>,
+    decompress: Option<bool>,
+    decompress_gzip: Option<bool>,
+    max_size: Option<Size>,
+    fail_on_empty: Option<bool>,
+    expect_content_type: Option<String>,
+    limit_rate: Option<Size>,
+    limit_rate_per_file: Option<Size>,
+    timeout: Option<f64>,
+    idle_timeout: Option<f64>,
+    per_file_timeout: Option<f64>,
+    pool_max_idle_per_host: Option<usize>,
+    pool_idle_timeout: Option<f64>,
+    tcp_keepalive: Option<f64>,
+    wait: Option<f64>,
+    random_wait: Option<bool>,
+    max_total_time: Option<f64>,
+    max_total_bytes: Option<Size>,
+    max_redirects: Option<usize>,
+    min_speed: Option<Size>,
+    min_speed_time: Option<f64>,
+    user_agent: Option<String>,
+    referer: Option<String>,
+    origin: Option<String>,
+    cookie_jar: Option<String>,
+    proxy: Option<String>,
+    cacert: Option<Vec<String>>,
+    cert: Option<String>,
+    key: Option<String>,
+    insecure: Option<bool>,
+    verify_sidecar: Option<bool>,
+    require_checksum: Option<bool>,
+    /// Octal, as a string like `"755"`.
+    chmod: Option<String>,
+    units: Option<String>,
+    notify: Option<bool>,
+    metrics_addr: Option<String>,
+    /// Milliseconds, with 0 meaning only on start and finish events.
+    progress_interval: Option<u64>,
+    progress: Option<String>,
+    /// Sent with every request unless the command line sets the same header.
+    headers: Option<BTreeMap<String, String>>,
+}
+
+/// A byte count or rate, either as a number or a string like `500k`.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Size {
+    Bytes(u64),
+    Text(String),
+}
+
+impl Config {
+    pub fn load(path: &str) -> Result<Config, String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+        toml::from_str(&text).map_err(|e| format!("{}: {}", path, e.to_string().trim_end()))
+    }
+
+    /// Overwrites `options` with every value the file sets, returning the
+    /// config's headers separately so the command line can override them.
+    pub fn apply(self, path: &str, options: &mut Options) -> Result<Vec<(HeaderName, HeaderValue)>, String> {
+        let invalid = |key: &str, expected: &str| format!("{}: {} must be {}", path, key, expected);
+
+        if let Some(n) = self.max_concurrent {
+            options.max_concurrent = Some(n).filter(|&n| n > 0).ok_or_else(|| invalid("max-concurrent", "a positive integer"))?;
+        }
+        if let Some(n) = self.max_per_host {
+            options.max_per_host = Some(Some(n).filter(|&n| n > 0).ok_or_else(|| invalid("max-per-host", "a positive integer"))?);
+        }
+        if let Some(n) = self.retries {
+            options.retries = n;
+        }
+        if let Some(statuses) = self.retry_on_status {
+            let statuses = parse_statuses(&statuses).ok_or_else(|| invalid("retry-on-status", "a list like \"429,503\" or \"500-599\""))?;
+            options.retry_statuses = Some(statuses);
+        }
+        if let Some(retry_failed) = self.retry_failed {
+            options.retry_failed = retry_failed;
+        }
+        if let Some(dir) = self.output_dir {
+            options.output_dir = Some(dir);
+        }
+        if let Some(preserve_path) = self.preserve_path {
+            options.preserve_path = preserve_path;
+        }
+        if let Some(pattern) = self.output_template {
+            options.output_template = Some(pattern.parse().map_err(|e| format!("{}: output-template: {}", path, e))?);
+        }
+        if let Some(n) = self.connections {
+            options.connections = Some(n).filter(|&n| n > 0).ok_or_else(|| invalid("connections", "a positive integer"))?;
+        }
+        if let Some(strategy) = self.segment_strategy {
+            options.segment_strategy = parse_segment_strategy(&strategy).ok_or_else(|| invalid("segment-strategy", "\"seek\" or \"temp-files\""))?;
+        }
+        if let Some(size) = self.buffer_size {
+            let size = size.bytes().filter(|&size| size > 0);
+            options.buffer_size = Some(size.ok_or_else(|| invalid("buffer-size", "a size like 64k or 1m"))? as usize);
+        }
+        if let Some(n) = self.max_open_files {
+            options.max_open_files = Some(Some(n).filter(|&n| n > 0).ok_or_else(|| invalid("max-open-files", "a positive integer"))?);
+        }
+        if let Some(preallocate) = self.preallocate {
+            options.preallocate = preallocate;
+        }
+        if let Some(timestamping) = self.timestamping {
+            options.timestamping = timestamping;
+        }
+        if let Some(glob) = self.glob {
+            options.glob = glob;
+        }
+        if let Some(newer_only) = self.newer_only {
+            options.newer_only = newer_only;
+        }
+        if let Some(date) = self.if_modified_since {
+            options.if_modified_since = Some(parse_date(&date).ok_or_else(|| invalid("if-modified-since", "an RFC 2822 or ISO 8601 date"))?);
+        }
+        if let Some(decompress) = self.decompress {
+            options.decompress = decompress;
+        }
+        if let Some(decompress_gzip) = self.decompress_gzip {
+            options.decompress_gzip = decompress_gzip;
+        }
+        if let Some(expected) = self.expect_content_type {
+            options.expect_content_type = Some(expected);
+        }
+        if let Some(size) = self.max_size {
+            options.max_size = Some(size.bytes().ok_or_else(|| invalid("max-size", "a size like 500m or 2g"))?);
+        }
+        if let Some(fail_on_empty) = self.fail_on_empty {
+            options.fail_on_empty = fail_on_empty;
+        }
+        if let Some(rate) = self.limit_rate_per_file {
+            options.limit_rate_per_file = Some(rate.bytes().ok_or_else(|| invalid("limit-rate-per-file", "a rate like 500k or 2m"))?);
+        }
+        if let Some(rate) = self.limit_rate {
+            options.limit_rate = Some(rate.bytes().ok_or_else(|| invalid("limit-rate", "a rate like 500k or 2m"))?);
+        }
+        if let Some(secs) = self.timeout {
+            options.timeout = Some(duration(secs).ok_or_else(|| invalid("timeout", "a positive number of seconds"))?);
+        }
+        if let Some(secs) = self.idle_timeout {
+            options.idle_timeout = Some(duration(secs).ok_or_else(|| invalid("idle-timeout", "a positive number of seconds"))?);
+        }
+        if let Some(secs) = self.wait {
+            options.wait = Some(duration(secs).ok_or_else(|| invalid("wait", "a positive number of seconds"))?);
+        }
+        if let Some(random_wait) = self.random_wait {
+            options.random_wait = random_wait;
+        }
+        if let Some(secs) = self.per_file_timeout {
+            options.per_file_timeout = Some(duration(secs).ok_or_else(|| invalid("per-file-timeout", "a positive number of seconds"))?);
+        }
+        if let Some(n) = self.pool_max_idle_per_host {
+            options.pool_max_idle_per_host = Some(n);
+        }
+        if let Some(secs) = self.pool_idle_timeout {
+            options.pool_idle_timeout = Some(duration(secs).ok_or_else(|| invalid("pool-idle-timeout", "a positive number of seconds"))?);
+        }
+        if let Some(secs) = self.tcp_keepalive {
+            options.tcp_keepalive = Some(duration(secs).ok_or_else(|| invalid("tcp-keepalive", "a positive number of seconds"))?);
+        }
+        if let Some(n) = self.max_redirects {
+            options.max_redirects = Some(n);
+        }
+        if let Some(secs) = self.max_total_time {
+            options.max_total_time = Some(duration(secs).ok_or_else(|| invalid("max-total-time", "a positive number of seconds"))?);
+        }
+        if let Some(size) = self.max_total_bytes {
+            options.max_total_bytes = Some(size.bytes().ok_or_else(|| invalid("max-total-bytes", "a size like 500m or 2g"))?);
+        }
+        if let Some(rate) = self.min_speed {
+            options.min_speed = Some(rate.bytes().ok_or_else(|| invalid("min-speed", "a rate like 500k or 2m"))?);
+        }
+        if let Some(secs) = self.min_speed_time {
+            options.min_speed_time = duration(secs).ok_or_else(|| invalid("min-speed-time", "a positive number of seconds"))?;
+        }
+        if let Some(user_agent) = self.user_agent {
+            options.user_agent = Some(user_agent);
+        }
+        if let Some(referer) = self.referer {
+            options.referer = Some(parse_referer(&referer).ok_or_else(|| invalid("referer", "a URL or auto"))?);
+        }
+        if let Some(origin) = self.origin {
+            options.origin = Some(parse_origin(&origin).ok_or_else(|| invalid("origin", "an http or https URL"))?);
+        }
+        if let Some(path) = self.cookie_jar {
+            options.cookie_jar = Some(path);
+        }
+        if let Some(proxy) = self.proxy {
+            options.proxy = Some(proxy);
+        }
+        if let Some(cacerts) = self.cacert {
+            options.cacerts = cacerts;
+        }
+        if let Some(cert) = self.cert {
+            options.cert = Some(cert);
+        }
+        if let Some(key) = self.key {
+            options.key = Some(key);
+        }
+        if let Some(insecure) = self.insecure {
+            options.insecure = insecure;
+        }
+        if let Some(units) = self.units {
+            options.units = match units.as_str() {
+                "si" => Units::Si,
+                "binary" => Units::Binary,
+                _ => return Err(invalid("units", "\"si\" or \"binary\"")),
+            };
+        }
+        if let Some(verify) = self.verify_sidecar {
+            options.verify_sidecar = verify;
+        }
+        if let Some(require) = self.require_checksum {
+            options.require_checksum = require;
+        }
+        if let Some(mode) = self.chmod {
+            options.chmod = Some(parse_mode(&mode).ok_or_else(|| invalid("chmod", "an octal mode like \"755\""))?);
+        }
+        if let Some(notify) = self.notify {
+            options.notify = notify;
+        }
+        if let Some(addr) = self.metrics_addr {
+            options.metrics_addr = Some(addr);
+        }
+
+        if let Some(ms) = self.progress_interval {
+            options.progress_interval = match Duration::from_millis(ms) {
+                interval if interval.is_zero() => None,
+                interval if interval < MIN_PROGRESS_INTERVAL => {
+                    return Err(invalid("progress-interval", "0, or at least 50 milliseconds"));
+                }
+                interval => Some(interval),
+            };
+        }
+        if let Some(mode) = self.progress {
+            options.progress_mode = match mode.as_str() {
+                "auto" => None,
+                "interactive" => Some(ProgressMode::Interactive),
+                "plain" => Some(ProgressMode::Plain),
+                _ => return Err(invalid("progress", "\"auto\", \"interactive\" or \"plain\"")),
+            };
+        }
+
+        let mut headers = vec![];
+        for (name, value) in self.headers.unwrap_or_default() {
+            let header = HeaderName::from_str(&name)
+                .map_err(|e| e.to_string())
+                .and_then(|name| Ok((name, HeaderValue::from_str(&value).map_err(|e| e.to_string())?)));
+            headers.push(header.map_err(|e| format!("{}: headers.{}: {}", path, name, e))?);
+        }
+        Ok(headers)
+    }
+}
+
+impl Size {
+    fn bytes(self) -> Option<u64> {
+        match self {
+            Size::Bytes(n) => Some(n),
+            Size::Text(text) => parse_rate(&text),
+        }
+    }
+}
+
+fn duration(secs: f64) -> Option<Duration> {
+    Duration::try_from_secs_f64(secs).ok().filter(|d| !d.is_zero())
+}
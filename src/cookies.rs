@@ -0,0 +1,255 @@
+use reqwest::header::{HeaderMap, HeaderValue, COOKIE, SET_COOKIE};
+use reqwest::{Request, Url};
+use std::fs;
+use std::io::{self, BufWriter, Write};
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::sync::Mutex as StdMutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Cookies set by responses and sent back with later requests, following
+/// RFC 6265 closely enough for logins, plus fixed ones sent everywhere.
+pub(crate) struct CookieJar {
+    cookies: StdMutex<Vec<Cookie>>,
+    fixed: Vec<(String, String)>,
+    /// The Netscape-format file read at the start and written by `save`.
+    path: Option<PathBuf>,
+}
+
+#[derive(Clone, Debug)]
+struct Cookie {
+    name: String,
+    value: String,
+    /// Lowercase, without a leading dot.
+    domain: String,
+    /// Only sent to `domain` itself, not its subdomains.
+    host_only: bool,
+    path: String,
+    secure: bool,
+    http_only: bool,
+    /// `None` for a session cookie.
+    expires: Option<SystemTime>,
+}
+
+impl CookieJar {
+    /// A jar sending `fixed` to every host, holding the cookies in `path`
+    /// if there is one. A missing file is fine, being created by `save`.
+    pub(crate) fn new(fixed: Vec<(String, String)>, path: Option<PathBuf>) -> Result<Self, String> {
+        let cookies = match &path {
+            Some(path) => match fs::read_to_string(path) {
+                Ok(text) => parse_netscape(&text).map_err(|e| format!("{}: {}", path.display(), e))?,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => vec![],
+                Err(e) => return Err(format!("{}: {}", path.display(), e)),
+            },
+            None => vec![],
+        };
+        Ok(CookieJar { cookies: StdMutex::new(cookies), fixed, path })
+    }
+
+    /// Adds the cookies for `request`'s URL to its `Cookie` header, after
+    /// any already there.
+    pub(crate) fn add_to(&self, request: &mut Request) {
+        let url = request.url().clone();
+        let now = SystemTime::now();
+        let mut cookies = self.lock();
+        cookies.retain(|cookie| !cookie.expired(now));
+        let mut matching: Vec<&Cookie> = cookies.iter().filter(|cookie| cookie.matches(&url)).collect();
+        // Longer paths first, as RFC 6265 suggests
+        matching.sort_by_key(|cookie| std::cmp::Reverse(cookie.path.len()));
+
+        let mut pairs: Vec<String> = self.fixed.iter().map(|(name, value)| format!("{}={}", name, value)).collect();
+        pairs.extend(matching.iter().map(|cookie| format!("{}={}", cookie.name, cookie.value)));
+        if pairs.is_empty() {
+            return;
+        }
+        let mut header = pairs.join("; ");
+        if let Some(existing) = request.headers().get(COOKIE).and_then(|v| v.to_str().ok()) {
+            header = format!("{}; {}", existing, header);
+        }
+        if let Ok(value) = HeaderValue::from_str(&header) {
+            request.headers_mut().insert(COOKIE, value);
+        }
+    }
+
+    /// Keeps the cookies a response from `url` set, dropping any it expired.
+    pub(crate) fn store(&self, url: &Url, headers: &HeaderMap) {
+        let mut cookies = self.lock();
+        for value in headers.get_all(SET_COOKIE).iter().filter_map(|v| v.to_str().ok()) {
+            let Some(cookie) = parse_set_cookie(url, value) else {
+                continue;
+            };
+            cookies.retain(|c| (&c.name, &c.domain, &c.path) != (&cookie.name, &cookie.domain, &cookie.path));
+            if !cookie.expired(SystemTime::now()) {
+                cookies.push(cookie);
+            }
+        }
+    }
+
+    /// Writes the cookies back to the file they were read from, session
+    /// cookies included, as curl does.
+    pub(crate) fn save(&self) -> io::Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let now = SystemTime::now();
+        let mut out = BufWriter::new(fs::File::create(path)?);
+        writeln!(out, "# Netscape HTTP Cookie File")?;
+        for cookie in self.lock().iter().filter(|cookie| !cookie.expired(now)) {
+            let expires = cookie.expires.map_or(0, |t| t.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs());
+            let domain = if cookie.host_only { cookie.domain.clone() } else { format!(".{}", cookie.domain) };
+            writeln!(
+                out,
+                "{}{}\t{}\t{}\t{}\t{}\t{}\t{}",
+                if cookie.http_only { "#HttpOnly_" } else { "" },
+                domain,
+                netscape_bool(!cookie.host_only),
+                cookie.path,
+                netscape_bool(cookie.secure),
+                expires,
+                cookie.name,
+                cookie.value,
+            )?;
+        }
+        out.flush()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Vec<Cookie>> {
+        self.cookies.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl Cookie {
+    fn expired(&self, now: SystemTime) -> bool {
+        self.expires.is_some_and(|expires| expires <= now)
+    }
+
+    fn matches(&self, url: &Url) -> bool {
+        let Some(host) = url.host_str() else {
+            return false;
+        };
+        let host = host.to_ascii_lowercase();
+        let domain_ok = if self.host_only { host == self.domain } else { domain_matches(&host, &self.domain) };
+        domain_ok && path_matches(url.path(), &self.path) && (!self.secure || url.scheme() == "https")
+    }
+}
+
+/// A `Set-Cookie` value from a response to `url`, or `None` if it's
+/// malformed or names a domain `url` isn't in.
+fn parse_set_cookie(url: &Url, value: &str) -> Option<Cookie> {
+    let mut parts = value.split(';');
+    let (name, value) = parts.next()?.split_once('=')?;
+    let name = name.trim();
+    if name.is_empty() {
+        return None;
+    }
+    let host = url.host_str()?.to_ascii_lowercase();
+
+    let mut cookie = Cookie {
+        name: name.to_string(),
+        value: value.trim().to_string(),
+        domain: host.clone(),
+        host_only: true,
+        path: default_path(url.path()),
+        secure: false,
+        http_only: false,
+        expires: None,
+    };
+    let mut max_age = None;
+    for attribute in parts {
+        let (key, value) = attribute.split_once('=').unwrap_or((attribute, ""));
+        let value = value.trim();
+        match key.trim().to_ascii_lowercase().as_str() {
+            "domain" if !value.is_empty() => {
+                let domain = value.trim_start_matches('.').to_ascii_lowercase();
+                // A bare suffix like `com` would reach every site under it
+                if !domain_matches(&host, &domain) || !domain.contains('.') {
+                    return None;
+                }
+                cookie.domain = domain;
+                cookie.host_only = false;
+            }
+            "path" if value.starts_with('/') => cookie.path = value.to_string(),
+            "secure" => cookie.secure = true,
+            "httponly" => cookie.http_only = true,
+            "expires" => {
+                if let Ok(expires) = httpdate::parse_http_date(value) {
+                    cookie.expires = Some(expires);
+                }
+            }
+            "max-age" => max_age = value.parse::<i64>().ok(),
+            _ => {}
+        }
+    }
+    // Max-Age wins over Expires
+    if let Some(secs) = max_age {
+        cookie.expires = Some(match u64::try_from(secs) {
+            Ok(secs) if secs > 0 => SystemTime::now() + Duration::from_secs(secs),
+            _ => UNIX_EPOCH,
+        });
+    }
+    Some(cookie)
+}
+
+/// Whether `host` is `domain` or a subdomain of it. IP addresses only
+/// match themselves.
+fn domain_matches(host: &str, domain: &str) -> bool {
+    if host == domain {
+        return true;
+    }
+    host.parse::<IpAddr>().is_err() && host.strip_suffix(domain).is_some_and(|rest| rest.ends_with('.'))
+}
+
+fn path_matches(request_path: &str, cookie_path: &str) -> bool {
+    request_path == cookie_path
+        || request_path
+            .strip_prefix(cookie_path)
+            .is_some_and(|rest| cookie_path.ends_with('/') || rest.starts_with('/'))
+}
+
+/// The directory of `path`, which a cookie without a `Path` is limited to.
+fn default_path(path: &str) -> String {
+    match path.rfind('/') {
+        Some(0) | None => "/".to_string(),
+        Some(last) => path[..last].to_string(),
+    }
+}
+
+/// Reads a Netscape/curl cookie file: tab-separated domain, subdomain flag,
+/// path, secure flag, expiry in Unix seconds (0 for a session cookie),
+/// name and value per line.
+fn parse_netscape(text: &str) -> Result<Vec<Cookie>, String> {
+    let mut cookies = vec![];
+    for (index, line) in text.lines().enumerate() {
+        let (line, http_only) = match line.strip_prefix("#HttpOnly_") {
+            Some(line) => (line, true),
+            None => (line, false),
+        };
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let fields: Vec<&str> = line.split('\t').collect();
+        let [domain, subdomains, path, secure, expires, name, value] = fields[..] else {
+            return Err(format!("line {}: expected 7 tab-separated fields", index + 1));
+        };
+        let expires: u64 = expires.trim().parse().map_err(|_| format!("line {}: invalid expiry {:?}", index + 1, expires))?;
+        cookies.push(Cookie {
+            name: name.to_string(),
+            value: value.to_string(),
+            domain: domain.trim_start_matches('.').to_ascii_lowercase(),
+            host_only: !subdomains.eq_ignore_ascii_case("TRUE"),
+            path: path.to_string(),
+            secure: secure.eq_ignore_ascii_case("TRUE"),
+            http_only,
+            expires: Some(expires).filter(|&secs| secs > 0).map(|secs| UNIX_EPOCH + Duration::from_secs(secs)),
+        });
+    }
+    Ok(cookies)
+}
+
+fn netscape_bool(value: bool) -> &'static str {
+    if value {
+        "TRUE"
+    } else {
+        "FALSE"
+    }
+}
@@ -0,0 +1,126 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const MONTHS: [&str; 12] = ["jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec"];
+
+/// Parses a date in RFC 2822 form (`Tue, 1 Jul 2003 10:52:37 +0200`), ISO
+/// 8601 form (`2003-07-01` or `2003-07-01T10:52:37+02:00`) or any HTTP
+/// date format. A time without a zone is taken as UTC, and a date without
+/// a time as its midnight.
+pub fn parse_date(value: &str) -> Option<SystemTime> {
+    let value = value.trim();
+    if let Ok(time) = httpdate::parse_http_date(value) {
+        return Some(time);
+    }
+    parse_iso8601(value).or_else(|| parse_rfc2822(value))
+}
+
+fn parse_iso8601(value: &str) -> Option<SystemTime> {
+    let (date, time) = match value.find(['T', 't', ' ']) {
+        Some(at) => (&value[..at], Some(&value[at + 1..])),
+        None => (value, None),
+    };
+    let fields: Vec<&str> = date.split('-').collect();
+    let [year, month, day] = fields[..] else {
+        return None;
+    };
+    if year.len() != 4 || month.len() != 2 || day.len() != 2 {
+        return None;
+    }
+    let (seconds, offset) = match time {
+        Some(time) => match time.find(['Z', 'z', '+', '-']) {
+            Some(at) => (clock_seconds(&time[..at])?, zone_offset(&time[at..])?),
+            None => (clock_seconds(time)?, 0),
+        },
+        None => (0, 0),
+    };
+    to_system_time(year.parse().ok()?, month.parse().ok()?, day.parse().ok()?, seconds, offset)
+}
+
+fn parse_rfc2822(value: &str) -> Option<SystemTime> {
+    // The day of the week adds nothing
+    let value = value.split_once(',').map_or(value, |(_, rest)| rest);
+    let parts: Vec<&str> = value.split_whitespace().collect();
+    let [day, month, year, clock, ref zone @ ..] = parts[..] else {
+        return None;
+    };
+    let offset = match zone {
+        [] => 0,
+        [zone] => zone_offset(zone)?,
+        _ => return None,
+    };
+    let month = MONTHS.iter().position(|m| m.eq_ignore_ascii_case(month))? as u32 + 1;
+    let year: i64 = match year.len() {
+        4 => year.parse().ok()?,
+        // Two-digit years, which RFC 2822 still reads
+        2 => year.parse::<i64>().ok().map(|y| if y < 50 { 2000 + y } else { 1900 + y })?,
+        _ => return None,
+    };
+    to_system_time(year, month, day.parse().ok()?, clock_seconds(clock)?, offset)
+}
+
+/// Seconds into the day of `HH:MM` or `HH:MM:SS`, ignoring any fraction of
+/// a second.
+fn clock_seconds(clock: &str) -> Option<i64> {
+    let clock = clock.split_once(['.', ',']).map_or(clock, |(whole, _)| whole);
+    let fields: Vec<i64> = clock.split(':').map(|field| field.parse().ok().filter(|_| field.len() == 2)).collect::<Option<_>>()?;
+    let (hour, minute, second) = match fields[..] {
+        [hour, minute] => (hour, minute, 0),
+        [hour, minute, second] => (hour, minute, second),
+        _ => return None,
+    };
+    // 60 for a leap second
+    (hour < 24 && minute < 60 && second <= 60).then_some(hour * 3600 + minute * 60 + second)
+}
+
+/// Seconds east of UTC for `Z`, `+HH:MM`, `+HHMM`, `+HH`, or a zone name
+/// RFC 2822 allows.
+fn zone_offset(zone: &str) -> Option<i64> {
+    let hours = match zone.to_ascii_uppercase().as_str() {
+        "Z" | "GMT" | "UT" | "UTC" => Some(0),
+        "EDT" => Some(-4),
+        "EST" | "CDT" => Some(-5),
+        "CST" | "MDT" => Some(-6),
+        "MST" | "PDT" => Some(-7),
+        "PST" => Some(-8),
+        _ => None,
+    };
+    if let Some(hours) = hours {
+        return Some(hours * 3600);
+    }
+    let (sign, digits) = match zone.split_at_checked(1)? {
+        ("+", digits) => (1, digits),
+        ("-", digits) => (-1, digits),
+        _ => return None,
+    };
+    let digits = digits.replacen(':', "", 1);
+    if !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let (hours, minutes) = match digits.len() {
+        2 => (digits.parse::<i64>().ok()?, 0),
+        4 => (digits[..2].parse::<i64>().ok()?, digits[2..].parse::<i64>().ok()?),
+        _ => return None,
+    };
+    (hours < 24 && minutes < 60).then_some(sign * (hours * 3600 + minutes * 60))
+}
+
+/// The instant of `seconds` into the given day, in a zone `offset` seconds
+/// east of UTC. `None` for a date that doesn't exist or is before 1970.
+fn to_system_time(year: i64, month: u32, day: u32, seconds: i64, offset: i64) -> Option<SystemTime> {
+    let leap = year % 4 == 0 && (year % 100 != 0 || year % 400 == 0);
+    let month_days = [31, if leap { 29 } else { 28 }, 31, 30, 31, 30, 31, 31, 30, 31, 30, 31];
+    if !(1..=12).contains(&month) || day == 0 || day > month_days[month as usize - 1] {
+        return None;
+    }
+    // A civil date to days since 1970-01-01 (Howard Hinnant's days_from_civil)
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year.rem_euclid(400);
+    let month_index = i64::from((month + 9) % 12);
+    let day_of_year = (153 * month_index + 2) / 5 + i64::from(day) - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = era * 146_097 + day_of_era - 719_468;
+
+    let secs = u64::try_from(days * 86_400 + seconds - offset).ok()?;
+    Some(UNIX_EPOCH + Duration::from_secs(secs))
+}
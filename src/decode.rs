@@ -0,0 +1,50 @@
+use flate2::read::GzDecoder;
+use reqwest::header::{HeaderMap, CONTENT_ENCODING};
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read};
+use std::path::Path;
+
+/// Whether the body was sent gzip-compressed as a `Content-Encoding`,
+/// rather than being a .gz file in its own right.
+pub(crate) fn is_gzip(headers: &HeaderMap) -> bool {
+    headers
+        .get(CONTENT_ENCODING)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| matches!(v.trim().to_ascii_lowercase().as_str(), "gzip" | "x-gzip"))
+}
+
+/// Whether `path` starts with the gzip magic number.
+pub(crate) fn starts_gzip(path: &Path) -> io::Result<bool> {
+    let mut magic = [0; 2];
+    match File::open(path)?.read_exact(&mut magic) {
+        Ok(()) => Ok(magic == [0x1f, 0x8b]),
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+/// `name` without a trailing `.gz`, unless that would leave nothing.
+pub(crate) fn strip_gz(name: &str) -> &str {
+    match name.len().checked_sub(3) {
+        Some(stem) if stem > 0 && name.is_char_boundary(stem) && name[stem..].eq_ignore_ascii_case(".gz") => &name[..stem],
+        _ => name,
+    }
+}
+
+/// Replaces the gzip data in `path` with its decompressed contents.
+pub(crate) fn gunzip_in_place(path: &Path) -> io::Result<()> {
+    let mut decoded_path = path.as_os_str().to_owned();
+    decoded_path.push(".decoded");
+
+    let result = (|| {
+        let mut decoder = GzDecoder::new(BufReader::new(File::open(path)?));
+        let mut out = BufWriter::new(File::create(&decoded_path)?);
+        io::copy(&mut decoder, &mut out)?;
+        out.into_inner().map_err(|e| e.into_error())?;
+        fs::rename(&decoded_path, path)
+    })();
+    if result.is_err() {
+        let _ = fs::remove_file(&decoded_path);
+    }
+    result
+}
@@ -0,0 +1,208 @@
+use md5::Md5;
+use sha2::{Digest, Sha256};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// A `WWW-Authenticate: Digest` challenge (RFC 7616), remembered per host so
+/// later requests can answer it up front.
+#[derive(Clone, Debug)]
+pub(crate) struct Challenge {
+    realm: String,
+    nonce: String,
+    opaque: Option<String>,
+    algorithm: Algorithm,
+    sess: bool,
+    qop: Option<Qop>,
+    /// Requests made with this nonce so far.
+    nonce_count: u32,
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Algorithm {
+    Md5,
+    Sha256,
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Qop {
+    Auth,
+    /// Integrity protection, which only has to cover the empty body of a GET or HEAD.
+    AuthInt,
+}
+
+impl Challenge {
+    /// The first Digest challenge among `WWW-Authenticate` header values
+    /// that uses an algorithm and qop this can answer.
+    pub(crate) fn from_headers<'a>(values: impl Iterator<Item = &'a str>) -> Option<Challenge> {
+        values.filter_map(Challenge::parse).next()
+    }
+
+    fn parse(value: &str) -> Option<Challenge> {
+        let value = value.trim();
+        let (scheme, params) = value.split_once(' ')?;
+        if !scheme.eq_ignore_ascii_case("digest") {
+            return None;
+        }
+
+        let mut realm = None;
+        let mut nonce = None;
+        let mut opaque = None;
+        let mut algorithm = "MD5".to_string();
+        let mut qop = None;
+        for (key, value) in params_of(params) {
+            match key.to_ascii_lowercase().as_str() {
+                "realm" => realm = Some(value),
+                "nonce" => nonce = Some(value),
+                "opaque" => opaque = Some(value),
+                "algorithm" => algorithm = value,
+                "qop" => qop = Some(value),
+                _ => {}
+            }
+        }
+
+        let (algorithm, sess) = match algorithm.to_ascii_uppercase().as_str() {
+            "MD5" => (Algorithm::Md5, false),
+            "MD5-SESS" => (Algorithm::Md5, true),
+            "SHA-256" => (Algorithm::Sha256, false),
+            "SHA-256-SESS" => (Algorithm::Sha256, true),
+            _ => return None,
+        };
+        let qop = match qop {
+            None => None,
+            Some(offered) => {
+                let offered: Vec<String> = offered.split(',').map(|q| q.trim().to_ascii_lowercase()).collect();
+                if offered.iter().any(|q| q == "auth") {
+                    Some(Qop::Auth)
+                } else if offered.iter().any(|q| q == "auth-int") {
+                    Some(Qop::AuthInt)
+                } else {
+                    return None;
+                }
+            }
+        };
+
+        Some(Challenge {
+            realm: realm?,
+            nonce: nonce?,
+            opaque,
+            algorithm,
+            sess,
+            qop,
+            nonce_count: 0,
+        })
+    }
+
+    /// The `Authorization` header value for a `method` request to `uri`
+    /// (the path and query), counting it against the nonce.
+    pub(crate) fn authorization(&mut self, user: &str, password: &str, method: &str, uri: &str) -> String {
+        self.nonce_count += 1;
+        let nc = format!("{:08x}", self.nonce_count);
+        let cnonce = self.cnonce();
+        let h = |data: &str| self.algorithm.hash(data);
+
+        let mut ha1 = h(&format!("{}:{}:{}", user, self.realm, password));
+        if self.sess {
+            ha1 = h(&format!("{}:{}:{}", ha1, self.nonce, cnonce));
+        }
+        let ha2 = match self.qop {
+            Some(Qop::AuthInt) => h(&format!("{}:{}:{}", method, uri, h(""))),
+            _ => h(&format!("{}:{}", method, uri)),
+        };
+        let response = match self.qop {
+            Some(qop) => h(&format!("{}:{}:{}:{}:{}:{}", ha1, self.nonce, nc, cnonce, qop.name(), ha2)),
+            None => h(&format!("{}:{}:{}", ha1, self.nonce, ha2)),
+        };
+
+        let mut header = format!(
+            "Digest username=\"{}\", realm=\"{}\", nonce=\"{}\", uri=\"{}\", algorithm={}, response=\"{}\"",
+            quote(user),
+            quote(&self.realm),
+            quote(&self.nonce),
+            quote(uri),
+            self.algorithm.name(self.sess),
+            response,
+        );
+        if let Some(qop) = self.qop {
+            header.push_str(&format!(", qop={}, nc={}, cnonce=\"{}\"", qop.name(), nc, cnonce));
+        }
+        if let Some(opaque) = &self.opaque {
+            header.push_str(&format!(", opaque=\"{}\"", quote(opaque)));
+        }
+        header
+    }
+
+    /// A client nonce that differs between requests; it only has to be
+    /// unpredictable enough that responses can't be precomputed.
+    fn cnonce(&self) -> String {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+        let seed = format!("{}:{}:{}", now.as_nanos(), self.nonce, self.nonce_count);
+        self.algorithm.hash(&seed)[..16].to_string()
+    }
+}
+
+impl Algorithm {
+    fn hash(self, data: &str) -> String {
+        match self {
+            Algorithm::Md5 => format!("{:x}", Md5::digest(data.as_bytes())),
+            Algorithm::Sha256 => format!("{:x}", Sha256::digest(data.as_bytes())),
+        }
+    }
+
+    fn name(self, sess: bool) -> &'static str {
+        match (self, sess) {
+            (Algorithm::Md5, false) => "MD5",
+            (Algorithm::Md5, true) => "MD5-sess",
+            (Algorithm::Sha256, false) => "SHA-256",
+            (Algorithm::Sha256, true) => "SHA-256-sess",
+        }
+    }
+}
+
+impl Qop {
+    fn name(self) -> &'static str {
+        match self {
+            Qop::Auth => "auth",
+            Qop::AuthInt => "auth-int",
+        }
+    }
+}
+
+/// Splits `key=value, key="quoted, value"` into pairs, unescaping quoted values.
+fn params_of(params: &str) -> Vec<(String, String)> {
+    let mut pairs = vec![];
+    let mut chars = params.chars().peekable();
+    loop {
+        while chars.peek().is_some_and(|&c| c == ',' || c.is_whitespace()) {
+            chars.next();
+        }
+        let key: String = chars.by_ref().take_while(|&c| c != '=').collect();
+        if key.is_empty() {
+            return pairs;
+        }
+
+        let mut value = String::new();
+        if chars.peek() == Some(&'"') {
+            chars.next();
+            while let Some(c) = chars.next() {
+                match c {
+                    '\\' => value.extend(chars.next()),
+                    '"' => break,
+                    c => value.push(c),
+                }
+            }
+        } else {
+            while let Some(&c) = chars.peek() {
+                if c == ',' {
+                    break;
+                }
+                value.push(c);
+                chars.next();
+            }
+            value = value.trim().to_string();
+        }
+        pairs.push((key.trim().to_string(), value));
+    }
+}
+
+fn quote(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"")
+}
@@ -0,0 +1,2508 @@
+use crate::checksum;
+use crate::cookies::CookieJar;
+use crate::decode;
+use crate::digest::Challenge;
+use crate::error::{DownloadError, DEFAULT_RETRY_STATUSES};
+use crate::events::{DownloadEvent, Subscribers};
+use crate::exec;
+use crate::filename;
+use crate::host_limit::{HostLimiter, HostPermit};
+use crate::open_files::{OpenFiles, PartWriter};
+use crate::proxy;
+use crate::rate_limit::RateLimiter;
+use crate::reporter::ProgressReporter;
+use crate::resolve::{IpFamily, Lookups, Resolver};
+use crate::session::Session;
+use crate::sidecar::{self, Validators};
+use crate::tls;
+use crate::stats::{DownloadRecord, DownloadStats, FileProgress};
+use crate::template::OutputTemplate;
+use log::{debug, info, trace, warn};
+use reqwest::redirect::Policy;
+use reqwest::{Client, Method, Proxy, Request, RequestBuilder, Response, StatusCode, Url};
+use reqwest::header::{
+    HeaderMap, HeaderName, HeaderValue, ACCEPT_ENCODING, ACCEPT_RANGES, AUTHORIZATION, CONTENT_DISPOSITION, CONTENT_LENGTH,
+    CONTENT_RANGE, CONTENT_TYPE, LAST_MODIFIED, LOCATION, ORIGIN, RANGE, REFERER, RETRY_AFTER, WWW_AUTHENTICATE,
+};
+use sha2::{Digest, Sha256};
+use std::cmp::Reverse;
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File, OpenOptions};
+use std::io;
+use std::net::SocketAddr;
+use std::panic::AssertUnwindSafe;
+use std::path::{Path, PathBuf};
+use filetime::FileTime;
+use futures_util::{FutureExt, Stream, StreamExt};
+use tokio::task;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex as StdMutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::time;
+use tokio::sync::{watch, Mutex, Semaphore, SemaphorePermit};
+
+mod probe;
+mod segmented;
+mod stream;
+mod verify;
+
+pub use probe::Probe;
+
+/// Configures and builds a [`Downloader`].
+pub struct DownloaderBuilder {
+    max_concurrent: usize,
+    max_per_host: Option<usize>,
+    retries: u32,
+    retry_statuses: HashSet<u16>,
+    output_dir: PathBuf,
+    preserve_path: bool,
+    output_template: Option<OutputTemplate>,
+    checksums: HashMap<String, String>,
+    sizes: HashMap<String, u64>,
+    priorities: HashMap<String, i32>,
+    file_names: HashMap<String, String>,
+    mirrors: HashMap<String, Vec<String>>,
+    limit_rate: Option<u64>,
+    limit_rate_per_file: Option<u64>,
+    headers: HeaderMap,
+    auth: Option<Auth>,
+    timeout: Option<Duration>,
+    idle_timeout: Option<Duration>,
+    file_timeout: Option<Duration>,
+    min_speed: Option<MinSpeed>,
+    wait: Option<Wait>,
+    connections: usize,
+    segment_strategy: SegmentStrategy,
+    buffer_size: usize,
+    max_open_files: usize,
+    preallocate: bool,
+    force: bool,
+    timestamping: bool,
+    if_changed: bool,
+    only_newer: bool,
+    modified_since: Option<SystemTime>,
+    decompress: bool,
+    decompress_gzip: bool,
+    max_size: Option<u64>,
+    max_total_bytes: Option<u64>,
+    fail_on_empty: bool,
+    expected_type: Option<String>,
+    /// Whether a checksum sidecar is required, if they are checked at all.
+    verify_sidecar: Option<bool>,
+    mode: Option<u32>,
+    user_agent: Option<String>,
+    referer: Option<Referer>,
+    origin: Option<String>,
+    exec: Option<Vec<String>>,
+    proxy: Option<String>,
+    ca_certs: Vec<PathBuf>,
+    client_cert: Option<(PathBuf, PathBuf)>,
+    accept_invalid_certs: bool,
+    ip_family: Option<(IpFamily, bool)>,
+    pool_max_idle_per_host: usize,
+    pool_idle_timeout: Option<Duration>,
+    tcp_keepalive: Option<Duration>,
+    max_redirects: usize,
+    cookies: Vec<(String, String)>,
+    cookie_jar: Option<PathBuf>,
+    session: Option<PathBuf>,
+    timing: bool,
+    client: Option<Client>,
+    reporter: Option<Arc<dyn ProgressReporter>>,
+}
+
+/// How [`DownloaderBuilder::segment_strategy`] writes a file's segments.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SegmentStrategy {
+    /// Each segment is written at its own offset in the one .part file.
+    Seek,
+    /// Each segment is written to a file of its own from the start, and
+    /// they are joined into the .part file in order once all have arrived,
+    /// for filesystems where seeking is unreliable, like some network
+    /// mounts. Joining needs about a segment's worth of extra space.
+    TempFiles,
+}
+
+/// What [`DownloaderBuilder::referer`] sends as the `Referer`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Referer {
+    /// This URL, with every request.
+    Url(String),
+    /// The origin of the URL being downloaded, like `https://example.com/`.
+    Auto,
+}
+
+/// Credentials attached to every request. Never printed.
+#[derive(Clone)]
+enum Auth {
+    Basic { user: String, password: Option<String> },
+    Bearer(String),
+    Digest { user: String, password: String },
+}
+
+impl DownloaderBuilder {
+    /// Maximum number of downloads running at once; the rest queue. Defaults to 4.
+    pub fn max_concurrent(mut self, max_concurrent: usize) -> Self {
+        self.max_concurrent = max_concurrent.max(1);
+        self
+    }
+
+    /// Also limits downloads running at once from any one host, counting
+    /// each `host:port` separately. Unlimited by default.
+    pub fn max_per_host(mut self, max_per_host: usize) -> Self {
+        self.max_per_host = Some(max_per_host.max(1));
+        self
+    }
+
+    /// How many times a transient failure is retried. Defaults to 0.
+    pub fn retries(mut self, retries: u32) -> Self {
+        self.retries = retries;
+        self
+    }
+
+    /// The HTTP statuses that get retried, replacing the default of 408,
+    /// 429, 500, 502, 503 and 504. Any other error status fails at once.
+    /// A 429 or 503 with `Retry-After` waits as long as it asks.
+    pub fn retry_on_status(mut self, statuses: impl IntoIterator<Item = u16>) -> Self {
+        self.retry_statuses = statuses.into_iter().collect();
+        self
+    }
+
+    /// Directory files are saved into. Defaults to the current directory.
+    pub fn output_dir(mut self, output_dir: impl Into<PathBuf>) -> Self {
+        self.output_dir = output_dir.into();
+        self
+    }
+
+    /// Saves each file under subdirectories of the output directory named
+    /// after its URL's host and path, e.g. `host/a/b/file.bin`, instead of
+    /// directly in it. Files given an explicit name aren't affected.
+    pub fn preserve_path(mut self, preserve_path: bool) -> Self {
+        self.preserve_path = preserve_path;
+        self
+    }
+
+    /// Saves each file at the path `template` expands to inside the output
+    /// directory, creating its directories, instead of directly in it or
+    /// following [`preserve_path`](Self::preserve_path). Files given an
+    /// explicit name aren't affected.
+    pub fn output_template(mut self, template: OutputTemplate) -> Self {
+        self.output_template = Some(template);
+        self
+    }
+
+    /// Expected SHA-256 (hex) of the file downloaded from `url`.
+    pub fn sha256(mut self, url: impl Into<String>, hex: impl Into<String>) -> Self {
+        self.checksums.insert(url.into(), hex.into().to_ascii_lowercase());
+        self
+    }
+
+    /// Expected size in bytes of the file downloaded from `url`. A response
+    /// announcing another length fails before its body is read, and a file
+    /// that turns out another size once saved is deleted, failing with
+    /// [`DownloadError::UnexpectedSize`] so any mirror is tried next.
+    pub fn expected_size(mut self, url: impl Into<String>, bytes: u64) -> Self {
+        self.sizes.insert(url.into(), bytes);
+        self
+    }
+
+    /// Makes [`Downloader::download_many`] start `url` before URLs with a
+    /// lower priority. URLs default to 0, and equal priorities go in order.
+    pub fn priority(mut self, url: impl Into<String>, priority: i32) -> Self {
+        self.priorities.insert(url.into(), priority);
+        self
+    }
+
+    /// Saves `url` as `name` inside the output directory instead of
+    /// deriving the name from the response or URL.
+    pub fn file_name(mut self, url: impl Into<String>, name: impl Into<String>) -> Self {
+        self.file_names.insert(url.into(), name.into());
+        self
+    }
+
+    /// Adds `mirror` as an alternative source for `url`, tried in the order
+    /// added once `url` itself has failed. The file keeps `url`'s name,
+    /// checksum and progress entry, and a partial download carries over to
+    /// the mirror when it reports the same size.
+    pub fn mirror(mut self, url: impl Into<String>, mirror: impl Into<String>) -> Self {
+        self.mirrors.entry(url.into()).or_default().push(mirror.into());
+        self
+    }
+
+    /// Caps the combined speed of all downloads, in bytes per second.
+    pub fn limit_rate(mut self, bytes_per_sec: u64) -> Self {
+        self.limit_rate = Some(bytes_per_sec);
+        self
+    }
+
+    /// Caps the speed of each download on its own, in bytes per second.
+    /// Combines with [`limit_rate`](Self::limit_rate): every chunk waits for
+    /// both.
+    pub fn limit_rate_per_file(mut self, bytes_per_sec: u64) -> Self {
+        self.limit_rate_per_file = Some(bytes_per_sec);
+        self
+    }
+
+    /// Adds a header to every request. Repeating a name sends it more than once.
+    pub fn header(mut self, name: HeaderName, value: HeaderValue) -> Self {
+        self.headers.append(name, value);
+        self
+    }
+
+    /// Sends HTTP Basic credentials with every request, replacing any bearer token.
+    pub fn basic_auth(mut self, user: impl Into<String>, password: Option<String>) -> Self {
+        self.auth = Some(Auth::Basic { user: user.into(), password });
+        self
+    }
+
+    /// Answers HTTP Digest challenges with these credentials, replacing any
+    /// Basic credentials or bearer token. Nothing is sent until a server asks.
+    pub fn digest_auth(mut self, user: impl Into<String>, password: impl Into<String>) -> Self {
+        self.auth = Some(Auth::Digest { user: user.into(), password: password.into() });
+        self
+    }
+
+    /// Sends `Authorization: Bearer <token>` with every request, replacing any
+    /// Basic credentials.
+    pub fn bearer_auth(mut self, token: impl Into<String>) -> Self {
+        self.auth = Some(Auth::Bearer(token.into()));
+        self
+    }
+
+    /// Bounds each whole request, from connecting to the last body byte.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Aborts a download when no bytes arrive for this long.
+    pub fn idle_timeout(mut self, idle_timeout: Duration) -> Self {
+        self.idle_timeout = Some(idle_timeout);
+        self
+    }
+
+    /// Aborts an attempt at a file that runs longer than this in total,
+    /// however much data is arriving, as a retryable timeout. The .part
+    /// file is kept as on [`Downloader::cancel`].
+    pub fn file_timeout(mut self, file_timeout: Duration) -> Self {
+        self.file_timeout = Some(file_timeout);
+        self
+    }
+
+    /// Aborts a download, as a retryable timeout, when its speed over the
+    /// last few seconds stays below `bytes_per_sec` for `grace`. Nothing is
+    /// checked until the first byte of an attempt arrives.
+    pub fn min_speed(mut self, bytes_per_sec: u64, grace: Duration) -> Self {
+        self.min_speed = Some(MinSpeed { bytes_per_sec, grace });
+        self
+    }
+
+    /// Waits `delay` after a download starts or finishes before starting
+    /// the next, to go easy on the server. One at a time this is a pause
+    /// between downloads; with more it staggers their starts. With `random`
+    /// each wait is anywhere from half to one and a half times `delay`, as
+    /// wget's `--random-wait` does.
+    pub fn wait(mut self, delay: Duration, random: bool) -> Self {
+        self.wait = Some(Wait { delay, random }).filter(|wait| !wait.delay.is_zero());
+        self
+    }
+
+    /// Splits each file across up to this many parallel ranged requests when
+    /// the server supports it. Defaults to 1.
+    pub fn connections(mut self, connections: usize) -> Self {
+        self.connections = connections.max(1);
+        self
+    }
+
+    /// How a file split across [`connections`](Self::connections) is put
+    /// together on disk. Defaults to [`SegmentStrategy::Seek`].
+    pub fn segment_strategy(mut self, strategy: SegmentStrategy) -> Self {
+        self.segment_strategy = strategy;
+        self
+    }
+
+    /// Bytes buffered in memory before each write to disk. Defaults to 64 KiB.
+    ///
+    /// Each download, and each connection of a segmented one, holds at most
+    /// this plus one network chunk: the next chunk isn't read until the
+    /// buffer has reached the disk.
+    pub fn buffer_size(mut self, buffer_size: usize) -> Self {
+        self.buffer_size = buffer_size.max(1);
+        self
+    }
+
+    /// Keeps at most this many downloaded files open at once, counting each
+    /// connection of a segmented download. Once they are all taken, writers
+    /// close their file after each buffer they write and reopen it for the
+    /// next. Defaults to 256.
+    pub fn max_open_files(mut self, max_open_files: usize) -> Self {
+        self.max_open_files = max_open_files.max(1);
+        self
+    }
+
+    /// Reserves the full size of single-stream downloads up front, failing
+    /// early when the disk doesn't have room. Segmented downloads always do.
+    ///
+    /// A preallocated .part file is truncated back to the bytes written if
+    /// the download fails, so it can still be resumed; a crash skips that
+    /// step, which is why this is off by default.
+    pub fn preallocate(mut self, preallocate: bool) -> Self {
+        self.preallocate = preallocate;
+        self
+    }
+
+    /// Re-downloads files that already exist instead of skipping them.
+    /// An existing .part file is resumed either way.
+    pub fn force(mut self, force: bool) -> Self {
+        self.force = force;
+        self
+    }
+
+    /// Sets each saved file's modification time from the server's
+    /// `Last-Modified` header, when it sends a valid one. Defaults to true.
+    pub fn timestamping(mut self, timestamping: bool) -> Self {
+        self.timestamping = timestamping;
+        self
+    }
+
+    /// Saves each file's `ETag` and `Last-Modified` in a `<file>.meta`
+    /// sidecar, and instead of skipping an existing file that has one, asks
+    /// the server whether it changed. A 304 leaves the file alone. Defaults
+    /// to false.
+    pub fn if_changed(mut self, if_changed: bool) -> Self {
+        self.if_changed = if_changed;
+        self
+    }
+
+    /// Checks existing files against the server instead of skipping them,
+    /// fetching one again only if the server's copy is newer than the
+    /// file's modification time, like wget's `-N`. The check is an
+    /// `If-Modified-Since` request, and a server that ignores it and sends
+    /// a `Last-Modified` no newer than the file, for a body of the file's
+    /// size, is taken as answering 304. Works best with
+    /// [`timestamping`](Self::timestamping), which the file's time comes
+    /// from. Defaults to false.
+    pub fn only_newer(mut self, only_newer: bool) -> Self {
+        self.only_newer = only_newer;
+        self
+    }
+
+    /// Sends `If-Modified-Since: since` for every URL, whether or not its
+    /// file exists here, and skips the ones the server answers 304 for: a
+    /// stateless alternative to [`only_newer`](Self::only_newer) for callers
+    /// who keep track of when they last synced. A .part file being resumed
+    /// is fetched without it. Unset by default.
+    pub fn if_modified_since(mut self, since: SystemTime) -> Self {
+        self.modified_since = Some(since);
+        self
+    }
+
+    /// Accepts gzip-compressed responses and saves them decompressed.
+    /// Progress and resuming count the compressed bytes, and the file is
+    /// decompressed once they have all arrived. Defaults to false, which
+    /// saves bodies exactly as sent.
+    pub fn decompress(mut self, decompress: bool) -> Self {
+        self.decompress = decompress;
+        self
+    }
+
+    /// Saves gzip files decompressed, dropping a `.gz` from their names.
+    /// Unlike [`decompress`](Self::decompress) this is about what the file
+    /// is rather than how it was sent: any download whose bytes are gzip
+    /// data is decompressed once complete, so progress, resuming and
+    /// checksums all go by the compressed download. Names set with
+    /// [`file_name`](Self::file_name) are kept as given. Defaults to false.
+    pub fn decompress_gzip(mut self, decompress_gzip: bool) -> Self {
+        self.decompress_gzip = decompress_gzip;
+        self
+    }
+
+    /// Rejects files bigger than `bytes` with [`DownloadError::TooLarge`]:
+    /// before downloading when the server sends a length, otherwise as soon
+    /// as more arrives. The partial file is deleted.
+    pub fn max_size(mut self, bytes: u64) -> Self {
+        self.max_size = Some(bytes);
+        self
+    }
+
+    /// Fails downloads that finish with no bytes with [`DownloadError::Empty`],
+    /// which is retried, instead of saving an empty file with a warning.
+    /// Defaults to false.
+    pub fn fail_on_empty(mut self, fail_on_empty: bool) -> Self {
+        self.fail_on_empty = fail_on_empty;
+        self
+    }
+
+    /// Caps the bytes received over the whole run, counting every attempt
+    /// and mirror, for metered connections. Once more than `bytes` have
+    /// arrived, no more downloads start and the running ones are stopped as
+    /// by [`Downloader::cancel`], keeping their .part files to resume later.
+    /// See [`DownloadStats::received`].
+    pub fn max_total_bytes(mut self, bytes: u64) -> Self {
+        self.max_total_bytes = Some(bytes);
+        self
+    }
+
+    /// Fails a download with [`DownloadError::UnexpectedContentType`] before
+    /// saving anything unless its `Content-Type` matches one of the
+    /// comma-separated media types in `expected`, such as
+    /// `application/zip, application/octet-stream`. `application/*` matches
+    /// any subtype and parameters like `charset` are ignored. Catches HTML
+    /// error and login pages served with a 200.
+    pub fn expect_content_type(mut self, expected: impl Into<String>) -> Self {
+        self.expected_type = Some(expected.into());
+        self
+    }
+
+    /// After each download, fetches the first of `<url>.sha256`, `.sha1` and
+    /// `.md5` the server has and checks the file against it, as sent and so
+    /// before any [`decompress_gzip`](Self::decompress_gzip), deleting it on
+    /// a mismatch. Without a sidecar the file is kept with a warning, or
+    /// with `required` deleted and the download failed.
+    pub fn verify_sidecar(mut self, required: bool) -> Self {
+        self.verify_sidecar = Some(required);
+        self
+    }
+
+    /// Sets each downloaded file's Unix permission bits to `mode`, e.g.
+    /// `0o755`, once it has its final name and passed any checks. Other files
+    /// are created with the usual mode less the umask. Does nothing on
+    /// Windows.
+    pub fn chmod(mut self, mode: u32) -> Self {
+        self.mode = Some(mode);
+        self
+    }
+
+    /// The `User-Agent` sent with every request, instead of
+    /// `rs-downloader/<version>`. A `User-Agent` passed to
+    /// [`header`](Self::header) takes precedence over both.
+    pub fn user_agent(mut self, user_agent: impl Into<String>) -> Self {
+        self.user_agent = Some(user_agent.into());
+        self
+    }
+
+    /// The `Referer` sent with every request, for servers that only serve
+    /// files linked from their own pages. It is kept across redirects
+    /// rather than replaced by the URL redirected from. A `Referer` passed
+    /// to [`header`](Self::header) takes precedence.
+    pub fn referer(mut self, referer: Referer) -> Self {
+        self.referer = Some(referer);
+        self
+    }
+
+    /// The `Origin` sent with every request: the scheme, host and port of
+    /// `origin`, which can be any URL on that site. An `Origin` passed to
+    /// [`header`](Self::header) takes precedence.
+    pub fn origin(mut self, origin: impl Into<String>) -> Self {
+        self.origin = Some(origin.into());
+        self
+    }
+
+    /// Runs `command` after each file is downloaded and verified, with `{}`
+    /// in it replaced by the saved path. It is split on whitespace and run
+    /// without a shell; a non-zero exit is reported as a warning. Files
+    /// skipped as already present don't run it.
+    pub fn exec(mut self, command: &str) -> Self {
+        self.exec = Some(command.split_whitespace().map(str::to_string).collect());
+        self
+    }
+
+    /// Trusts the certificates in the PEM file at `path` as well as the
+    /// system's. Repeatable.
+    pub fn ca_cert(mut self, path: impl Into<PathBuf>) -> Self {
+        self.ca_certs.push(path.into());
+        self
+    }
+
+    /// Authenticates to servers that ask for a client certificate with the
+    /// PEM certificate at `cert` and the PKCS#8 PEM key at `key`.
+    pub fn client_cert(mut self, cert: impl Into<PathBuf>, key: impl Into<PathBuf>) -> Self {
+        self.client_cert = Some((cert.into(), key.into()));
+        self
+    }
+
+    /// Accepts any certificate, including expired, self-signed and
+    /// wrong-host ones. Anyone on the network path can then read and alter
+    /// the downloads, so only use this for testing. Defaults to false.
+    pub fn danger_accept_invalid_certs(mut self, accept: bool) -> Self {
+        self.accept_invalid_certs = accept;
+        self
+    }
+
+    /// Connects only over `family`. With `fallback`, hosts are still
+    /// reached over the other family when they have no `family` address or
+    /// none of those connect, with a warning in the first case.
+    pub fn ip_family(mut self, family: IpFamily, fallback: bool) -> Self {
+        self.ip_family = Some((family, fallback));
+        self
+    }
+
+    /// Keeps up to `max` idle connections per host for later requests to
+    /// reuse, 0 closing each once its response is read. A batch running
+    /// more than `max` requests on one host at once, as
+    /// [`max_per_host`](Self::max_per_host) times
+    /// [`connections`](Self::connections) allows, drops the connections
+    /// past `max` when they go idle, to be made again. Defaults to 10.
+    pub fn pool_max_idle_per_host(mut self, max: usize) -> Self {
+        self.pool_max_idle_per_host = max;
+        self
+    }
+
+    /// Closes pooled connections once they've been idle this long. Defaults
+    /// to 90 seconds.
+    pub fn pool_idle_timeout(mut self, timeout: Duration) -> Self {
+        self.pool_idle_timeout = Some(timeout);
+        self
+    }
+
+    /// Sends TCP keepalive probes on connections idle this long, so ones a
+    /// NAT or firewall silently dropped are noticed. Off by default.
+    pub fn tcp_keepalive(mut self, interval: Duration) -> Self {
+        self.tcp_keepalive = Some(interval);
+        self
+    }
+
+    /// Follows at most this many redirects per request, failing past that.
+    /// With 0 a redirect fails the download like any other non-2xx status.
+    /// Defaults to 10.
+    pub fn max_redirects(mut self, max_redirects: usize) -> Self {
+        self.max_redirects = max_redirects;
+        self
+    }
+
+    /// Sends the cookie `name=value` with every request.
+    ///
+    /// This or [`cookie_jar`](Self::cookie_jar) also keeps the cookies
+    /// responses set, sending them back as a browser would, so a login
+    /// followed by the download it allows works in one run. The
+    /// downloader then follows redirects itself so cookies set along the
+    /// way are kept too, and one past [`max_redirects`](Self::max_redirects)
+    /// fails as an HTTP status.
+    pub fn cookie(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.cookies.push((name.into(), value.into()));
+        self
+    }
+
+    /// Starts with the cookies in the Netscape-format file at `path`, as
+    /// written by curl and browser extensions, and keeps cookies as
+    /// [`cookie`](Self::cookie) describes. [`Downloader::save_cookies`]
+    /// writes them back. A missing file starts the jar empty; an
+    /// unreadable or malformed one fails [`build`](Self::build).
+    pub fn cookie_jar(mut self, path: impl Into<PathBuf>) -> Self {
+        self.cookie_jar = Some(path.into());
+        self
+    }
+
+    /// Keeps a JSON manifest at `path` of every URL downloaded: its status,
+    /// where it's saved, how many bytes are down and the validators it was
+    /// served with, updated as downloads go. A later downloader with the
+    /// same session saves each URL where the manifest says, wherever it's
+    /// run from, so finished files are skipped and partial ones resumed.
+    /// [`Downloader::save_session`] writes out the last progress.
+    ///
+    /// Only one downloader can have a session open at a time; another
+    /// fails [`build`](Self::build), as does a manifest that can't be read.
+    pub fn session(mut self, path: impl Into<PathBuf>) -> Self {
+        self.session = Some(path.into());
+        self
+    }
+
+    /// Logs how long each download's DNS lookup, response headers and
+    /// first body byte took, and the address it connected to, at the info
+    /// level rather than debug.
+    /// They are logged at debug level either way.
+    pub fn timing(mut self, timing: bool) -> Self {
+        self.timing = timing;
+        self
+    }
+
+    /// Sends every request through `proxy`, an `http://`, `https://` or
+    /// `socks5://` URL that may embed `user:pass@` credentials.
+    ///
+    /// Without one, `HTTP_PROXY`, `HTTPS_PROXY` and `ALL_PROXY` are honoured.
+    /// Either way a malformed proxy URL fails [`build`](Self::build) rather
+    /// than falling back to a direct connection.
+    pub fn proxy(mut self, proxy: impl Into<String>) -> Self {
+        self.proxy = Some(proxy.into());
+        self
+    }
+
+    /// Sends every request through `client` instead of one built here,
+    /// sharing its connection pool, cookie store and TLS setup.
+    ///
+    /// The options that configure the client itself ([`timeout`](Self::timeout),
+    /// [`user_agent`](Self::user_agent), [`proxy`](Self::proxy),
+    /// [`ca_cert`](Self::ca_cert), [`client_cert`](Self::client_cert),
+    /// [`danger_accept_invalid_certs`](Self::danger_accept_invalid_certs),
+    /// [`ip_family`](Self::ip_family), the pool and keepalive options and
+    /// [`max_redirects`](Self::max_redirects))
+    /// would have no effect, so setting any of them fails [`build`](Self::build).
+    /// [`timing`](Self::timing) can't see `client`'s DNS lookups.
+    pub fn client(mut self, client: Client) -> Self {
+        self.client = Some(client);
+        self
+    }
+
+    /// Sends download events to `reporter`.
+    pub fn reporter(mut self, reporter: Arc<dyn ProgressReporter>) -> Self {
+        self.reporter = Some(reporter);
+        self
+    }
+
+    pub fn build(mut self) -> Result<Downloader, DownloadError> {
+        if let (Some(Referer::Url(url)), false) = (&self.referer, self.headers.contains_key(REFERER)) {
+            let value = Url::parse(url).ok().and_then(|url| HeaderValue::from_str(url.as_str()).ok());
+            let value = value.ok_or_else(|| DownloadError::Config(format!("Invalid referer {:?}: expected an absolute URL", url)))?;
+            self.headers.insert(REFERER, value);
+        }
+        if let (Some(origin), false) = (&self.origin, self.headers.contains_key(ORIGIN)) {
+            let value = Url::parse(origin).ok().map(|url| url.origin()).filter(|origin| origin.is_tuple());
+            let value = value.and_then(|origin| HeaderValue::from_str(&origin.ascii_serialization()).ok());
+            let value = value.ok_or_else(|| DownloadError::Config(format!("Invalid origin {:?}: expected an http or https URL", origin)))?;
+            self.headers.insert(ORIGIN, value);
+        }
+        let cookies = match (self.cookies.is_empty(), &self.cookie_jar) {
+            (true, None) => None,
+            _ => Some(Arc::new(CookieJar::new(self.cookies.clone(), self.cookie_jar.clone()).map_err(DownloadError::Config)?)),
+        };
+        let session = match &self.session {
+            Some(path) => Some(Arc::new(Session::open(path).map_err(DownloadError::Config)?)),
+            None => None,
+        };
+        let lookups = Lookups::default();
+        let client = match &self.client {
+            Some(client) => {
+                if let Some(option) = self.client_option() {
+                    return Err(DownloadError::Config(format!(
+                        "{} can't be set along with an existing client; configure the client instead",
+                        option
+                    )));
+                }
+                client.clone()
+            }
+            None => self.build_client(&lookups, cookies.is_some())?,
+        };
+
+        Ok(Downloader {
+            client,
+            semaphore: Arc::new(Semaphore::new(self.max_concurrent)),
+            max_concurrent: self.max_concurrent,
+            hosts: self.max_per_host.map(|max| Arc::new(HostLimiter::new(max))),
+            priorities: Arc::new(self.priorities),
+            retries: self.retries,
+            retry_statuses: Arc::new(self.retry_statuses),
+            output_dir: self.output_dir,
+            preserve_path: self.preserve_path,
+            template: self.output_template.map(Arc::new),
+            batch: Arc::new(StdMutex::new(HashMap::new())),
+            checksums: Arc::new(self.checksums),
+            sizes: Arc::new(self.sizes),
+            file_names: Arc::new(self.file_names),
+            mirrors: Arc::new(self.mirrors),
+            rate_limiter: self.limit_rate.map(|rate| Arc::new(RateLimiter::new(rate))),
+            file_rate: self.limit_rate_per_file,
+            file_limiters: Arc::new(StdMutex::new(HashMap::new())),
+            headers: self.headers,
+            auth: self.auth,
+            auto_referer: self.referer == Some(Referer::Auto),
+            idle_timeout: self.idle_timeout,
+            file_timeout: self.file_timeout,
+            min_speed: self.min_speed,
+            wait: self.wait,
+            last_turn: Arc::new(StdMutex::new(None)),
+            connections: self.connections,
+            segment_strategy: self.segment_strategy,
+            buffer_size: self.buffer_size,
+            open_files: OpenFiles::new(self.max_open_files),
+            preallocate: self.preallocate,
+            force: self.force,
+            timestamping: self.timestamping,
+            if_changed: self.if_changed,
+            only_newer: self.only_newer,
+            modified_since: self.modified_since,
+            decompress: self.decompress,
+            decompress_gzip: self.decompress_gzip,
+            max_size: self.max_size,
+            max_total_bytes: self.max_total_bytes,
+            fail_on_empty: self.fail_on_empty,
+            byte_limit_hit: Arc::new(AtomicBool::new(false)),
+            expected_type: self.expected_type,
+            verify_sidecar: self.verify_sidecar,
+            mode: self.mode,
+            exec: self.exec.map(Arc::new),
+            max_redirects: self.max_redirects,
+            cookies,
+            session,
+            timing: self.timing,
+            lookups,
+            reporter: self.reporter,
+            events: Arc::new(Subscribers::default()),
+            cancel: Arc::new(watch::channel(Cancellation::default()).0),
+            claimed: Arc::new(StdMutex::new(HashMap::new())),
+            challenges: Arc::new(StdMutex::new(HashMap::new())),
+            stats: Arc::new(Mutex::new(DownloadStats::new())),
+        })
+    }
+
+    /// With `cookies`, redirects are left for [`Downloader::send`] to follow.
+    fn build_client(&self, lookups: &Lookups, cookies: bool) -> Result<Client, DownloadError> {
+        let user_agent = self.user_agent.as_deref().unwrap_or(DEFAULT_USER_AGENT);
+        let mut client = Client::builder()
+            .pool_max_idle_per_host(self.pool_max_idle_per_host)
+            .tcp_keepalive(self.tcp_keepalive)
+            .user_agent(user_agent);
+        if let Some(timeout) = self.pool_idle_timeout {
+            client = client.pool_idle_timeout(timeout);
+        }
+        if self.referer.is_some() {
+            // Redirects would otherwise replace it with the URL they came from
+            client = client.referer(false);
+        }
+        if let Some(timeout) = self.timeout {
+            client = client.timeout(timeout);
+        }
+        match &self.proxy {
+            Some(url) => client = client.proxy(Proxy::all(proxy::parse(url)?)?),
+            None => proxy::check_env()?,
+        }
+        // Read here so a bad file fails now, not as a handshake error later
+        for path in &self.ca_certs {
+            for cert in tls::root_certificates(path)? {
+                client = client.add_root_certificate(cert);
+            }
+        }
+        if let Some((cert, key)) = &self.client_cert {
+            client = client.identity(tls::identity(cert, key)?);
+        }
+        if self.accept_invalid_certs {
+            client = client.danger_accept_invalid_certs(true);
+        }
+        client = client.redirect(if cookies { Policy::none() } else { redirect_policy(self.max_redirects) });
+        client = client.dns_resolver(Arc::new(Resolver { family: self.ip_family, lookups: lookups.clone() }));
+        Ok(client.build()?)
+    }
+
+    /// The first option set that only a client built here would use.
+    fn client_option(&self) -> Option<&'static str> {
+        [
+            ("timeout", self.timeout.is_some()),
+            ("user_agent", self.user_agent.is_some()),
+            ("proxy", self.proxy.is_some()),
+            ("ca_cert", !self.ca_certs.is_empty()),
+            ("client_cert", self.client_cert.is_some()),
+            ("danger_accept_invalid_certs", self.accept_invalid_certs),
+            ("ip_family", self.ip_family.is_some()),
+            ("pool_max_idle_per_host", self.pool_max_idle_per_host != DEFAULT_POOL_MAX_IDLE_PER_HOST),
+            ("pool_idle_timeout", self.pool_idle_timeout.is_some()),
+            ("tcp_keepalive", self.tcp_keepalive.is_some()),
+            ("max_redirects", self.max_redirects != DEFAULT_MAX_REDIRECTS),
+        ]
+        .into_iter()
+        .find(|&(_, set)| set)
+        .map(|(option, _)| option)
+    }
+}
+
+impl Default for DownloaderBuilder {
+    fn default() -> Self {
+        DownloaderBuilder {
+            max_concurrent: 4,
+            max_per_host: None,
+            retries: 0,
+            retry_statuses: DEFAULT_RETRY_STATUSES.into_iter().collect(),
+            output_dir: PathBuf::from("."),
+            preserve_path: false,
+            output_template: None,
+            checksums: HashMap::new(),
+            sizes: HashMap::new(),
+            priorities: HashMap::new(),
+            file_names: HashMap::new(),
+            mirrors: HashMap::new(),
+            limit_rate: None,
+            limit_rate_per_file: None,
+            headers: HeaderMap::new(),
+            auth: None,
+            timeout: None,
+            idle_timeout: None,
+            file_timeout: None,
+            min_speed: None,
+            wait: None,
+            connections: 1,
+            segment_strategy: SegmentStrategy::Seek,
+            buffer_size: DEFAULT_BUFFER_SIZE,
+            max_open_files: DEFAULT_MAX_OPEN_FILES,
+            preallocate: false,
+            force: false,
+            timestamping: true,
+            if_changed: false,
+            only_newer: false,
+            modified_since: None,
+            decompress: false,
+            decompress_gzip: false,
+            max_size: None,
+            max_total_bytes: None,
+            fail_on_empty: false,
+            expected_type: None,
+            verify_sidecar: None,
+            mode: None,
+            user_agent: None,
+            referer: None,
+            origin: None,
+            exec: None,
+            proxy: None,
+            ca_certs: vec![],
+            client_cert: None,
+            accept_invalid_certs: false,
+            ip_family: None,
+            pool_max_idle_per_host: DEFAULT_POOL_MAX_IDLE_PER_HOST,
+            pool_idle_timeout: None,
+            tcp_keepalive: None,
+            max_redirects: DEFAULT_MAX_REDIRECTS,
+            cookies: vec![],
+            cookie_jar: None,
+            session: None,
+            timing: false,
+            client: None,
+            reporter: None,
+        }
+    }
+}
+
+/// Downloads URLs to disk with bounded concurrency, retries and resume.
+///
+/// Cloning is cheap and clones share the same client, concurrency limit and stats.
+#[derive(Clone)]
+pub struct Downloader {
+    client: Client,
+    semaphore: Arc<Semaphore>,
+    max_concurrent: usize,
+    hosts: Option<Arc<HostLimiter>>,
+    priorities: Arc<HashMap<String, i32>>,
+    retries: u32,
+    retry_statuses: Arc<HashSet<u16>>,
+    output_dir: PathBuf,
+    preserve_path: bool,
+    template: Option<Arc<OutputTemplate>>,
+    /// Each URL's place in the batch, from 1, for the template's `{index}`.
+    batch: Arc<StdMutex<HashMap<String, usize>>>,
+    checksums: Arc<HashMap<String, String>>,
+    sizes: Arc<HashMap<String, u64>>,
+    file_names: Arc<HashMap<String, String>>,
+    mirrors: Arc<HashMap<String, Vec<String>>>,
+    rate_limiter: Option<Arc<RateLimiter>>,
+    file_rate: Option<u64>,
+    /// A bucket per progress entry, holding `file_rate`, shared by a
+    /// segmented download's connections.
+    file_limiters: Arc<StdMutex<HashMap<usize, Arc<RateLimiter>>>>,
+    headers: HeaderMap,
+    auth: Option<Auth>,
+    /// Send each URL's origin as its `Referer`.
+    auto_referer: bool,
+    idle_timeout: Option<Duration>,
+    file_timeout: Option<Duration>,
+    min_speed: Option<MinSpeed>,
+    wait: Option<Wait>,
+    /// When the last download started, or is due to, or finished, for `wait`.
+    last_turn: Arc<StdMutex<Option<Instant>>>,
+    connections: usize,
+    segment_strategy: SegmentStrategy,
+    buffer_size: usize,
+    open_files: OpenFiles,
+    preallocate: bool,
+    force: bool,
+    timestamping: bool,
+    if_changed: bool,
+    only_newer: bool,
+    modified_since: Option<SystemTime>,
+    decompress: bool,
+    decompress_gzip: bool,
+    max_size: Option<u64>,
+    max_total_bytes: Option<u64>,
+    fail_on_empty: bool,
+    expected_type: Option<String>,
+    verify_sidecar: Option<bool>,
+    mode: Option<u32>,
+    exec: Option<Arc<Vec<String>>>,
+    max_redirects: usize,
+    cookies: Option<Arc<CookieJar>>,
+    session: Option<Arc<Session>>,
+    timing: bool,
+    /// DNS lookup times, for `timing`.
+    lookups: Lookups,
+    reporter: Option<Arc<dyn ProgressReporter>>,
+    events: Arc<Subscribers>,
+    cancel: Arc<watch::Sender<Cancellation>>,
+    /// `max_total_bytes` was passed and everything cancelled.
+    byte_limit_hit: Arc<AtomicBool>,
+    /// Every path handed out this run, mapped to the URL-derived path of the
+    /// download it belongs to.
+    claimed: Arc<StdMutex<HashMap<PathBuf, Claim>>>,
+    /// The latest Digest challenge from each host, by `host:port`.
+    challenges: Arc<StdMutex<HashMap<String, Challenge>>>,
+    stats: Arc<Mutex<DownloadStats>>,
+}
+
+impl Downloader {
+    pub fn builder() -> DownloaderBuilder {
+        DownloaderBuilder::default()
+    }
+
+    /// A builder for a downloader that sends its requests through the
+    /// existing `client`; see [`DownloaderBuilder::client`].
+    pub fn with_client(client: Client) -> DownloaderBuilder {
+        DownloaderBuilder::default().client(client)
+    }
+
+    /// Shared progress counters, updated as chunks arrive.
+    pub fn stats(&self) -> Arc<Mutex<DownloadStats>> {
+        self.stats.clone()
+    }
+
+    /// Stops every in-flight and queued download on this downloader and its
+    /// clones; they fail with [`DownloadError::Cancelled`].
+    ///
+    /// Single-stream downloads keep what they received in their .part file
+    /// so a later run resumes them. Segmented .part files have holes and
+    /// are deleted.
+    pub fn cancel(&self) {
+        self.cancel.send_modify(|cancellation| cancellation.all = true);
+    }
+
+    /// Whether more than [`DownloaderBuilder::max_total_bytes`] arrived, so
+    /// every download was cancelled.
+    pub fn byte_limit_hit(&self) -> bool {
+        self.byte_limit_hit.load(Ordering::Relaxed)
+    }
+
+    /// Stops the download of `url`, whether in flight or still queued,
+    /// leaving the others running. It fails with
+    /// [`DownloadError::Cancelled`] and its .part file is handled as with
+    /// [`cancel`](Self::cancel). Does nothing if `url` isn't being downloaded.
+    pub fn cancel_download(&self, url: &str) {
+        self.cancel.send_if_modified(|cancellation| {
+            let active = cancellation.active.contains_key(url);
+            if active {
+                cancellation.urls.insert(url.to_string());
+            }
+            active
+        });
+    }
+
+    /// Writes the cookies kept this run to the
+    /// [`cookie_jar`](DownloaderBuilder::cookie_jar) file. Does nothing
+    /// without one.
+    pub fn save_cookies(&self) -> io::Result<()> {
+        match &self.cookies {
+            Some(cookies) => cookies.save(),
+            None => Ok(()),
+        }
+    }
+
+    /// Writes the [`session`](DownloaderBuilder::session) manifest with
+    /// every change so far. Does nothing without one.
+    pub fn save_session(&self) -> io::Result<()> {
+        match &self.session {
+            Some(session) => session.save(),
+            None => Ok(()),
+        }
+    }
+
+    /// Resolves once [`cancel`](Self::cancel) has been called, or
+    /// [`cancel_download`](Self::cancel_download) for `url`.
+    async fn cancelled(&self, url: &str) {
+        let mut cancelled = self.cancel.subscribe();
+        let _ = cancelled.wait_for(|c| c.all || c.urls.contains(url)).await;
+    }
+
+    /// Downloads `url` into the output directory and returns the saved path.
+    ///
+    /// Unless a name was set with [`DownloaderBuilder::file_name`], it comes
+    /// from the `Content-Disposition` header when present, otherwise from
+    /// the URL path. If another download this run already uses that name,
+    /// ` (1)`, ` (2)` and so on go before the extension. The output
+    /// directory is created if missing.
+    ///
+    /// If the file already exists it is left alone and its path returned,
+    /// unless [`DownloaderBuilder::force`] is set or a .part file shows an
+    /// earlier download of it was interrupted. With
+    /// [`DownloaderBuilder::if_changed`] it is only left alone if the server
+    /// says it hasn't changed.
+    pub async fn download(&self, url: &str) -> Result<PathBuf, DownloadError> {
+        self.download_holding(url, None).await
+    }
+
+    /// [`download`](Self::download), with a slot on `url`'s host that
+    /// [`download_many`](Self::download_many) already took.
+    async fn download_holding(&self, url: &str, host_permit: Option<HostPermit>) -> Result<PathBuf, DownloadError> {
+        let _active = ActiveDownload::new(&self.cancel, url);
+        let file_path = self.claim(url, &self.default_path(url), None);
+        if let Some(parent) = file_path.parent() {
+            if let Err(e) = fs::create_dir_all(parent) {
+                let e = DownloadError::from(e);
+                self.report(|r| r.on_error(url, &e));
+                return Err(e);
+            }
+        }
+
+        debug!("{} -> {}", url, file_path.display());
+        let conditional = self.conditional(&file_path);
+        if !self.force && conditional.is_none() && file_path.exists() && !part_path(&file_path).exists() {
+            info!("Skipping {}: {} already exists", url, file_path.display());
+            self.stats.lock().await.records.push(DownloadRecord {
+                url: url.to_string(),
+                bytes: fs::metadata(&file_path).map(|m| m.len()).unwrap_or(0),
+                path: Some(file_path.clone()),
+                elapsed: Duration::ZERO,
+                error: None,
+                skipped: true,
+                current: false,
+                cancelled: false,
+                partial: None,
+            });
+            self.report(|r| r.on_complete(url, &file_path));
+            return Ok(file_path);
+        }
+
+        // Held until the download finishes or fails, queueing the rest
+        let started = Instant::now();
+        let result = tokio::select! {
+            permits = self.acquire(url, host_permit) => match permits {
+                Ok(_permits) => {
+                    self.wait_turn().await;
+                    let result = self.download_with_retries(url, &file_path, conditional.as_ref()).await;
+                    self.end_turn();
+                    result
+                }
+                Err(e) => Err(e),
+            },
+            _ = self.cancelled(url) => Err(DownloadError::Cancelled),
+        };
+        let result = match (result, self.mode) {
+            (Ok(Saved::Downloaded(path)), Some(mode)) => {
+                set_mode(&path, mode).map(|()| Saved::Downloaded(path)).map_err(DownloadError::from)
+            }
+            (result, _) => result,
+        };
+        match &result {
+            Ok(saved) => debug!("Finished {} in {:?}: {}", url, started.elapsed(), saved.path().display()),
+            Err(e) => debug!("Gave up on {} after {:?}: {}", url, started.elapsed(), e),
+        }
+        let unchanged = matches!(result, Ok(Saved::Unchanged(_)));
+        if unchanged {
+            match self.modified_since {
+                Some(since) => info!("Skipping {}: not modified since {}", url, httpdate::fmt_http_date(since)),
+                None if self.if_changed => info!("Skipping {}: {} is unchanged on the server", url, file_path.display()),
+                None => info!("Skipping {}: {} is no older than the server's copy", url, file_path.display()),
+            }
+        }
+        let result = result.map(Saved::into_path);
+        let partial = part_path(&file_path);
+        let record = DownloadRecord {
+            url: url.to_string(),
+            path: result.as_ref().ok().cloned(),
+            bytes: match &result {
+                Ok(path) => fs::metadata(path).map(|m| m.len()).unwrap_or(0),
+                Err(_) => 0,
+            },
+            elapsed: started.elapsed(),
+            error: result.as_ref().err().map(|e| e.to_string()),
+            skipped: unchanged,
+            current: unchanged,
+            cancelled: matches!(result, Err(DownloadError::Cancelled)),
+            partial: Some(partial).filter(|p| result.is_err() && p.exists()),
+        };
+        self.stats.lock().await.records.push(record);
+
+        self.report(|r| match &result {
+            Ok(path) => r.on_complete(url, path),
+            Err(e) => r.on_error(url, e),
+        });
+        if let (Some(command), Ok(path), false) = (&self.exec, &result, unchanged) {
+            exec::run(command, path).await;
+        }
+        result
+    }
+
+    /// A stream of events from every download this downloader and its
+    /// clones run from now on, as an alternative to
+    /// [`DownloaderBuilder::reporter`].
+    ///
+    /// Events queue up until the stream is polled. A stream that falls
+    /// far behind misses `Progress` events, whose next one has the latest
+    /// count, but never the others. It ends once the downloader and all
+    /// its clones are dropped.
+    pub fn events(&self) -> impl Stream<Item = DownloadEvent> {
+        let mut events = self.events.subscribe();
+        futures_util::stream::poll_fn(move |cx| events.poll_recv(cx))
+    }
+
+    /// Passes an event to the reporter and any [`events`](Self::events) streams.
+    fn report(&self, event: impl Fn(&dyn ProgressReporter)) {
+        if let Some(reporter) = &self.reporter {
+            event(reporter.as_ref());
+        }
+        if !self.events.is_empty() {
+            event(self.events.as_ref());
+        }
+        if let Some(session) = &self.session {
+            event(session.as_ref());
+        }
+    }
+
+    /// Downloads every URL concurrently, returning one result per URL in order.
+    ///
+    /// A pool of workers, one per allowed concurrent download, takes URLs
+    /// highest [`priority`](DownloaderBuilder::priority) first. With
+    /// [`DownloaderBuilder::max_per_host`], URLs whose host is busy wait
+    /// while ones from other hosts go ahead of them.
+    pub async fn download_many(&self, urls: &[String]) -> Vec<Result<PathBuf, DownloadError>> {
+        let mut queue: Vec<_> = urls
+            .iter()
+            .enumerate()
+            .map(|(index, url)| (self.priorities.get(url).copied().unwrap_or(0), Reverse(index)))
+            .collect();
+        // Ascending, so the next URL is at the end
+        queue.sort();
+        let queue = Arc::new(StdMutex::new(queue));
+        for url in urls {
+            self.batch_index(url);
+            self.report(|r| r.on_queued(url));
+        }
+        let results = Arc::new(StdMutex::new((0..urls.len()).map(|_| None).collect::<Vec<_>>()));
+        let urls = Arc::new(urls.to_vec());
+
+        let mut workers = vec![];
+        for _ in 0..self.max_concurrent.min(urls.len()) {
+            let downloader = self.clone();
+            let (queue, results, urls) = (queue.clone(), results.clone(), urls.clone());
+            workers.push(task::spawn(async move {
+                while let Some((index, host_permit)) = downloader.next_queued(&queue, &urls).await {
+                    let url = &urls[index];
+                    let result = match AssertUnwindSafe(downloader.download_holding(url, host_permit)).catch_unwind().await {
+                        Ok(result) => result,
+                        Err(payload) => Err(downloader.record_panic(url, payload).await),
+                    };
+                    results.lock().unwrap_or_else(|e| e.into_inner())[index] = Some(result);
+                }
+            }));
+        }
+
+        let mut failure = None;
+        for worker in workers {
+            if let Err(e) = worker.await {
+                failure = Some(e.to_string());
+            }
+        }
+
+        let results = std::mem::take(&mut *results.lock().unwrap_or_else(|e| e.into_inner()));
+        results
+            .into_iter()
+            .map(|result| {
+                // Left unset when a worker panicked mid-download
+                result.unwrap_or_else(|| Err(DownloadError::Other(failure.clone().unwrap_or_default())))
+            })
+            .collect()
+    }
+
+    /// Records a panic that escaped `url`'s download, failing it in the
+    /// stats and reporters as if it had returned the error.
+    async fn record_panic(&self, url: &str, payload: Box<dyn std::any::Any + Send>) -> DownloadError {
+        let error = DownloadError::panicked(url, payload);
+        self.stats.lock().await.records.push(DownloadRecord {
+            url: url.to_string(),
+            path: None,
+            bytes: 0,
+            elapsed: Duration::ZERO,
+            error: Some(error.to_string()),
+            skipped: false,
+            current: false,
+            cancelled: false,
+            partial: None,
+        });
+        self.report(|r| r.on_error(url, &error));
+        error
+    }
+
+    /// Takes the next URL off `queue` whose host has a free slot, waiting for
+    /// one to free up if none does. `None` once the queue is empty.
+    async fn next_queued(&self, queue: &StdMutex<Vec<(i32, Reverse<usize>)>>, urls: &[String]) -> Option<(usize, Option<HostPermit>)> {
+        let Some(hosts) = &self.hosts else {
+            let next = queue.lock().unwrap_or_else(|e| e.into_inner()).pop();
+            return next.map(|(_, Reverse(index))| (index, None));
+        };
+        loop {
+            // Listening before looking, so a slot freed in between isn't missed
+            let released = hosts.released();
+            tokio::pin!(released);
+            released.as_mut().enable();
+            {
+                let mut queue = queue.lock().unwrap_or_else(|e| e.into_inner());
+                if queue.is_empty() {
+                    return None;
+                }
+                for position in (0..queue.len()).rev() {
+                    let Reverse(index) = queue[position].1;
+                    if let Some(permit) = hosts.try_acquire(&urls[index]) {
+                        queue.remove(position);
+                        return Some((index, Some(permit)));
+                    }
+                }
+            }
+            released.await;
+        }
+    }
+
+    /// Sleeps until the [`wait`](DownloaderBuilder::wait) has passed since
+    /// the last download started or finished. Downloads ready at once take
+    /// turns, each due a wait after the one before.
+    async fn wait_turn(&self) {
+        let Some(wait) = self.wait else {
+            return;
+        };
+        let now = Instant::now();
+        let start = {
+            let mut last = self.last_turn.lock().unwrap_or_else(|e| e.into_inner());
+            let delay = if wait.random { jittered(wait.delay) } else { wait.delay };
+            let start = last.map_or(now, |last| (last + delay).max(now));
+            *last = Some(start);
+            start
+        };
+        if start > now {
+            debug!("Waiting {:?} before the next download", start - now);
+            time::sleep_until(start.into()).await;
+        }
+    }
+
+    /// Starts the next [`wait`](DownloaderBuilder::wait) from now, a download
+    /// having finished, unless a later turn is already due.
+    fn end_turn(&self) {
+        if self.wait.is_some() {
+            let mut last = self.last_turn.lock().unwrap_or_else(|e| e.into_inner());
+            let now = Instant::now();
+            *last = Some(last.map_or(now, |last| last.max(now)));
+        }
+    }
+
+    /// Waits for a slot on `url`'s host unless `host_permit` already is one,
+    /// then for one of the overall download slots.
+    async fn acquire(&self, url: &str, host_permit: Option<HostPermit>) -> Result<(Option<HostPermit>, SemaphorePermit<'_>), DownloadError> {
+        let host_permit = match (host_permit, &self.hosts) {
+            (None, Some(hosts)) => Some(hosts.acquire(url).await),
+            (host_permit, _) => host_permit,
+        };
+        let permit = self.semaphore.acquire().await.map_err(|e| DownloadError::Other(e.to_string()))?;
+        Ok((host_permit, permit))
+    }
+
+    /// The .part file an earlier run left for `url` and how many bytes it
+    /// holds, which [`download`](Self::download) resumes from. `None` when
+    /// there isn't one and the download would start fresh.
+    pub fn partial(&self, url: &str) -> Option<(PathBuf, u64)> {
+        let part_path = part_path(&self.default_path(url));
+        let len = fs::metadata(&part_path).ok().filter(|m| m.is_file())?.len();
+        Some((part_path, len))
+    }
+
+    /// Where `url` is saved before any response headers are seen: the name set
+    /// for it, or else the last segment of its path.
+    fn default_path(&self, url: &str) -> PathBuf {
+        if let Some(name) = self.file_names.get(url) {
+            return self.output_dir.join(name);
+        }
+        if let Some(path) = self.session.as_ref().and_then(|session| session.file_path(url)) {
+            return path;
+        }
+        let file_name = filename::from_url(url).unwrap_or_else(|| "downloaded_file".to_string());
+        self.named_path(url, &file_name)
+    }
+
+    /// Where `url` is saved when its file name is `file_name`, going by the
+    /// template or `preserve_path`.
+    fn named_path(&self, url: &str, file_name: &str) -> PathBuf {
+        let file_name = if self.decompress_gzip { decode::strip_gz(file_name) } else { file_name };
+        if let Some(template) = &self.template {
+            return self.output_dir.join(template.expand(url, file_name, self.batch_index(url)));
+        }
+        if self.preserve_path {
+            return self.output_dir.join(filename::dirs_from_url(url)).join(file_name);
+        }
+        self.output_dir.join(file_name)
+    }
+
+    /// `url`'s place in the batch, from 1. URLs [`download_many`](Self::download_many)
+    /// didn't number are numbered after the rest as they are first seen.
+    fn batch_index(&self, url: &str) -> usize {
+        let mut batch = self.batch.lock().unwrap_or_else(|e| e.into_inner());
+        let next = batch.len() + 1;
+        *batch.entry(url.to_string()).or_insert(next)
+    }
+
+    /// Reserves `file_path` for a download for the rest of the run. If
+    /// another download already has it, takes the first `name (n).ext` that
+    /// is neither claimed nor on disk instead, and says so.
+    ///
+    /// `owner` is the download's own URL-derived path when claiming a second
+    /// name for it, so a retry gets back the name it had. Downloading a URL
+    /// again once its earlier download has returned reuses its names too.
+    fn claim(&self, url: &str, file_path: &Path, owner: Option<&Path>) -> PathBuf {
+        let mut claimed = self.claimed.lock().unwrap_or_else(|e| e.into_inner());
+        match claimed.get(file_path) {
+            None => {
+                let owner = owner.unwrap_or(file_path).to_path_buf();
+                claimed.insert(file_path.to_path_buf(), Claim { owner, url: url.to_string() });
+                return file_path.to_path_buf();
+            }
+            Some(claim) if Some(claim.owner.as_path()) == owner => return file_path.to_path_buf(),
+            Some(claim) if claim.url == url && owner.is_none() && self.in_flight(url) == 1 => {
+                return file_path.to_path_buf();
+            }
+            Some(_) => {}
+        }
+
+        let mut n = 1;
+        let renamed = loop {
+            let candidate = filename::numbered(file_path, n);
+            let taken = claimed.contains_key(&candidate) || candidate.exists() || part_path(&candidate).exists();
+            if !taken {
+                break candidate;
+            }
+            n += 1;
+        };
+        let owner = owner.unwrap_or(&renamed).to_path_buf();
+        claimed.insert(renamed.clone(), Claim { owner, url: url.to_string() });
+
+        info!(
+            "Saving {} as {}: {} is used by another download",
+            url,
+            renamed.display(),
+            file_path.display()
+        );
+        renamed
+    }
+
+    /// How many downloads of `url` are running or queued, this one included.
+    fn in_flight(&self, url: &str) -> usize {
+        self.cancel.borrow().active.get(url).copied().unwrap_or(0)
+    }
+
+    /// The validators for a conditional request for `file_path`, if one
+    /// should be sent: the date from [`DownloaderBuilder::if_modified_since`],
+    /// or for an existing file its sidecar's with
+    /// [`DownloaderBuilder::if_changed`], otherwise its modification time
+    /// with [`DownloaderBuilder::only_newer`].
+    fn conditional(&self, file_path: &Path) -> Option<Validators> {
+        if part_path(file_path).exists() {
+            return None;
+        }
+        if let Some(since) = self.modified_since {
+            return Some(Validators { etag: None, last_modified: Some(httpdate::fmt_http_date(since)) });
+        }
+        if !(self.if_changed || self.only_newer) || self.force || !file_path.exists() {
+            return None;
+        }
+        let sidecar = if self.if_changed { Validators::load(file_path) } else { None };
+        sidecar.or_else(|| {
+            let modified = fs::metadata(file_path).and_then(|m| m.modified()).ok().filter(|_| self.only_newer)?;
+            Some(Validators { etag: None, last_modified: Some(httpdate::fmt_http_date(modified)) })
+        })
+    }
+
+    /// Whether a successful response for an existing file checked with
+    /// [`DownloaderBuilder::only_newer`] is for a copy no newer than it, the
+    /// server having ignored `If-Modified-Since`.
+    fn up_to_date(&self, file_path: &Path, conditional: Option<&Validators>, headers: &HeaderMap) -> bool {
+        if !self.only_newer || conditional.is_none() {
+            return false;
+        }
+        let (Ok(metadata), Some(remote)) = (fs::metadata(file_path), last_modified(headers)) else {
+            return false;
+        };
+        let Ok(local) = metadata.modified() else {
+            return false;
+        };
+        // A decompressed file's size says nothing about the body's
+        let same_size = self.decoding(headers)
+            || self.decompress_gzip
+            || content_length(headers).is_none_or(|len| len == metadata.len());
+        remote <= local && same_size
+    }
+
+    /// Tries `url` and then each of its mirrors, with retries for each, until
+    /// one succeeds. Fails with the last mirror's error.
+    async fn download_with_retries(&self, url: &str, file_path: &Path, conditional: Option<&Validators>) -> Result<Saved, DownloadError> {
+        let mirrors = self.mirrors.get(url).map(Vec::as_slice).unwrap_or_default();
+        // Shared across mirrors so a resume is only attempted against the same size
+        let mut known_size = None;
+
+        let mut result = self.download_from(url, url, file_path, conditional, &mut known_size).await;
+        for mirror in mirrors {
+            match &result {
+                Err(DownloadError::Cancelled) | Ok(_) => break,
+                Err(e) => info!("Trying mirror {} for {}: {}", mirror, url, e),
+            }
+            result = self.download_from(url, mirror, file_path, conditional, &mut known_size).await;
+            if result.is_ok() {
+                info!("Downloaded {} from mirror {}", url, mirror);
+            }
+        }
+        result
+    }
+
+    /// Downloads `url`'s file from `source`, retrying transient failures.
+    async fn download_from(&self, url: &str, source: &str, file_path: &Path, conditional: Option<&Validators>, known_size: &mut Option<u64>) -> Result<Saved, DownloadError> {
+        let mut attempt = 0;
+        loop {
+            match self.download_file(url, source, file_path, conditional, known_size).await {
+                Err(e) if attempt < self.retries && self.should_retry(&e) => {
+                    attempt += 1;
+                    let delay = retry_delay(&e, attempt);
+                    info!("Retrying {} in {:?} (attempt {}/{}): {}", source, delay, attempt, self.retries, e);
+                    debug!("{} failed with {:?}", source, e);
+                    tokio::select! {
+                        _ = time::sleep(delay) => {}
+                        _ = self.cancelled(url) => return Err(DownloadError::Cancelled),
+                    }
+                }
+                result => return result,
+            }
+        }
+    }
+
+    async fn download_file(&self, url: &str, source: &str, file_path: &Path, conditional: Option<&Validators>, known_size: &mut Option<u64>) -> Result<Saved, DownloadError> {
+        let mut contribution = Contribution::default();
+        let attempt = self.try_download(url, source, file_path, conditional, known_size, &mut contribution);
+        let attempt = async {
+            let Some(limit) = self.file_timeout else {
+                return attempt.await;
+            };
+            time::timeout(limit, attempt)
+                .await
+                .unwrap_or_else(|_| Err(DownloadError::Timeout(format!("{} took longer than {:?}", source, limit))))
+        };
+        // A panic fails this URL like any other error, so the cleanup below still runs
+        let attempt = AssertUnwindSafe(attempt)
+            .catch_unwind()
+            .map(|result| result.unwrap_or_else(|payload| Err(DownloadError::panicked(url, payload))));
+        // Dropping the attempt on cancel or timeout closes its file; the cleanup below still runs
+        let result = tokio::select! {
+            result = attempt => result,
+            _ = self.cancelled(url) => Err(DownloadError::Cancelled),
+        };
+        let mut stats = self.stats.lock().await;
+        if let Some(index) = contribution.file {
+            stats.files[index].finished = true;
+            self.file_limiters.lock().unwrap_or_else(|e| e.into_inner()).remove(&index);
+        }
+        if let Err(e) = &result {
+            stats.total_size -= contribution.size;
+            stats.total_bytes -= contribution.bytes;
+            if contribution.segmented || matches!(e, DownloadError::TooLarge { .. }) {
+                // A file with holes can't be resumed by appending, so start over next time
+                let _ = fs::remove_file(part_path(file_path));
+                let _ = sidecar::remove(&part_path(file_path));
+                for index in 0..contribution.segment_files {
+                    let _ = fs::remove_file(segmented::segment_path(&part_path(file_path), index));
+                }
+            } else if contribution.preallocated {
+                // Drop the zeroed tail, and any range whose bytes were still
+                // buffered, so the .part length is what was written
+                if let Ok(file) = OpenOptions::new().write(true).open(part_path(file_path)) {
+                    let _ = file.set_len(contribution.written);
+                }
+            }
+        }
+        drop(stats);
+        result
+    }
+
+    /// One attempt at fetching `url`'s file from `source`, which is `url`
+    /// itself or one of its mirrors.
+    async fn try_download(&self, url: &str, source: &str, file_path: &Path, conditional: Option<&Validators>, known_size: &mut Option<u64>, contribution: &mut Contribution) -> Result<Saved, DownloadError> {
+        let part_path = part_path(file_path);
+        let mut existing_len = fs::metadata(&part_path).map(|m| m.len()).unwrap_or(0);
+        let expected_sha256 = self.checksums.get(url);
+
+        if self.connections > 1 && existing_len == 0 {
+            if let Some(saved) = self.try_segmented(url, source, file_path, conditional, contribution).await? {
+                return Ok(saved);
+            }
+        }
+
+        // What the .part file's first response was served with
+        let started_with = Validators::load(&part_path)
+            .or_else(|| self.session.as_ref().and_then(|session| session.validators(url)))
+            .filter(|_| existing_len > 0);
+        let mut sent;
+        let response = loop {
+            let mut request = self.request(source);
+            if existing_len > 0 {
+                request = request.header(RANGE, format!("bytes={}-", existing_len));
+                if let Some(validators) = &started_with {
+                    request = validators.apply_if_range(request);
+                }
+            }
+            if let Some(validators) = conditional {
+                request = validators.apply(request);
+            }
+
+            debug!("GET {} from byte {}", source, existing_len);
+            sent = Instant::now();
+            let response = self.send(request).await?;
+            log_response(source, &response, sent);
+            if existing_len == 0 {
+                break response;
+            }
+
+            let resuming = response.status() == StatusCode::PARTIAL_CONTENT;
+            if resuming && range_start(response.headers()) != Some(existing_len) {
+                // Appending a body that starts anywhere else would corrupt the file
+                warn!(
+                    "{} didn't resume {} from byte {}; restarting from zero",
+                    source, url, existing_len
+                );
+                fs::remove_file(&part_path)?;
+                existing_len = 0;
+                continue;
+            }
+            if resuming {
+                debug!("Resuming {} from byte {}", url, existing_len);
+            } else if response.status() == StatusCode::OK && started_with.is_some() {
+                warn!(
+                    "{} changed since {} was started, or no longer serves byte ranges, so the {} bytes in it are discarded",
+                    source,
+                    part_path.display(),
+                    existing_len
+                );
+            } else if response.status() == StatusCode::OK {
+                warn!(
+                    "{} no longer serves byte ranges, so it starts over and the {} bytes in {} are discarded",
+                    source,
+                    existing_len,
+                    part_path.display()
+                );
+            }
+
+            let size = response.content_length().map(|len| len + existing_len);
+            if let (true, Some(known), Some(size)) = (resuming, *known_size, size) {
+                if known != size {
+                    // Likely another mirror's copy; its bytes can't be appended to this one's
+                    warn!(
+                        "{} is {} bytes, not {}; restarting {} from zero",
+                        source, size, known, url
+                    );
+                    fs::remove_file(&part_path)?;
+                    existing_len = 0;
+                    continue;
+                }
+            }
+            break response;
+        };
+        let status = response.status();
+        if status == StatusCode::NOT_MODIFIED && conditional.is_some() {
+            return Ok(Saved::Unchanged(file_path.to_path_buf()));
+        }
+        if status == StatusCode::OK && self.up_to_date(file_path, conditional, response.headers()) {
+            return Ok(Saved::Unchanged(file_path.to_path_buf()));
+        }
+
+        // The partial file already holds the whole body
+        if status == StatusCode::RANGE_NOT_SATISFIABLE && existing_len > 0 {
+            self.check_size(source, Some(existing_len), existing_len)?;
+            if self.decompress {
+                // A 416 doesn't say how the body it refers to was encoded
+                let head = self.send(self.request_with(Method::HEAD, source)).await?;
+                self.decode_part(&part_path, head.headers()).await?;
+            }
+            if let Some(expected) = expected_sha256 {
+                let hasher = checksum::hash_part(Sha256::new(), &part_path).await?;
+                checksum::verify(hasher, expected, url, &part_path)?;
+            }
+            self.check_part_size(url, source, &part_path)?;
+            self.verify_part(url, &part_path, file_path).await?;
+            self.gunzip_part(&part_path).await?;
+            move_into_place(&part_path, file_path).await?;
+            return Ok(Saved::Downloaded(file_path.to_path_buf()));
+        }
+
+        if !status.is_success() {
+            return Err(status_error(&response, source));
+        }
+        self.check_content_type(source, response.headers())?;
+
+        let file_path = self.resolve_file_path(url, response.headers(), redirected(source, response.url()), file_path);
+        let modified = last_modified(response.headers());
+        let validators = Validators::from_headers(response.headers());
+        if let Some(session) = &self.session {
+            session.set_validators(url, &validators);
+        }
+        let headers = response.headers().clone();
+
+        // A 200 means the server ignored the range, so start over from zero
+        let resumed = status == StatusCode::PARTIAL_CONTENT;
+        let offset = if resumed { existing_len } else { 0 };
+        let content_length = response.content_length();
+        let total_size = content_length.map(|len| len + offset).unwrap_or(0);
+        if content_length.is_some() {
+            // Compressed bodies are checked once decoded
+            if !self.decoding(&headers) {
+                self.check_expected_size(url, source, total_size)?;
+            }
+            *known_size = Some(total_size);
+            self.check_size(source, Some(total_size), total_size)?;
+        }
+        let file_index = self.register(url, &file_path, offset, total_size, contribution).await;
+
+        // A compressed body is hashed once decoded, below
+        let mut hasher = expected_sha256.filter(|_| !self.decoding(&headers)).map(|_| Sha256::new());
+        if resumed {
+            if let Some(fresh) = hasher.take() {
+                hasher = Some(checksum::hash_part(fresh, &part_path).await?);
+            }
+        }
+
+        let file = if resumed {
+            self.open_files.open(&part_path, OpenOptions::new().write(true)).await?
+        } else {
+            let file = self.open_files.open(&part_path, File::options().write(true).create(true).truncate(true)).await?;
+            validators.save(&part_path)?;
+            file
+        };
+        if self.preallocate && total_size > 0 {
+            preallocate(&file.file, &part_path, total_size, offset)?;
+            contribution.preallocated = true;
+            contribution.written = offset;
+        }
+        let mut file = PartWriter::new(file, &part_path, offset, self.buffer_size, &self.open_files);
+
+        let mut timing = Some(Timing::new(&self.lookups, &response, sent));
+        let mut stream = response.bytes_stream();
+        let mut slow_since = None;
+        while let Some(chunk) = self.next_chunk(&mut stream, url, file_index, &mut slow_since).await? {
+            if let Some(timing) = timing.take() {
+                self.log_timing(source, timing, true);
+            }
+            self.check_size(source, None, offset + contribution.bytes + chunk.len() as u64)?;
+            file.write_all(&chunk).await?;
+            contribution.written = file.position();
+            if let Some(hasher) = hasher.as_mut() {
+                hasher.update(&chunk);
+            }
+            self.record_chunk(url, file_index, chunk.len() as u64).await;
+            contribution.bytes += chunk.len() as u64;
+        }
+
+        if let Some(timing) = timing {
+            self.log_timing(source, timing, false);
+        }
+        file.finish().await?;
+        contribution.written = contribution.bytes;
+        // Chunked responses have no length to hold them to
+        if content_length.is_some() && contribution.bytes != total_size {
+            return Err(DownloadError::SizeMismatch {
+                url: source.to_string(),
+                expected: total_size,
+                actual: contribution.bytes,
+            });
+        }
+        if self.decoding(&headers) {
+            self.decode_part(&part_path, &headers).await?;
+            if expected_sha256.is_some() {
+                hasher = Some(checksum::hash_part(Sha256::new(), &part_path).await?);
+            }
+        }
+        self.check_empty(source, &part_path, content_length)?;
+        if let (Some(hasher), Some(expected)) = (hasher, expected_sha256) {
+            checksum::verify(hasher, expected, url, &part_path)?;
+        }
+        self.check_part_size(url, source, &part_path)?;
+        self.verify_part(url, &part_path, &file_path).await?;
+        self.gunzip_part(&part_path).await?;
+        move_into_place(&part_path, &file_path).await?;
+        self.set_modified(&file_path, modified);
+        self.save_validators(&file_path, &validators)?;
+
+        Ok(Saved::Downloaded(file_path))
+    }
+
+    /// A GET for `url` carrying the configured headers and credentials.
+    fn request(&self, url: &str) -> RequestBuilder {
+        self.request_with(Method::GET, url)
+    }
+
+    fn request_with(&self, method: Method, url: &str) -> RequestBuilder {
+        let mut request = self.client.request(method, url).headers(self.headers.clone());
+        if self.decompress && !self.headers.contains_key(ACCEPT_ENCODING) {
+            request = request.header(ACCEPT_ENCODING, "gzip");
+        }
+        if self.auto_referer && !self.headers.contains_key(REFERER) {
+            if let Some(origin) = Url::parse(url).ok().map(|url| url.origin()).filter(|origin| origin.is_tuple()) {
+                request = request.header(REFERER, format!("{}/", origin.ascii_serialization()));
+            }
+        }
+        match &self.auth {
+            Some(Auth::Basic { user, password }) => request.basic_auth(user, password.as_ref()),
+            Some(Auth::Bearer(token)) => request.bearer_auth(token),
+            Some(Auth::Digest { .. }) | None => request,
+        }
+    }
+
+    /// Sends `request`. With Digest credentials it answers the host's last
+    /// challenge up front, and resends once if a 401 brings a new one.
+    async fn send(&self, request: RequestBuilder) -> Result<Response, reqwest::Error> {
+        let Some(cookies) = &self.cookies else {
+            return self.send_authorized(request).await;
+        };
+
+        let mut request = request.build()?;
+        let mut redirects = 0;
+        loop {
+            // Taken before the cookies go in, since the next hop gets its own
+            let mut next = request.try_clone();
+            cookies.add_to(&mut request);
+            let response = self.send_authorized(RequestBuilder::from_parts(self.client.clone(), request)).await?;
+            cookies.store(response.url(), response.headers());
+
+            let location = response.headers().get(LOCATION).and_then(|v| v.to_str().ok());
+            let target = location.and_then(|location| response.url().join(location).ok());
+            let (true, Some(target), Some(next)) = (response.status().is_redirection(), target, next.as_mut()) else {
+                return Ok(response);
+            };
+            if redirects >= self.max_redirects {
+                return Ok(response);
+            }
+            redirects += 1;
+            debug!("{} redirects to {} ({} of at most {})", response.url(), target, redirects, self.max_redirects);
+            // Credentials stay with the origin they were meant for
+            if target.origin() != response.url().origin() {
+                next.headers_mut().remove(AUTHORIZATION);
+            }
+            *next.url_mut() = target;
+            request = next.try_clone().expect("requests without a body can be cloned");
+        }
+    }
+
+    /// Sends `request`, answering a Digest challenge if that's configured.
+    async fn send_authorized(&self, request: RequestBuilder) -> Result<Response, reqwest::Error> {
+        let Some(Auth::Digest { user, password }) = &self.auth else {
+            return request.send().await;
+        };
+
+        let mut request = request.build()?;
+        let host = format!(
+            "{}:{}",
+            request.url().host_str().unwrap_or_default(),
+            request.url().port_or_known_default().unwrap_or_default()
+        );
+        self.authorize(&mut request, &host, user, password);
+        let retry = request.try_clone();
+        let response = self.client.execute(request).await?;
+        if response.status() != StatusCode::UNAUTHORIZED {
+            return Ok(response);
+        }
+
+        let values = response.headers().get_all(WWW_AUTHENTICATE).iter().filter_map(|v| v.to_str().ok());
+        let (Some(challenge), Some(mut retry)) = (Challenge::from_headers(values), retry) else {
+            return Ok(response);
+        };
+        debug!("Answering the Digest challenge from {}", host);
+        self.challenges.lock().unwrap_or_else(|e| e.into_inner()).insert(host.clone(), challenge);
+        self.authorize(&mut retry, &host, user, password);
+        self.client.execute(retry).await
+    }
+
+    /// Adds the answer to `host`'s Digest challenge, if it has sent one.
+    fn authorize(&self, request: &mut Request, host: &str, user: &str, password: &str) {
+        let mut challenges = self.challenges.lock().unwrap_or_else(|e| e.into_inner());
+        let Some(challenge) = challenges.get_mut(host) else {
+            return;
+        };
+        let url = request.url();
+        let uri = match url.query() {
+            Some(query) => format!("{}?{}", url.path(), query),
+            None => url.path().to_string(),
+        };
+        let header = challenge.authorization(user, password, request.method().as_str(), &uri);
+        if let Ok(value) = HeaderValue::from_str(&header) {
+            request.headers_mut().insert(AUTHORIZATION, value);
+        }
+    }
+
+    /// The .part name stays URL-derived so a later run can find it before
+    /// seeing any headers; only the final name follows Content-Disposition,
+    /// or failing that the URL `source` was `redirected` to.
+    fn resolve_file_path(&self, url: &str, headers: &HeaderMap, redirected: Option<&Url>, file_path: &Path) -> PathBuf {
+        if self.file_names.contains_key(url) {
+            return file_path.to_path_buf();
+        }
+        headers
+            .get(CONTENT_DISPOSITION)
+            .and_then(|v| v.to_str().ok())
+            .and_then(filename::from_content_disposition)
+            .or_else(|| redirected.and_then(|to| filename::from_url(to.as_str())))
+            .map(|name| self.named_path(url, &name))
+            .filter(|path| path != file_path)
+            .map(|path| self.claim(url, &path, Some(file_path)))
+            .unwrap_or_else(|| file_path.to_path_buf())
+    }
+
+    /// Reports `timing` for `source`, with the first body byte having just
+    /// arrived if `first_byte`.
+    fn log_timing(&self, source: &str, timing: Timing, first_byte: bool) {
+        let ms = |d: Duration| format!("{:.1}ms", d.as_secs_f64() * 1000.0);
+        let message = format!(
+            "{}: DNS {}, headers after {}, first byte after {}, from {}",
+            source,
+            timing.dns.map_or_else(|| "-".to_string(), ms),
+            ms(timing.headers),
+            if first_byte { ms(timing.sent.elapsed()) } else { "-".to_string() },
+            timing.remote.map_or_else(|| "-".to_string(), |addr| addr.to_string()),
+        );
+        if self.timing {
+            info!("{}", message);
+        } else {
+            debug!("{}", message);
+        }
+    }
+
+    /// Whether `error` is worth another attempt, going by the configured
+    /// statuses for HTTP errors.
+    fn should_retry(&self, error: &DownloadError) -> bool {
+        match error {
+            DownloadError::HttpStatus(status, _) | DownloadError::Throttled { status, .. } => {
+                self.retry_statuses.contains(&status.as_u16())
+            }
+            error => error.is_retryable(),
+        }
+    }
+
+    /// Fails with [`DownloadError::TooLarge`] once `bytes` passes the size
+    /// limit; `advertised` is the length the server announced, if that's
+    /// what is being checked.
+    fn check_size(&self, source: &str, advertised: Option<u64>, bytes: u64) -> Result<(), DownloadError> {
+        match self.max_size {
+            Some(limit) if bytes > limit => Err(DownloadError::TooLarge {
+                url: source.to_string(),
+                size: advertised,
+                limit,
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    /// Fails with [`DownloadError::UnexpectedSize`] if `url` is expected to
+    /// be some other size than `size`.
+    fn check_expected_size(&self, url: &str, source: &str, size: u64) -> Result<(), DownloadError> {
+        match self.sizes.get(url) {
+            Some(&expected) if expected != size => Err(DownloadError::UnexpectedSize {
+                url: source.to_string(),
+                expected,
+                actual: size,
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    /// Checks the finished `part_path` against `url`'s expected size,
+    /// deleting it on a mismatch so a mirror doesn't resume from it.
+    fn check_part_size(&self, url: &str, source: &str, part_path: &Path) -> Result<(), DownloadError> {
+        if !self.sizes.contains_key(url) {
+            return Ok(());
+        }
+        let result = self.check_expected_size(url, source, fs::metadata(part_path)?.len());
+        if result.is_err() {
+            fs::remove_file(part_path)?;
+            sidecar::remove(part_path)?;
+        }
+        result
+    }
+
+    /// Warns when the saved body is empty, or with
+    /// [`DownloaderBuilder::fail_on_empty`] deletes it and fails. Some URLs
+    /// are empty on purpose, but an empty body without a `Content-Length`
+    /// can also be a server failing without saying so.
+    fn check_empty(&self, source: &str, part_path: &Path, content_length: Option<u64>) -> Result<(), DownloadError> {
+        if fs::metadata(part_path)?.len() > 0 {
+            return Ok(());
+        }
+        if self.fail_on_empty {
+            fs::remove_file(part_path)?;
+            sidecar::remove(part_path)?;
+            return Err(DownloadError::Empty { url: source.to_string() });
+        }
+        match content_length {
+            Some(_) => warn!("{} is empty, as its Content-Length said", source),
+            None => warn!("{} sent an empty body and no Content-Length, which can mean the server failed", source),
+        }
+        Ok(())
+    }
+
+    /// Fails unless the `Content-Type` in `headers` is one
+    /// [`DownloaderBuilder::expect_content_type`] allows.
+    fn check_content_type(&self, source: &str, headers: &HeaderMap) -> Result<(), DownloadError> {
+        let Some(expected) = &self.expected_type else {
+            return Ok(());
+        };
+        let content_type = headers.get(CONTENT_TYPE).and_then(|v| v.to_str().ok());
+        let matches = content_type.is_some_and(|value| {
+            let media_type = value.split(';').next().unwrap_or_default().trim();
+            expected.split(',').any(|pattern| media_type_matches(pattern.trim(), media_type))
+        });
+        if matches {
+            return Ok(());
+        }
+        Err(DownloadError::UnexpectedContentType {
+            url: source.to_string(),
+            content_type: content_type.map(str::to_string),
+            expected: expected.clone(),
+        })
+    }
+
+    /// Whether a response with these headers is saved decompressed.
+    fn decoding(&self, headers: &HeaderMap) -> bool {
+        self.decompress && decode::is_gzip(headers)
+    }
+
+    /// Decompresses a complete .part file in place if its response was
+    /// gzip-encoded. A body that won't decode is deleted, so the next
+    /// attempt fetches it afresh.
+    async fn decode_part(&self, part_path: &Path, headers: &HeaderMap) -> Result<(), DownloadError> {
+        if !self.decoding(headers) {
+            return Ok(());
+        }
+        gunzip(part_path).await
+    }
+
+    /// Decompresses a complete .part file in place if it holds gzip data and
+    /// [`DownloaderBuilder::decompress_gzip`] is on, deleting it if it won't
+    /// decompress.
+    async fn gunzip_part(&self, part_path: &Path) -> Result<(), DownloadError> {
+        if !self.decompress_gzip || !decode::starts_gzip(part_path)? {
+            return Ok(());
+        }
+        gunzip(part_path).await
+    }
+
+    /// Applies the server's modification time to a saved file if timestamping is on.
+    fn set_modified(&self, file_path: &Path, modified: Option<SystemTime>) {
+        if let (true, Some(modified)) = (self.timestamping, modified) {
+            // The file is already saved; a wrong mtime isn't worth failing over
+            let _ = filetime::set_file_mtime(file_path, FileTime::from_system_time(modified));
+        }
+    }
+
+    /// Records what the file was served with for the next conditional request.
+    fn save_validators(&self, file_path: &Path, validators: &Validators) -> io::Result<()> {
+        if self.if_changed {
+            validators.save(file_path)?;
+        }
+        Ok(())
+    }
+
+    /// Adds a progress entry for this attempt and counts its size and any
+    /// resumed bytes towards the totals.
+    async fn register(&self, url: &str, file_path: &Path, offset: u64, total_size: u64, contribution: &mut Contribution) -> usize {
+        let index = {
+            let mut stats = self.stats.lock().await;
+            stats.total_size += total_size;
+            stats.total_bytes += offset;
+            let name = file_path.file_name().unwrap_or_default().to_string_lossy();
+            stats.files.push(FileProgress::new(url, &name, offset, total_size));
+            stats.files.len() - 1
+        };
+
+        contribution.size = total_size;
+        contribution.bytes = offset;
+        contribution.file = Some(index);
+
+        self.report(|r| r.on_start(url, file_path, offset, Some(total_size).filter(|&size| size > 0)));
+        index
+    }
+
+    /// Pulls the next chunk off a body stream, failing if the idle timeout
+    /// elapses first or the download stays below the minimum speed.
+    ///
+    /// `slow_since` carries when the speed last dropped under the minimum
+    /// across calls for the same stream.
+    async fn next_chunk<S, T>(&self, stream: &mut S, url: &str, file_index: usize, slow_since: &mut Option<Instant>) -> Result<Option<T>, DownloadError>
+    where
+        S: Stream<Item = reqwest::Result<T>> + Unpin,
+    {
+        let idle = async {
+            match self.idle_timeout {
+                Some(idle) => time::sleep(idle).await,
+                None => std::future::pending().await,
+            }
+        };
+        tokio::pin!(idle);
+        self.check_speed(url, file_index, slow_since).await?;
+        let start = time::Instant::now() + SPEED_CHECK_INTERVAL;
+        let mut speed_checks = time::interval_at(start, SPEED_CHECK_INTERVAL);
+        let mut next = stream.next();
+
+        loop {
+            tokio::select! {
+                item = &mut next => return Ok(item.transpose()?),
+                _ = &mut idle => {
+                    return Err(DownloadError::Timeout(format!(
+                        "no data received from {} for {:?}",
+                        url,
+                        self.idle_timeout.unwrap_or_default()
+                    )));
+                }
+                _ = speed_checks.tick(), if self.min_speed.is_some() => {
+                    self.check_speed(url, file_index, slow_since).await?;
+                }
+            }
+        }
+    }
+
+    /// Fails once the download has been under the minimum speed for the
+    /// whole grace period.
+    async fn check_speed(&self, url: &str, file_index: usize, slow_since: &mut Option<Instant>) -> Result<(), DownloadError> {
+        let Some(MinSpeed { bytes_per_sec, grace }) = self.min_speed else {
+            return Ok(());
+        };
+
+        let (started, speed) = {
+            let stats = self.stats.lock().await;
+            let file = &stats.files[file_index];
+            (file.bytes > file.resumed_from, file.current_speed())
+        };
+        // Connecting and waiting for the first byte is the idle timeout's job
+        if !started || speed >= bytes_per_sec as f64 {
+            *slow_since = None;
+            return Ok(());
+        }
+
+        let since = *slow_since.get_or_insert_with(Instant::now);
+        if since.elapsed() >= grace {
+            return Err(DownloadError::Timeout(format!(
+                "{} stayed below {} bytes/s for {:?}",
+                url, bytes_per_sec, grace
+            )));
+        }
+        Ok(())
+    }
+
+    /// Counts `len` bytes as downloaded, then waits out the rate limits for
+    /// them. Counting first keeps bytes already written from going uncounted
+    /// when a cancel ends the wait.
+    async fn record_chunk(&self, url: &str, file_index: usize, len: u64) {
+        let (downloaded, received) = {
+            let mut stats = self.stats.lock().await;
+            stats.total_bytes += len;
+            stats.received += len;
+            let file = &mut stats.files[file_index];
+            file.bytes += len;
+            file.window.record(file.bytes);
+            (file.bytes, stats.received)
+        };
+
+        self.report(|r| r.on_progress(url, downloaded));
+        if let Some(limit) = self.max_total_bytes {
+            if received > limit && !self.byte_limit_hit.swap(true, Ordering::Relaxed) {
+                warn!("Received {} bytes, over the {}-byte limit; cancelling downloads", received, limit);
+                self.cancel();
+            }
+        }
+
+        if let Some(rate) = self.file_rate {
+            let limiter = self
+                .file_limiters
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .entry(file_index)
+                .or_insert_with(|| Arc::new(RateLimiter::new(rate)))
+                .clone();
+            limiter.acquire(len).await;
+        }
+        if let Some(limiter) = &self.rate_limiter {
+            limiter.acquire(len).await;
+        }
+    }
+}
+
+const DEFAULT_USER_AGENT: &str = concat!("rs-downloader/", env!("CARGO_PKG_VERSION"));
+
+const DEFAULT_MAX_REDIRECTS: usize = 10;
+/// The longest a `Retry-After` is waited out, so a server can't stall a
+/// retry indefinitely.
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(300);
+const DEFAULT_POOL_MAX_IDLE_PER_HOST: usize = 10;
+const DEFAULT_BUFFER_SIZE: usize = 64 * 1024;
+/// Well below the usual 1024 descriptors a process gets, leaving room for
+/// sockets.
+const DEFAULT_MAX_OPEN_FILES: usize = 256;
+
+/// How often a download's speed is compared against [`MinSpeed`].
+const SPEED_CHECK_INTERVAL: Duration = Duration::from_millis(500);
+
+#[derive(Clone, Copy)]
+struct MinSpeed {
+    bytes_per_sec: u64,
+    grace: Duration,
+}
+
+#[derive(Clone, Copy)]
+struct Wait {
+    delay: Duration,
+    random: bool,
+}
+
+/// A successful download attempt.
+enum Saved {
+    Downloaded(PathBuf),
+    /// The server answered a conditional request with 304 Not Modified.
+    Unchanged(PathBuf),
+}
+
+impl Saved {
+    fn path(&self) -> &Path {
+        match self {
+            Saved::Downloaded(path) | Saved::Unchanged(path) => path,
+        }
+    }
+
+    fn into_path(self) -> PathBuf {
+        match self {
+            Saved::Downloaded(path) | Saved::Unchanged(path) => path,
+        }
+    }
+}
+
+/// Bytes a single attempt has added to the shared stats, so they can be
+/// taken back out if the attempt fails and is retried.
+#[derive(Default)]
+struct Contribution {
+    size: u64,
+    bytes: u64,
+    file: Option<usize>,
+    /// The .part file was extended to its full size ahead of the data.
+    preallocated: bool,
+    /// Where the data written to a preallocated .part file ends, which
+    /// trails `bytes` by whatever is still buffered.
+    written: u64,
+    /// The .part file is being written by parallel segments.
+    segmented: bool,
+    /// How many of those segments have files of their own, deleted along
+    /// with the .part file.
+    segment_files: u64,
+}
+
+/// A path reserved by [`Downloader::claim`].
+struct Claim {
+    /// The URL-derived path of the download it belongs to.
+    owner: PathBuf,
+    url: String,
+}
+
+/// Cancellations requested so far, watched by every download.
+#[derive(Default)]
+struct Cancellation {
+    all: bool,
+    /// URLs passed to `cancel_download`, until their downloads return.
+    urls: HashSet<String>,
+    /// How many downloads of each URL are running or queued.
+    active: HashMap<String, usize>,
+}
+
+/// Counts a download as active until dropped, then forgets any
+/// cancellation of its URL once no other download of it remains.
+struct ActiveDownload {
+    cancel: Arc<watch::Sender<Cancellation>>,
+    url: String,
+}
+
+impl ActiveDownload {
+    fn new(cancel: &Arc<watch::Sender<Cancellation>>, url: &str) -> Self {
+        cancel.send_modify(|c| *c.active.entry(url.to_string()).or_default() += 1);
+        ActiveDownload { cancel: cancel.clone(), url: url.to_string() }
+    }
+}
+
+impl Drop for ActiveDownload {
+    fn drop(&mut self) {
+        self.cancel.send_if_modified(|c| {
+            let count = c.active.entry(self.url.clone()).or_default();
+            *count -= 1;
+            if *count == 0 {
+                c.active.remove(&self.url);
+                c.urls.remove(&self.url);
+            }
+            false
+        });
+    }
+}
+
+/// Follows up to `max` redirects, logging each hop; with 0 the 3xx response
+/// itself comes back.
+fn redirect_policy(max: usize) -> Policy {
+    if max == 0 {
+        return Policy::none();
+    }
+    Policy::custom(move |attempt| {
+        let hops = attempt.previous().len();
+        if hops > max {
+            return attempt.error(format!("more than {} redirects", max));
+        }
+        if let Some(from) = attempt.previous().last() {
+            debug!("{} redirects to {} ({} of at most {})", from, attempt.url(), hops, max);
+        }
+        attempt.follow()
+    })
+}
+
+/// `final_url` if following redirects from `source` ended up somewhere else.
+fn redirected<'a>(source: &str, final_url: &'a Url) -> Option<&'a Url> {
+    Url::parse(source).ok().filter(|source| source != final_url).map(|_| final_url)
+}
+
+/// Whether `media_type` fits `pattern`, where either half of the pattern
+/// may be `*`. Case doesn't matter.
+fn media_type_matches(pattern: &str, media_type: &str) -> bool {
+    let (Some((want_type, want_subtype)), Some((main, sub))) = (pattern.split_once('/'), media_type.split_once('/')) else {
+        return pattern == "*" || pattern.eq_ignore_ascii_case(media_type);
+    };
+    let part = |want: &str, got: &str| want == "*" || want.trim().eq_ignore_ascii_case(got.trim());
+    part(want_type, main) && part(want_subtype, sub)
+}
+
+/// Whether the server says it serves byte ranges, and so can resume or
+/// split a download.
+fn accepts_ranges(headers: &HeaderMap) -> bool {
+    headers
+        .get(ACCEPT_RANGES)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.trim().eq_ignore_ascii_case("bytes"))
+}
+
+/// How long the phases of a request took, for [`DownloaderBuilder::timing`].
+struct Timing {
+    /// `None` when no lookup was needed, e.g. on a reused connection.
+    dns: Option<Duration>,
+    /// From sending the request to the response headers, which covers
+    /// connecting, the TLS handshake and the server's own wait.
+    headers: Duration,
+    remote: Option<SocketAddr>,
+    sent: Instant,
+}
+
+impl Timing {
+    fn new(lookups: &Lookups, response: &Response, sent: Instant) -> Self {
+        Timing {
+            dns: response.url().host_str().and_then(|host| lookups.take(host)),
+            headers: sent.elapsed(),
+            remote: response.remote_addr(),
+            sent,
+        }
+    }
+}
+
+/// Logs a response's status and the headers that decide how it is saved,
+/// plus every header at trace level.
+fn log_response(source: &str, response: &Response, sent: Instant) {
+    let header = |name| response.headers().get(name).and_then(|v| v.to_str().ok()).unwrap_or("-");
+    debug!(
+        "{}: HTTP {} in {:?}, Content-Length: {}, Accept-Ranges: {}, Content-Range: {}",
+        source,
+        response.status(),
+        sent.elapsed(),
+        header(CONTENT_LENGTH),
+        header(ACCEPT_RANGES),
+        header(CONTENT_RANGE),
+    );
+    trace!("{}: {:?}", source, response.headers());
+}
+
+/// The first byte of a 206 response, from `Content-Range: bytes START-END/TOTAL`.
+fn range_start(headers: &HeaderMap) -> Option<u64> {
+    let value = headers.get(CONTENT_RANGE)?.to_str().ok()?;
+    let range = value.trim().strip_prefix("bytes ")?;
+    let (start, _) = range.split_once('-')?;
+    start.trim().parse().ok()
+}
+
+/// The `Content-Length` header's value. A HEAD response's body is empty, so
+/// its length has to come from the header rather than the response.
+fn content_length(headers: &HeaderMap) -> Option<u64> {
+    headers
+        .get(CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.trim().parse().ok())
+}
+
+/// The `Last-Modified` time, if the header is present and a valid HTTP date.
+fn last_modified(headers: &HeaderMap) -> Option<SystemTime> {
+    let value = headers.get(LAST_MODIFIED)?.to_str().ok()?;
+    httpdate::parse_http_date(value).ok()
+}
+
+fn part_path(file_path: &Path) -> PathBuf {
+    let mut name = file_path.as_os_str().to_owned();
+    name.push(".part");
+    PathBuf::from(name)
+}
+
+/// Renames the finished `part_path` to `file_path`, so nobody sees the
+/// file half-written. Its data is synced first, so a crash can't leave a
+/// complete-looking file whose contents never reached the disk.
+///
+/// The .part sits next to the URL-derived path, but a template can put the
+/// file in another directory, created here; if that is on another
+/// filesystem the data is copied next to it under a .part name first, so
+/// the final rename is still atomic.
+///
+/// Syncing and copying can take a while, so they run on the blocking pool.
+async fn move_into_place(part_path: &Path, file_path: &Path) -> io::Result<()> {
+    let (part_path, file_path) = (part_path.to_path_buf(), file_path.to_path_buf());
+    task::spawn_blocking(move || place(&part_path, &file_path)).await.map_err(io::Error::other)?
+}
+
+fn place(part_path: &Path, file_path: &Path) -> io::Result<()> {
+    OpenOptions::new().write(true).open(part_path)?.sync_all()?;
+    if let Some(parent) = file_path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    match fs::rename(part_path, file_path) {
+        Err(e) if e.kind() == io::ErrorKind::CrossesDevices => {
+            let staged = self::part_path(file_path);
+            let copied = fs::copy(part_path, &staged)
+                .and_then(|_| OpenOptions::new().write(true).open(&staged)?.sync_all())
+                .and_then(|()| fs::rename(&staged, file_path));
+            if copied.is_err() {
+                let _ = fs::remove_file(&staged);
+            }
+            copied?;
+            fs::remove_file(part_path)?;
+        }
+        result => result?,
+    }
+    sidecar::remove(part_path)
+}
+
+/// Decompresses the gzip data in `part_path` in place on the blocking
+/// pool, deleting it if it won't decompress.
+async fn gunzip(part_path: &Path) -> Result<(), DownloadError> {
+    debug!("Decompressing {}", part_path.display());
+    let path = part_path.to_path_buf();
+    let result = task::spawn_blocking(move || decode::gunzip_in_place(&path)).await.map_err(io::Error::other)?;
+    result.map_err(|e| {
+        let _ = fs::remove_file(part_path);
+        let _ = sidecar::remove(part_path);
+        DownloadError::Decompress { path: part_path.to_path_buf(), source: e }
+    })
+}
+
+/// Extends `file` to `total_size` after checking the filesystem has room for
+/// the bytes not yet on disk.
+fn preallocate(file: &File, path: &Path, total_size: u64, on_disk: u64) -> io::Result<()> {
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let needed = total_size.saturating_sub(on_disk);
+    // Not every platform or filesystem can report free space; skip the check there
+    if let Ok(available) = fs2::available_space(dir) {
+        if available < needed {
+            return Err(io::Error::other(format!(
+                "not enough disk space for {}: need {} bytes, {} available",
+                path.display(),
+                needed,
+                available
+            )));
+        }
+    }
+    file.set_len(total_size)
+}
+
+/// How long to wait before retrying after `error`: what the server asked
+/// for, or the backoff for this attempt.
+fn retry_delay(error: &DownloadError, attempt: u32) -> Duration {
+    match error {
+        DownloadError::Throttled { retry_after, .. } => *retry_after,
+        _ => backoff_delay(attempt),
+    }
+}
+
+/// The error for a failed `response` from `source`, carrying its
+/// `Retry-After` on a 429 or 503.
+fn status_error(response: &Response, source: &str) -> DownloadError {
+    let status = response.status();
+    let retry_after = response
+        .headers()
+        .get(RETRY_AFTER)
+        .and_then(|v| v.to_str().ok())
+        .and_then(parse_retry_after)
+        .filter(|_| matches!(status, StatusCode::TOO_MANY_REQUESTS | StatusCode::SERVICE_UNAVAILABLE));
+    match retry_after {
+        Some(retry_after) => DownloadError::Throttled { status, url: source.to_string(), retry_after },
+        None => DownloadError::HttpStatus(status, source.to_string()),
+    }
+}
+
+/// `Retry-After` as either a number of seconds or an HTTP date, capped at
+/// [`MAX_RETRY_AFTER`].
+fn parse_retry_after(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    let wait = match value.parse::<u64>() {
+        Ok(secs) => Duration::from_secs(secs),
+        Err(_) => {
+            let date = httpdate::parse_http_date(value).ok()?;
+            // Dates only go to the second, so round up rather than print nanoseconds
+            let wait = date.duration_since(SystemTime::now()).unwrap_or(Duration::ZERO);
+            Duration::from_secs(wait.as_secs_f64().ceil() as u64)
+        }
+    };
+    Some(wait.min(MAX_RETRY_AFTER))
+}
+
+#[cfg(unix)]
+fn set_mode(path: &Path, mode: u32) -> io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    fs::set_permissions(path, fs::Permissions::from_mode(mode))
+}
+
+#[cfg(not(unix))]
+fn set_mode(_path: &Path, _mode: u32) -> io::Result<()> {
+    Ok(())
+}
+
+/// `delay` scaled by a factor between 0.5 and 1.5.
+fn jittered(delay: Duration) -> Duration {
+    let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.subsec_nanos()).unwrap_or(0);
+    delay.mul_f64(0.5 + nanos as f64 / 1e9)
+}
+
+/// Exponential backoff starting at 500ms, with up to 250ms of jitter so
+/// parallel retries against the same host don't line up.
+fn backoff_delay(attempt: u32) -> Duration {
+    let base = 500u64.saturating_mul(1 << (attempt - 1).min(16));
+    let jitter = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.subsec_nanos() as u64 % 250)
+        .unwrap_or(0);
+    Duration::from_millis(base + jitter)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn retry_after_takes_seconds_or_a_date() {
+        assert_eq!(parse_retry_after("120"), Some(Duration::from_secs(120)));
+        assert_eq!(parse_retry_after(" 0 "), Some(Duration::ZERO));
+        assert_eq!(parse_retry_after("Wed, 21 Oct 2015 07:28:00 GMT"), Some(Duration::ZERO));
+        let soon = httpdate::fmt_http_date(SystemTime::now() + Duration::from_secs(30));
+        let wait = parse_retry_after(&soon).unwrap();
+        assert!(wait > Duration::from_secs(28) && wait <= Duration::from_secs(31), "{:?}", wait);
+        assert_eq!(parse_retry_after("soon"), None);
+    }
+
+    #[test]
+    fn retry_after_is_capped() {
+        assert_eq!(parse_retry_after("86400"), Some(MAX_RETRY_AFTER));
+        let tomorrow = httpdate::fmt_http_date(SystemTime::now() + Duration::from_secs(86_400));
+        assert_eq!(parse_retry_after(&tomorrow), Some(MAX_RETRY_AFTER));
+    }
+}
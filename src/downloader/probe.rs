@@ -0,0 +1,69 @@
+use super::{content_length, redirected, Downloader};
+use crate::error::DownloadError;
+use reqwest::{Method, StatusCode};
+use std::path::PathBuf;
+use tokio::task;
+
+/// What [`Downloader::probe`] found out about a URL without downloading it.
+pub struct Probe {
+    pub url: String,
+    /// Where the file would be saved.
+    pub path: PathBuf,
+    /// `None` when the server didn't say.
+    pub size: Option<u64>,
+    /// The status of the HEAD response, or of the GET it fell back to.
+    pub status: StatusCode,
+}
+
+impl Downloader {
+    /// Asks the server where `url` would be saved and how big it is,
+    /// without writing anything.
+    ///
+    /// Sends a HEAD request, falling back to a GET that is dropped once the
+    /// headers arrive for servers that refuse HEAD.
+    pub async fn probe(&self, url: &str) -> Result<Probe, DownloadError> {
+        let file_path = self.claim(url, &self.default_path(url), None);
+        let _permits = self.acquire(url, None).await?;
+
+        let head = self.send(self.request_with(Method::HEAD, url)).await?;
+        let (headers, final_url, size, status) = if head.status().is_success() {
+            let size = content_length(head.headers());
+            (head.headers().clone(), head.url().clone(), size, head.status())
+        } else {
+            // The body is never read, so dropping the response aborts the transfer
+            let get = self.send(self.request(url)).await?;
+            if !get.status().is_success() {
+                return Err(DownloadError::HttpStatus(get.status(), url.to_string()));
+            }
+            (get.headers().clone(), get.url().clone(), get.content_length(), get.status())
+        };
+
+        self.report(|r| r.on_probed(url, size));
+        Ok(Probe {
+            url: url.to_string(),
+            path: self.resolve_file_path(url, &headers, redirected(url, &final_url), &file_path),
+            size,
+            status,
+        })
+    }
+
+    /// Probes every URL concurrently, returning one result per URL in order.
+    pub async fn probe_many(&self, urls: &[String]) -> Vec<Result<Probe, DownloadError>> {
+        let mut handles = vec![];
+        for url in urls {
+            self.batch_index(url);
+            let downloader = self.clone();
+            let url = url.clone();
+            handles.push(task::spawn(async move { downloader.probe(&url).await }));
+        }
+
+        let mut results = vec![];
+        for (handle, url) in handles.into_iter().zip(urls) {
+            results.push(handle.await.unwrap_or_else(|e| match e.try_into_panic() {
+                Ok(payload) => Err(DownloadError::panicked(url, payload)),
+                Err(e) => Err(DownloadError::Other(e.to_string())),
+            }));
+        }
+        results
+    }
+}
@@ -0,0 +1,208 @@
+use super::{accepts_ranges, content_length, last_modified, log_response, move_into_place, part_path, preallocate, redirected, status_error, Contribution, Downloader, Saved, SegmentStrategy};
+use crate::checksum;
+use crate::open_files::PartWriter;
+use crate::error::DownloadError;
+use crate::sidecar::Validators;
+use log::debug;
+use reqwest::{Method, StatusCode};
+use reqwest::header::RANGE;
+use sha2::{Digest, Sha256};
+use std::fs::{self, File, OpenOptions};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::ops::RangeInclusive;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Instant;
+use futures_util::future::try_join_all;
+use tokio::task;
+
+/// Segments smaller than this aren't worth an extra connection.
+const MIN_SEGMENT_SIZE: u64 = 1024 * 1024;
+
+impl Downloader {
+    /// Downloads `url` as parallel ranged requests, each written at its own
+    /// offset in the .part file, or with [`SegmentStrategy::TempFiles`] to a
+    /// file of its own, joined into the .part file at the end.
+    ///
+    /// Returns `Ok(None)` when the server doesn't advertise byte ranges or
+    /// the file is too small to split, so the caller can fall back to a
+    /// single stream.
+    pub(super) async fn try_segmented(&self, url: &str, source: &str, file_path: &Path, conditional: Option<&Validators>, contribution: &mut Contribution) -> Result<Option<Saved>, DownloadError> {
+        let mut request = self.request_with(Method::HEAD, source);
+        if let Some(validators) = conditional {
+            request = validators.apply(request);
+        }
+
+        debug!("HEAD {}", source);
+        let sent = Instant::now();
+        let response = self.send(request).await?;
+        log_response(source, &response, sent);
+        let unchanged = response.status() == StatusCode::NOT_MODIFIED && conditional.is_some();
+        if unchanged || (response.status().is_success() && self.up_to_date(file_path, conditional, response.headers())) {
+            return Ok(Some(Saved::Unchanged(file_path.to_path_buf())));
+        }
+        if !response.status().is_success() {
+            // A GET right away would only be told to wait as well
+            if let error @ DownloadError::Throttled { .. } = status_error(&response, source) {
+                return Err(error);
+            }
+            // Let the plain GET report the failure
+            return Ok(None);
+        }
+
+        let headers = response.headers();
+        self.check_content_type(source, headers)?;
+        let accepts_ranges = accepts_ranges(headers);
+        let total_size = content_length(headers).unwrap_or(0);
+        if !accepts_ranges || total_size < MIN_SEGMENT_SIZE * 2 {
+            let reason = if accepts_ranges { "is too small to split" } else { "doesn't accept byte ranges" };
+            debug!("{} {}; using a single connection", source, reason);
+            return Ok(None);
+        }
+
+        self.check_size(source, Some(total_size), total_size)?;
+        // Compressed bodies are checked once decoded
+        if !self.decoding(headers) {
+            self.check_expected_size(url, source, total_size)?;
+        }
+        let part_path = part_path(file_path);
+        let file_path = self.resolve_file_path(url, headers, redirected(source, response.url()), file_path);
+        let modified = last_modified(headers);
+        let validators = Validators::from_headers(headers);
+        let file_index = self.register(url, &file_path, 0, total_size, contribution).await;
+
+        let segments = (total_size / MIN_SEGMENT_SIZE).min(self.connections as u64);
+        let segment_len = total_size.div_ceil(segments);
+        debug!("Splitting {} into {} segments of {} bytes", url, segments, segment_len);
+        contribution.segmented = true;
+        match self.segment_strategy {
+            SegmentStrategy::Seek => {
+                let file = self.open_files.open(&part_path, File::options().write(true).create(true).truncate(true)).await?;
+                preallocate(&file.file, &part_path, total_size, 0)?;
+            }
+            SegmentStrategy::TempFiles => contribution.segment_files = segments,
+        }
+
+        let written = AtomicU64::new(0);
+        let result = try_join_all((0..segments).map(|i| {
+            let start = i * segment_len;
+            let end = (start + segment_len).min(total_size) - 1;
+            let path = match self.segment_strategy {
+                SegmentStrategy::Seek => part_path.clone(),
+                SegmentStrategy::TempFiles => segment_path(&part_path, i),
+            };
+            let written = &written;
+            async move { self.download_segment(url, source, &path, start..=end, file_index, written).await }
+        }))
+        .await;
+        contribution.bytes += written.load(Ordering::Relaxed);
+        result?;
+        if self.segment_strategy == SegmentStrategy::TempFiles {
+            join_segments(source, &part_path, segments, total_size).await?;
+        }
+
+        self.decode_part(&part_path, headers).await?;
+        if let Some(expected) = self.checksums.get(url) {
+            let hasher = checksum::hash_part(Sha256::new(), &part_path).await?;
+            checksum::verify(hasher, expected, url, &part_path)?;
+        }
+        self.check_part_size(url, source, &part_path)?;
+        self.verify_part(url, &part_path, &file_path).await?;
+        self.gunzip_part(&part_path).await?;
+        move_into_place(&part_path, &file_path).await?;
+        self.set_modified(&file_path, modified);
+        self.save_validators(&file_path, &validators)?;
+
+        Ok(Some(Saved::Downloaded(file_path)))
+    }
+
+    /// Fetches `range` from `source` into the same range of `path`, the
+    /// .part file, or with [`SegmentStrategy::TempFiles`] into all of `path`,
+    /// the segment's own file.
+    async fn download_segment(&self, url: &str, source: &str, path: &Path, range: RangeInclusive<u64>, file_index: usize, written: &AtomicU64) -> Result<(), DownloadError> {
+        let (start, end) = range.into_inner();
+        debug!("GET {} bytes {}-{}", source, start, end);
+        let sent = Instant::now();
+        let request = self.request(source).header(RANGE, format!("bytes={}-{}", start, end));
+        let response = self.send(request).await?;
+        log_response(source, &response, sent);
+
+        let status = response.status();
+        if status != StatusCode::PARTIAL_CONTENT {
+            return Err(if status.is_success() {
+                DownloadError::RangeNotHonored { url: source.to_string(), start, end: Some(end) }
+            } else {
+                status_error(&response, source)
+            });
+        }
+
+        let mut file = match self.segment_strategy {
+            SegmentStrategy::Seek => {
+                let file = self.open_files.open(path, OpenOptions::new().write(true)).await?;
+                PartWriter::new(file, path, start, self.buffer_size, &self.open_files)
+            }
+            SegmentStrategy::TempFiles => {
+                let file = self.open_files.open(path, OpenOptions::new().write(true).create(true).truncate(true)).await?;
+                PartWriter::appending(file, path, self.buffer_size, &self.open_files)
+            }
+        };
+
+        let expected = end - start + 1;
+        let mut received = 0;
+        let mut stream = response.bytes_stream();
+        let mut slow_since = None;
+        while let Some(chunk) = self.next_chunk(&mut stream, url, file_index, &mut slow_since).await? {
+            let len = chunk.len() as u64;
+            if received + len > expected {
+                return Err(DownloadError::RangeNotHonored { url: source.to_string(), start, end: Some(end) });
+            }
+
+            file.write_all(&chunk).await?;
+            received += len;
+            self.record_chunk(url, file_index, len).await;
+            written.fetch_add(len, Ordering::Relaxed);
+        }
+
+        if received != expected {
+            return Err(DownloadError::SizeMismatch {
+                url: source.to_string(),
+                expected,
+                actual: received,
+            });
+        }
+        file.finish().await?;
+        Ok(())
+    }
+}
+
+/// Where segment `index` of `part_path` is written with
+/// [`SegmentStrategy::TempFiles`].
+pub(super) fn segment_path(part_path: &Path, index: u64) -> PathBuf {
+    let mut name = part_path.as_os_str().to_owned();
+    name.push(format!(".{}", index));
+    PathBuf::from(name)
+}
+
+/// Appends the `segments` segment files to a fresh `part_path` in order,
+/// deleting each once copied, and checks they add up to `total_size`.
+async fn join_segments(source: &str, part_path: &Path, segments: u64, total_size: u64) -> Result<(), DownloadError> {
+    debug!("Joining {} segments into {}", segments, part_path.display());
+    let part_path = part_path.to_path_buf();
+    let joined = task::spawn_blocking(move || {
+        let mut part = File::create(&part_path)?;
+        let mut joined = 0;
+        for index in 0..segments {
+            let path = segment_path(&part_path, index);
+            joined += io::copy(&mut File::open(&path)?, &mut part)?;
+            fs::remove_file(&path)?;
+        }
+        Ok::<_, io::Error>(joined)
+    })
+    .await
+    .map_err(io::Error::other)??;
+
+    if joined != total_size {
+        return Err(DownloadError::SizeMismatch { url: source.to_string(), expected: total_size, actual: joined });
+    }
+    Ok(())
+}
@@ -0,0 +1,125 @@
+use super::{range_start, retry_delay, status_error, ActiveDownload, Contribution, Downloader};
+use crate::error::DownloadError;
+use reqwest::header::RANGE;
+use reqwest::StatusCode;
+use std::path::Path;
+use log::{debug, info};
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+use tokio::time;
+
+impl Downloader {
+    /// Streams `url`'s body to stdout instead of saving it, returning the
+    /// number of bytes written. See [`download_to_writer`](Self::download_to_writer).
+    pub async fn download_to_stdout(&self, url: &str) -> Result<u64, DownloadError> {
+        self.download_to_writer(url, &mut tokio::io::stdout()).await
+    }
+
+    /// Streams `url`'s body into `writer` instead of a file, returning the
+    /// number of bytes written. Progress is reported as for files, with `-`
+    /// as the path.
+    ///
+    /// Bytes reach `writer` in body order, each chunk written in full before
+    /// the next is read from the network. `writer` is flushed once the body
+    /// is complete, and not on failure.
+    ///
+    /// Retries resume with a range request from the last byte written, so
+    /// `writer` never sees a byte twice; a server that can't resume fails
+    /// the download instead. Mirrors, checksums and the other file-only
+    /// options don't apply.
+    pub async fn download_to_writer<W: AsyncWrite + Unpin>(&self, url: &str, writer: &mut W) -> Result<u64, DownloadError> {
+        let _active = ActiveDownload::new(&self.cancel, url);
+        let _permits = tokio::select! {
+            permits = self.acquire(url, None) => permits?,
+            _ = self.cancelled(url) => return Err(DownloadError::Cancelled),
+        };
+
+        let mut written = 0;
+        let mut attempt = 0;
+        let result = loop {
+            let mut contribution = Contribution::default();
+            let result = tokio::select! {
+                result = self.stream_to(url, writer, &mut written, &mut contribution) => result,
+                _ = self.cancelled(url) => Err(DownloadError::Cancelled),
+            };
+
+            let mut stats = self.stats.lock().await;
+            if let Some(index) = contribution.file {
+                stats.files[index].finished = true;
+                self.file_limiters.lock().unwrap_or_else(|e| e.into_inner()).remove(&index);
+            }
+            if result.is_err() {
+                stats.total_size -= contribution.size;
+                stats.total_bytes -= contribution.bytes;
+            }
+            drop(stats);
+
+            match result {
+                Err(e) if attempt < self.retries && self.should_retry(&e) => {
+                    attempt += 1;
+                    let delay = retry_delay(&e, attempt);
+                    info!("Retrying {} in {:?} (attempt {}/{}): {}", url, delay, attempt, self.retries, e);
+                    tokio::select! {
+                        _ = time::sleep(delay) => {}
+                        _ = self.cancelled(url) => break Err(DownloadError::Cancelled),
+                    }
+                }
+                result => break result,
+            }
+        };
+
+        self.report(|r| match &result {
+            Ok(()) => r.on_complete(url, Path::new("-")),
+            Err(e) => r.on_error(url, e),
+        });
+        result.map(|()| written)
+    }
+
+    /// One attempt at streaming `url` into `writer`, picking up after the
+    /// `written` bytes an earlier attempt got through.
+    async fn stream_to<W: AsyncWrite + Unpin>(&self, url: &str, writer: &mut W, written: &mut u64, contribution: &mut Contribution) -> Result<(), DownloadError> {
+        let mut request = self.request(url);
+        if *written > 0 {
+            request = request.header(RANGE, format!("bytes={}-", written));
+        }
+        debug!("GET {} into a writer from byte {}", url, written);
+        let response = self.send(request).await?;
+
+        let status = response.status();
+        if !status.is_success() {
+            return Err(status_error(&response, url));
+        }
+        self.check_content_type(url, response.headers())?;
+        if *written > 0
+            && (status != StatusCode::PARTIAL_CONTENT || range_start(response.headers()) != Some(*written))
+        {
+            // The bytes already written can't be taken back
+            return Err(DownloadError::RangeNotHonored { url: url.to_string(), start: *written, end: None });
+        }
+
+        let content_length = response.content_length();
+        let total_size = content_length.map(|len| len + *written).unwrap_or(0);
+        if content_length.is_some() {
+            self.check_size(url, Some(total_size), total_size)?;
+        }
+        let file_index = self.register(url, Path::new("-"), *written, total_size, contribution).await;
+
+        let mut stream = response.bytes_stream();
+        let mut slow_since = None;
+        while let Some(chunk) = self.next_chunk(&mut stream, url, file_index, &mut slow_since).await? {
+            self.check_size(url, None, *written + chunk.len() as u64)?;
+            writer.write_all(&chunk).await?;
+            *written += chunk.len() as u64;
+            self.record_chunk(url, file_index, chunk.len() as u64).await;
+            contribution.bytes += chunk.len() as u64;
+        }
+        if content_length.is_some() && *written != total_size {
+            return Err(DownloadError::SizeMismatch {
+                url: url.to_string(),
+                expected: total_size,
+                actual: *written,
+            });
+        }
+        writer.flush().await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,116 @@
+use super::Downloader;
+use crate::checksum::{self, Algorithm};
+use crate::decode;
+use crate::error::DownloadError;
+use crate::filename;
+use crate::sidecar;
+use log::{debug, warn};
+use reqwest::{StatusCode, Url};
+use std::fs;
+use std::io;
+use std::path::Path;
+use tokio::task;
+
+impl Downloader {
+    /// With [`DownloaderBuilder::verify_sidecar`](super::DownloaderBuilder::verify_sidecar),
+    /// checks the finished `part_path`, to be saved as `file_path`, against
+    /// the first of `url.sha256`, `url.sha1` and `url.md5` the server has,
+    /// deleting it on a mismatch. Without a usable sidecar this warns, or
+    /// deletes it and fails if one is required, so a later run doesn't take
+    /// the unverified file as done.
+    ///
+    /// This runs before any gunzipping, since the sidecar is for the file
+    /// as served.
+    pub(super) async fn verify_part(&self, url: &str, part_path: &Path, file_path: &Path) -> Result<(), DownloadError> {
+        let Some(required) = self.verify_sidecar else {
+            return Ok(());
+        };
+        // The sidecar lists a .gz about to be decompressed under its own name
+        let gzip_name = match self.decompress_gzip && decode::starts_gzip(part_path)? {
+            true => filename::from_url(url),
+            false => None,
+        };
+        let name = gzip_name.unwrap_or_else(|| file_path.file_name().unwrap_or_default().to_string_lossy().into_owned());
+        let mut problem = None;
+        for algorithm in Algorithm::ALL {
+            let sidecar = sidecar_url(url, algorithm);
+            debug!("GET {}", sidecar);
+            let text = match self.fetch_sidecar(&sidecar).await {
+                Ok(Some(text)) => text,
+                Ok(None) => continue,
+                Err(e) => {
+                    problem = Some(format!("couldn't fetch {}: {}", sidecar, e));
+                    continue;
+                }
+            };
+            let Some(expected) = checksum::parse_sidecar(&text, &name, algorithm) else {
+                problem = Some(format!("{} has no {} for {}", sidecar, algorithm.name(), name));
+                continue;
+            };
+
+            let path = part_path.to_path_buf();
+            let actual = task::spawn_blocking(move || algorithm.hash_file(&path)).await.map_err(io::Error::other)??;
+            if actual != expected {
+                remove_part(part_path)?;
+                debug!("{} says {}", sidecar, expected);
+                return Err(DownloadError::ChecksumMismatch {
+                    url: url.to_string(),
+                    algorithm: algorithm.name(),
+                    expected,
+                    actual,
+                });
+            }
+            debug!("{} matches {}", part_path.display(), sidecar);
+            return Ok(());
+        }
+
+        let problem = problem.unwrap_or_else(|| format!("no .sha256, .sha1 or .md5 sidecar for {}", url));
+        if required {
+            remove_part(part_path)?;
+            return Err(DownloadError::Unverified { url: url.to_string(), reason: problem });
+        }
+        warn!("Not verified, {}", problem);
+        Ok(())
+    }
+
+    /// The sidecar's text, or `None` if the server doesn't have it.
+    async fn fetch_sidecar(&self, sidecar: &str) -> Result<Option<String>, DownloadError> {
+        let response = self.send(self.request(sidecar)).await?;
+        let status = response.status();
+        if status == StatusCode::NOT_FOUND || status == StatusCode::GONE {
+            return Ok(None);
+        }
+        if !status.is_success() {
+            return Err(DownloadError::HttpStatus(status, sidecar.to_string()));
+        }
+        Ok(Some(response.text().await?))
+    }
+}
+
+/// `url` with `algorithm`'s extension added to its path, keeping any
+/// query, since that is often what grants access.
+fn sidecar_url(url: &str, algorithm: Algorithm) -> String {
+    let Ok(mut sidecar) = Url::parse(url) else {
+        return format!("{}.{}", url, algorithm.extension());
+    };
+    sidecar.set_path(&format!("{}.{}", sidecar.path(), algorithm.extension()));
+    sidecar.set_fragment(None);
+    sidecar.to_string()
+}
+
+fn remove_part(part_path: &Path) -> std::io::Result<()> {
+    fs::remove_file(part_path)?;
+    sidecar::remove(part_path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sidecar_extension_goes_on_the_path() {
+        assert_eq!(sidecar_url("https://host/f.iso", Algorithm::Sha256), "https://host/f.iso.sha256");
+        assert_eq!(sidecar_url("https://host/f.iso?token=x", Algorithm::Md5), "https://host/f.iso.md5?token=x");
+        assert_eq!(sidecar_url("https://host/f.iso#part", Algorithm::Sha1), "https://host/f.iso.sha1");
+    }
+}
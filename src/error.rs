@@ -0,0 +1,147 @@
+use reqwest::StatusCode;
+use std::any::Any;
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// Statuses retried unless [`DownloaderBuilder::retry_on_status`](crate::DownloaderBuilder::retry_on_status)
+/// says otherwise.
+pub(crate) const DEFAULT_RETRY_STATUSES: [u16; 6] = [408, 429, 500, 502, 503, 504];
+
+#[derive(Debug)]
+pub enum DownloadError {
+    ReqwestError(reqwest::Error),
+    IoError(std::io::Error),
+    HttpStatus(StatusCode, String),
+    /// A 429 or 503 whose `Retry-After` asked to wait `retry_after`, at
+    /// most five minutes, before trying again.
+    Throttled { status: StatusCode, url: String, retry_after: Duration },
+    Timeout(String),
+    /// The body ended before, or ran past, the length the server announced.
+    SizeMismatch { url: String, expected: u64, actual: u64 },
+    /// The file is bigger than [`DownloaderBuilder::max_size`](crate::DownloaderBuilder::max_size)
+    /// allows. `size` is the advertised length, or `None` when the limit was
+    /// passed mid-stream.
+    TooLarge { url: String, size: Option<u64>, limit: u64 },
+    /// The file isn't the size [`DownloaderBuilder::expected_size`](crate::DownloaderBuilder::expected_size)
+    /// gave: `actual` is the advertised length, or what was saved, which was
+    /// then deleted.
+    UnexpectedSize { url: String, expected: u64, actual: u64 },
+    /// The response's `Content-Type` isn't what
+    /// [`DownloaderBuilder::expect_content_type`](crate::DownloaderBuilder::expect_content_type)
+    /// allows. `content_type` is `None` when the server sent none.
+    UnexpectedContentType { url: String, content_type: Option<String>, expected: String },
+    /// The saved file's `algorithm` digest wasn't the `expected` one, and
+    /// the file was deleted.
+    ChecksumMismatch { url: String, algorithm: &'static str, expected: String, actual: String },
+    /// [`DownloaderBuilder::verify_sidecar`](crate::DownloaderBuilder::verify_sidecar)
+    /// required a checksum that couldn't be found, and the file was deleted.
+    Unverified { url: String, reason: String },
+    /// The server answered a range request for bytes `start` to `end` with
+    /// something else, or with `end` as `None`, couldn't resume from `start`.
+    RangeNotHonored { url: String, start: u64, end: Option<u64> },
+    /// The body was empty, which
+    /// [`DownloaderBuilder::fail_on_empty`](crate::DownloaderBuilder::fail_on_empty)
+    /// rejects, and the empty file was deleted.
+    Empty { url: String },
+    /// A gzip-encoded body didn't decompress, and `path` was deleted.
+    Decompress { path: PathBuf, source: std::io::Error },
+    /// A proxy, certificate or key given to the builder can't be used.
+    Config(String),
+    /// Stopped by [`Downloader::cancel`](crate::Downloader::cancel).
+    Cancelled,
+    /// A bug, here or in a dependency, panicked while downloading `url`.
+    /// Only that URL fails; the rest of the batch carries on.
+    Panicked { url: String, message: String },
+    Other(String),
+}
+
+impl DownloadError {
+    /// The error for a panic caught while working on `url`, with the
+    /// panic's message if it had one.
+    pub(crate) fn panicked(url: &str, payload: Box<dyn Any + Send>) -> Self {
+        let message = match payload.downcast::<String>() {
+            Ok(message) => *message,
+            Err(payload) => payload.downcast_ref::<&str>().map_or("no message", |message| message).to_string(),
+        };
+        DownloadError::Panicked { url: url.to_string(), message }
+    }
+
+    /// Network failures and temporary statuses (408, 429, 500, 502, 503
+    /// and 504) are worth another attempt; other statuses like 404 and
+    /// local I/O errors are not. This checks statuses against those
+    /// defaults only: a `Downloader` built with
+    /// `DownloaderBuilder::retry_on_status` retries the statuses given
+    /// there instead.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            DownloadError::ReqwestError(e) => e.is_connect() || e.is_timeout() || e.is_request() || e.is_body(),
+            DownloadError::HttpStatus(status, _) => DEFAULT_RETRY_STATUSES.contains(&status.as_u16()),
+            DownloadError::Throttled { .. } | DownloadError::Timeout(_) | DownloadError::SizeMismatch { .. } | DownloadError::Empty { .. } => true,
+            _ => false,
+        }
+    }
+}
+
+impl std::error::Error for DownloadError {}
+
+impl std::fmt::Display for DownloadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DownloadError::ReqwestError(e) => write!(f, "Reqwest error: {}", e),
+            DownloadError::IoError(e) => write!(f, "IO error: {}", e),
+            DownloadError::HttpStatus(status, url) => write!(f, "HTTP {} for {}", status, url),
+            DownloadError::Throttled { status, url, retry_after } => {
+                write!(f, "HTTP {} for {}, retry after {:?}", status, url, retry_after)
+            }
+            DownloadError::Timeout(s) => write!(f, "Timed out: {}", s),
+            DownloadError::SizeMismatch { url, expected, actual } => {
+                write!(f, "Size mismatch for {}: expected {} bytes, got {}", url, expected, actual)
+            }
+            DownloadError::TooLarge { url, size: Some(size), limit } => {
+                write!(f, "{} is {} bytes, over the {}-byte limit", url, size, limit)
+            }
+            DownloadError::TooLarge { url, size: None, limit } => {
+                write!(f, "{} passed the {}-byte limit before finishing", url, limit)
+            }
+            DownloadError::UnexpectedSize { url, expected, actual } => {
+                write!(f, "{} is {} bytes, expected {}", url, actual, expected)
+            }
+            DownloadError::UnexpectedContentType { url, content_type: Some(content_type), expected } => {
+                write!(f, "{} is {}, not {}", url, content_type, expected)
+            }
+            DownloadError::UnexpectedContentType { url, content_type: None, expected } => {
+                write!(f, "{} has no Content-Type, expected {}", url, expected)
+            }
+            DownloadError::ChecksumMismatch { url, algorithm, expected, actual } => {
+                write!(f, "{} mismatch for {}: expected {}, got {}", algorithm, url, expected, actual)
+            }
+            DownloadError::Unverified { url, reason } => write!(f, "Couldn't verify {}: {}", url, reason),
+            DownloadError::RangeNotHonored { url, start, end: Some(end) } => {
+                write!(f, "{} didn't honor the range request for bytes {}-{}", url, start, end)
+            }
+            DownloadError::RangeNotHonored { url, start, end: None } => {
+                write!(f, "{} can't resume at byte {}", url, start)
+            }
+            DownloadError::Empty { url } => write!(f, "{} sent an empty body", url),
+            DownloadError::Decompress { path, source } => {
+                write!(f, "Couldn't decompress {}: {}", path.display(), source)
+            }
+            DownloadError::Config(s) => write!(f, "{}", s),
+            DownloadError::Cancelled => write!(f, "Cancelled"),
+            DownloadError::Panicked { url, message } => write!(f, "Panicked while downloading {}: {}", url, message),
+            DownloadError::Other(s) => write!(f, "Other error: {}", s),
+        }
+    }
+}
+
+impl From<reqwest::Error> for DownloadError {
+    fn from(err: reqwest::Error) -> Self {
+        DownloadError::ReqwestError(err)
+    }
+}
+
+impl From<std::io::Error> for DownloadError {
+    fn from(err: std::io::Error) -> Self {
+        DownloadError::IoError(err)
+    }
+}
@@ -0,0 +1,164 @@
+use crate::error::DownloadError;
+use crate::reporter::ProgressReporter;
+use std::collections::{HashMap, VecDeque};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex as StdMutex};
+use std::task::{Context, Poll};
+use tokio::sync::mpsc::{self, error::TrySendError};
+
+/// How many events a stream holds before it starts missing `Progress`
+/// events. The others are kept however far behind it falls, since there
+/// are only a few per download.
+const CAPACITY: usize = 256;
+
+/// A download event from [`Downloader::events`](crate::Downloader::events),
+/// mirroring the [`ProgressReporter`] methods.
+#[derive(Clone, Debug)]
+pub enum DownloadEvent {
+    /// An attempt got its response headers; a retry sends this again.
+    /// `downloaded` is what an earlier attempt left on disk.
+    Started { url: String, path: PathBuf, downloaded: u64, total: Option<u64> },
+    /// `bytes` of `url` are on disk, counting resumed bytes.
+    Progress { url: String, bytes: u64, total: Option<u64> },
+    Finished { url: String, path: PathBuf },
+    /// `url` failed for good, after any retries.
+    Failed { url: String, error: String },
+}
+
+/// The senders behind every open event stream.
+#[derive(Default)]
+pub(crate) struct Subscribers {
+    senders: StdMutex<Vec<Subscriber>>,
+    /// The size from each URL's latest `Started`, repeated in its `Progress`.
+    totals: StdMutex<HashMap<String, Option<u64>>>,
+}
+
+struct Subscriber {
+    sender: mpsc::Sender<DownloadEvent>,
+    /// Events that must not be missed but found the channel full, read
+    /// once it has been emptied.
+    overflow: Arc<StdMutex<VecDeque<DownloadEvent>>>,
+}
+
+impl Subscriber {
+    /// Sends `event` unless it's a `Progress` event and the stream is
+    /// behind. Returns whether the stream is still open.
+    fn send(&self, event: DownloadEvent) -> bool {
+        let mut overflow = self.overflow.lock().unwrap_or_else(|e| e.into_inner());
+        // Once anything has overflowed, later events queue behind it to stay in order
+        let event = if overflow.is_empty() {
+            match self.sender.try_send(event) {
+                Ok(()) => return true,
+                Err(TrySendError::Closed(_)) => return false,
+                Err(TrySendError::Full(event)) => event,
+            }
+        } else {
+            event
+        };
+        if !matches!(event, DownloadEvent::Progress { .. }) {
+            overflow.push_back(event);
+        }
+        !self.sender.is_closed()
+    }
+}
+
+/// The receiving end of one event stream.
+pub(crate) struct EventReceiver {
+    receiver: mpsc::Receiver<DownloadEvent>,
+    overflow: Arc<StdMutex<VecDeque<DownloadEvent>>>,
+}
+
+impl EventReceiver {
+    pub(crate) fn poll_recv(&mut self, cx: &mut Context<'_>) -> Poll<Option<DownloadEvent>> {
+        // Not poll_recv, which can also be pending while the channel holds events
+        if let Ok(event) = self.receiver.try_recv() {
+            return Poll::Ready(Some(event));
+        }
+        // Overflowed events are newer than any that were in the channel
+        if let Some(event) = self.overflow.lock().unwrap_or_else(|e| e.into_inner()).pop_front() {
+            return Poll::Ready(Some(event));
+        }
+        self.receiver.poll_recv(cx)
+    }
+}
+
+impl Subscribers {
+    pub(crate) fn subscribe(&self) -> EventReceiver {
+        let (sender, receiver) = mpsc::channel(CAPACITY);
+        let overflow = Arc::new(StdMutex::new(VecDeque::new()));
+        self.lock().push(Subscriber { sender, overflow: overflow.clone() });
+        EventReceiver { receiver, overflow }
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.lock().is_empty()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Vec<Subscriber>> {
+        self.senders.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Sends `event` to every stream, forgetting the ones that were dropped.
+    fn send(&self, event: DownloadEvent) {
+        self.lock().retain(|subscriber| subscriber.send(event.clone()));
+    }
+
+    fn total(&self, url: &str) -> Option<u64> {
+        self.totals.lock().unwrap_or_else(|e| e.into_inner()).get(url).copied().flatten()
+    }
+}
+
+impl ProgressReporter for Subscribers {
+    fn on_start(&self, url: &str, path: &Path, downloaded: u64, total: Option<u64>) {
+        self.totals.lock().unwrap_or_else(|e| e.into_inner()).insert(url.to_string(), total);
+        self.send(DownloadEvent::Started {
+            url: url.to_string(),
+            path: path.to_path_buf(),
+            downloaded,
+            total,
+        });
+    }
+
+    fn on_progress(&self, url: &str, downloaded: u64) {
+        self.send(DownloadEvent::Progress {
+            url: url.to_string(),
+            bytes: downloaded,
+            total: self.total(url),
+        });
+    }
+
+    fn on_complete(&self, url: &str, path: &Path) {
+        self.totals.lock().unwrap_or_else(|e| e.into_inner()).remove(url);
+        self.send(DownloadEvent::Finished { url: url.to_string(), path: path.to_path_buf() });
+    }
+
+    fn on_error(&self, url: &str, error: &DownloadError) {
+        self.totals.lock().unwrap_or_else(|e| e.into_inner()).remove(url);
+        self.send(DownloadEvent::Failed { url: url.to_string(), error: error.to_string() });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn a_stream_that_falls_behind_misses_only_progress() {
+        let subscribers = Subscribers::default();
+        let mut events = subscribers.subscribe();
+        subscribers.on_start("u", Path::new("f"), 0, None);
+        for bytes in 0..CAPACITY as u64 * 2 {
+            subscribers.on_progress("u", bytes);
+        }
+        subscribers.on_complete("u", Path::new("f"));
+        drop(subscribers);
+
+        let mut received = Vec::new();
+        while let Some(event) = std::future::poll_fn(|cx| events.poll_recv(cx)).await {
+            received.push(event);
+        }
+        assert_eq!(received.len(), CAPACITY + 1);
+        assert!(matches!(received[0], DownloadEvent::Started { .. }));
+        assert!(matches!(received[CAPACITY], DownloadEvent::Finished { .. }));
+    }
+}
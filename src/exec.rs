@@ -0,0 +1,30 @@
+use log::warn;
+use std::path::Path;
+use tokio::process::Command;
+
+/// Runs `command` with every `{}` in its arguments replaced by `path`,
+/// logging a warning if it can't start or exits unsuccessfully.
+///
+/// No shell is involved, so a file name chosen by the server can't inject
+/// commands; wrap the command in `sh -c` explicitly if a shell is needed.
+pub(crate) async fn run(command: &[String], path: &Path) {
+    let path = path.to_string_lossy();
+    let args: Vec<String> = command.iter().map(|arg| arg.replace("{}", &path)).collect();
+    let Some((program, args)) = args.split_first() else {
+        return;
+    };
+
+    match Command::new(program).args(args).output().await {
+        Ok(output) if output.status.success() => {}
+        Ok(output) => {
+            let mut message = format!("{} for {} exited with {}", program, path, output.status);
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            for line in stderr.lines().filter(|l| !l.trim().is_empty()) {
+                message.push_str("\n  ");
+                message.push_str(line);
+            }
+            warn!("{}", message);
+        }
+        Err(e) => warn!("Couldn't run {} for {}: {}", program, path, e),
+    }
+}
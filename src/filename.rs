@@ -0,0 +1,291 @@
+use reqwest::Url;
+use std::path::{Path, PathBuf};
+
+/// Picks the filename from a `Content-Disposition` header, preferring the
+/// RFC 5987 `filename*` parameter over plain `filename`.
+pub(crate) fn from_content_disposition(value: &str) -> Option<String> {
+    let mut plain = None;
+    let mut extended = None;
+
+    for param in split_params(value) {
+        let Some((key, val)) = param.split_once('=') else {
+            continue;
+        };
+        let key = key.trim().to_ascii_lowercase();
+        let val = unquote(val.trim());
+        match key.as_str() {
+            "filename*" => {
+                // charset'language'percent-encoded-value
+                let encoded = val.splitn(3, '\'').nth(2).unwrap_or(&val);
+                extended = Some(percent_decode(encoded));
+            }
+            "filename" => plain = Some(percent_decode(&val)),
+            _ => {}
+        }
+    }
+
+    extended.or(plain).and_then(|name| strip_dirs(&name))
+}
+
+/// Uses the last non-empty path segment of the URL, ignoring query and fragment.
+pub(crate) fn from_url(url: &str) -> Option<String> {
+    let url = Url::parse(url).ok()?;
+    let segment = url.path_segments()?.rfind(|s| !s.is_empty())?;
+    strip_dirs(&percent_decode(segment))
+}
+
+/// The URL's host and the directories of its path, e.g. `host/a/b` for
+/// `https://host/a/b/file.bin`. Each part is sanitized like a file name and
+/// `.`, `..` and empty segments are dropped, so the result never leaves the
+/// directory it is joined to.
+pub(crate) fn dirs_from_url(url: &str) -> PathBuf {
+    let Ok(url) = Url::parse(url) else {
+        return PathBuf::new();
+    };
+    std::iter::once(host(&url))
+        .chain(dirs(&url))
+        .filter_map(|segment| sanitize(segment.trim()))
+        .collect()
+}
+
+/// The URL's host, with `:port` when the URL gives one.
+pub(crate) fn host(url: &Url) -> String {
+    match (url.host_str(), url.port()) {
+        (Some(host), Some(port)) => format!("{}:{}", host, port),
+        (Some(host), None) => host.to_string(),
+        (None, _) => String::new(),
+    }
+}
+
+/// The decoded directories of the URL's path, leaving out the file name.
+pub(crate) fn dirs(url: &Url) -> Vec<String> {
+    let mut segments: Vec<String> = url.path_segments().map(|s| s.map(percent_decode).collect()).unwrap_or_default();
+    // The last segment is the file name
+    segments.pop();
+    segments
+}
+
+/// `name (n).ext` in the same directory, the way browsers number duplicates.
+pub(crate) fn numbered(path: &Path, n: u32) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let name = match path.extension() {
+        Some(ext) => format!("{} ({}).{}", stem, n, ext.to_string_lossy()),
+        None => format!("{} ({})", stem, n),
+    };
+    path.with_file_name(name)
+}
+
+/// Splits header parameters on `;`, leaving semicolons inside quotes alone.
+fn split_params(value: &str) -> Vec<String> {
+    let mut params = vec![];
+    let mut current = String::new();
+    let mut in_quotes = false;
+    let mut escaped = false;
+
+    for c in value.chars() {
+        match c {
+            _ if escaped => {
+                current.push(c);
+                escaped = false;
+            }
+            '\\' if in_quotes => {
+                current.push(c);
+                escaped = true;
+            }
+            '"' => {
+                current.push(c);
+                in_quotes = !in_quotes;
+            }
+            ';' if !in_quotes => params.push(std::mem::take(&mut current)),
+            _ => current.push(c),
+        }
+    }
+    params.push(current);
+    params
+}
+
+fn unquote(value: &str) -> String {
+    match value.strip_prefix('"').and_then(|v| v.strip_suffix('"')) {
+        Some(inner) => {
+            let mut out = String::new();
+            let mut chars = inner.chars();
+            while let Some(c) = chars.next() {
+                if c == '\\' {
+                    if let Some(next) = chars.next() {
+                        out.push(next);
+                    }
+                } else {
+                    out.push(c);
+                }
+            }
+            out
+        }
+        None => value.to_string(),
+    }
+}
+
+fn percent_decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' && i + 2 < bytes.len() {
+            let hex = std::str::from_utf8(&bytes[i + 1..i + 3]).ok();
+            if let Some(b) = hex.and_then(|h| u8::from_str_radix(h, 16).ok()) {
+                out.push(b);
+                i += 3;
+                continue;
+            }
+        }
+        out.push(bytes[i]);
+        i += 1;
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+/// Keeps only the final path component so a name can't escape the output
+/// directory, then sanitizes it.
+fn strip_dirs(name: &str) -> Option<String> {
+    let name = name.rsplit(['/', '\\']).next()?.trim();
+    sanitize(name)
+}
+
+/// Longest name kept, in bytes: 255 is the usual filesystem limit, less
+/// room for a `.part` suffix and a ` (n)` counter.
+const MAX_NAME_LEN: usize = 240;
+
+/// Windows device names, unusable as a file name even with an extension.
+const RESERVED_NAMES: [&str; 22] = [
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8", "COM9",
+    "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// Makes `name` valid on every common filesystem, since downloads get copied
+/// between machines: characters Windows forbids become `_`, trailing dots
+/// and spaces are dropped, device names like `CON` get a `_` prefix, and
+/// long names are shortened keeping their extension.
+pub(crate) fn sanitize(name: &str) -> Option<String> {
+    let mut name: String = name
+        .chars()
+        .map(|c| if c.is_control() || r#"<>:"/\|?*"#.contains(c) { '_' } else { c })
+        .collect();
+    name.truncate(name.trim_end_matches(['.', ' ']).len());
+
+    if is_reserved(&name) {
+        name.insert(0, '_');
+    }
+
+    let name = shorten(name);
+    match name.as_str() {
+        "" | "." | ".." => None,
+        _ => Some(name),
+    }
+}
+
+/// Whether `name` is a Windows device name like `CON` or `com1.txt`,
+/// which refers to the device whatever the extension.
+fn is_reserved(name: &str) -> bool {
+    let stem = name.split('.').next().unwrap_or_default().trim_end();
+    RESERVED_NAMES.iter().any(|reserved| stem.eq_ignore_ascii_case(reserved))
+}
+
+/// Checks that `name`, given explicitly rather than taken from a URL or
+/// header, is a relative path that stays inside the output directory and
+/// is valid on every common filesystem. Unlike `sanitize` it fails
+/// instead of changing the name.
+pub fn check_name(name: &str) -> Result<(), String> {
+    if name.is_empty() {
+        return Err("the name is empty".to_string());
+    }
+    if name.starts_with(['/', '\\']) {
+        return Err("it must be relative to the output directory".to_string());
+    }
+    for part in name.split(['/', '\\']) {
+        match part {
+            "" | "." | ".." => return Err(format!("{:?} isn't allowed as a path component", part)),
+            _ if part.chars().any(|c| c.is_control() || r#"<>:"|?*"#.contains(c)) => {
+                return Err(format!("{:?} has a character some filesystems don't allow", part));
+            }
+            _ if part.ends_with(['.', ' ']) => return Err(format!("{:?} ends with a dot or space", part)),
+            _ if is_reserved(part) => return Err(format!("{:?} is a reserved device name on Windows", part)),
+            _ if part.len() > MAX_NAME_LEN => return Err(format!("{:?} is longer than {} bytes", part, MAX_NAME_LEN)),
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// Cuts `name` to [`MAX_NAME_LEN`] bytes on a character boundary, keeping a
+/// short extension intact.
+fn shorten(name: String) -> String {
+    if name.len() <= MAX_NAME_LEN {
+        return name;
+    }
+    let ext = match name.rfind('.') {
+        Some(dot) if dot > 0 && name.len() - dot <= 16 => &name[dot..],
+        _ => "",
+    };
+    let mut end = MAX_NAME_LEN - ext.len();
+    while !name.is_char_boundary(end) {
+        end -= 1;
+    }
+    format!("{}{}", &name[..end], ext)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reserved_names_get_a_prefix() {
+        assert_eq!(sanitize("CON").as_deref(), Some("_CON"));
+        assert_eq!(sanitize("nul.txt").as_deref(), Some("_nul.txt"));
+        assert_eq!(sanitize("Com1.tar.gz").as_deref(), Some("_Com1.tar.gz"));
+        assert_eq!(sanitize("CONSOLE.txt").as_deref(), Some("CONSOLE.txt"));
+    }
+
+    #[test]
+    fn forbidden_characters_are_replaced() {
+        assert_eq!(sanitize("a<b>c:d\"e|f?g*h").as_deref(), Some("a_b_c_d_e_f_g_h"));
+        assert_eq!(sanitize("name. . ").as_deref(), Some("name"));
+        assert_eq!(sanitize("tab\there").as_deref(), Some("tab_here"));
+    }
+
+    #[test]
+    fn traversal_is_refused() {
+        assert_eq!(sanitize(".."), None);
+        assert_eq!(sanitize("."), None);
+        assert_eq!(sanitize("../etc/passwd").as_deref(), Some(".._etc_passwd"));
+        assert_eq!(from_content_disposition("attachment; filename=\"../../etc/passwd\"").as_deref(), Some("passwd"));
+        assert_eq!(from_content_disposition("attachment; filename=\"..\\\\boot.ini\"").as_deref(), Some("boot.ini"));
+        assert_eq!(from_url("https://host/a/%2E%2E%2F%2E%2E%2Fpasswd").as_deref(), Some("passwd"));
+        assert_eq!(from_url("https://host/a/.."), None);
+    }
+
+    #[test]
+    fn long_names_are_shortened_keeping_the_extension() {
+        let long = format!("{}.tar.gz", "a".repeat(300));
+        let short = sanitize(&long).unwrap();
+        assert_eq!(short.len(), MAX_NAME_LEN);
+        assert!(short.ends_with("a.gz"), "{}", short);
+
+        // Never cut inside a character
+        let wide = "é".repeat(200);
+        let short = sanitize(&wide).unwrap();
+        assert!(short.len() <= MAX_NAME_LEN && short.chars().all(|c| c == 'é'));
+
+        let exact = "b".repeat(MAX_NAME_LEN);
+        assert_eq!(sanitize(&exact).as_deref(), Some(exact.as_str()));
+    }
+
+    #[test]
+    fn explicit_names_have_the_same_length_limit() {
+        assert!(check_name(&"c".repeat(MAX_NAME_LEN)).is_ok());
+        assert!(check_name(&format!("dir/{}", "c".repeat(MAX_NAME_LEN + 1))).is_err());
+        assert!(check_name("dir/../escape").is_err());
+        assert!(check_name("/absolute").is_err());
+        assert!(check_name("NUL").is_err());
+        assert!(check_name("dir/COM1.txt").is_err());
+        assert!(check_name("console.txt").is_ok());
+    }
+}
@@ -0,0 +1,109 @@
+use std::time::Duration;
+
+/// Which multiple a byte unit steps by.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Units {
+    /// Powers of 1000: KB, MB, GB.
+    #[default]
+    Si,
+    /// Powers of 1024: KiB, MiB, GiB.
+    Binary,
+}
+
+impl Units {
+    fn base(self) -> f64 {
+        match self {
+            Units::Si => 1000.0,
+            Units::Binary => 1024.0,
+        }
+    }
+
+    fn labels(self) -> [&'static str; 5] {
+        match self {
+            Units::Si => ["B", "KB", "MB", "GB", "TB"],
+            Units::Binary => ["B", "KiB", "MiB", "GiB", "TiB"],
+        }
+    }
+}
+
+/// Formats a byte count with the largest unit that keeps it at or above 1,
+/// e.g. `1.50 MB`. Plain bytes are shown without decimals.
+pub fn format_bytes(bytes: u64, units: Units) -> String {
+    format_scaled(bytes as f64, units, "")
+}
+
+/// Formats a speed in bytes per second, e.g. `12.30 MB/s`.
+pub fn format_speed(bytes_per_sec: f64, units: Units) -> String {
+    format_scaled(bytes_per_sec.max(0.0), units, "/s")
+}
+
+fn format_scaled(value: f64, units: Units, suffix: &str) -> String {
+    let base = units.base();
+    let labels = units.labels();
+    let mut value = value;
+    let mut unit = 0;
+    while unit < labels.len() - 1 {
+        // Compare what would be printed, so 999.999 KB becomes 1.00 MB rather than 1000.00 KB
+        let shown = if unit == 0 { value.round() } else { (value * 100.0).round() / 100.0 };
+        if shown < base {
+            break;
+        }
+        value /= base;
+        unit += 1;
+    }
+
+    if unit == 0 {
+        format!("{} {}{}", value.round() as u64, labels[0], suffix)
+    } else {
+        format!("{:.2} {}{}", value, labels[unit], suffix)
+    }
+}
+
+/// Formats an elapsed time as `4.2s`, `3m 07s` or `1h 02m 03s`.
+pub fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    if secs < 60 {
+        format!("{:.1}s", duration.as_secs_f64())
+    } else if secs < 3600 {
+        format!("{}m {:02}s", secs / 60, secs % 60)
+    } else {
+        format!("{}h {:02}m {:02}s", secs / 3600, secs / 60 % 60, secs % 60)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bytes_at_unit_boundaries() {
+        assert_eq!(format_bytes(0, Units::Si), "0 B");
+        assert_eq!(format_bytes(999, Units::Si), "999 B");
+        assert_eq!(format_bytes(1000, Units::Si), "1.00 KB");
+        assert_eq!(format_bytes(1023, Units::Si), "1.02 KB");
+        assert_eq!(format_bytes(1024, Units::Si), "1.02 KB");
+
+        assert_eq!(format_bytes(0, Units::Binary), "0 B");
+        assert_eq!(format_bytes(1000, Units::Binary), "1000 B");
+        assert_eq!(format_bytes(1023, Units::Binary), "1023 B");
+        assert_eq!(format_bytes(1024, Units::Binary), "1.00 KiB");
+        assert_eq!(format_bytes(1024 * 1024, Units::Binary), "1.00 MiB");
+    }
+
+    #[test]
+    fn rounding_moves_up_a_unit() {
+        assert_eq!(format_bytes(999_999, Units::Si), "1.00 MB");
+        assert_eq!(format_bytes(1024 * 1024 - 1, Units::Binary), "1.00 MiB");
+        assert_eq!(format_bytes(u64::MAX, Units::Si), "18446744.07 TB");
+    }
+
+    #[test]
+    fn speeds_at_unit_boundaries() {
+        assert_eq!(format_speed(0.0, Units::Si), "0 B/s");
+        assert_eq!(format_speed(-5.0, Units::Si), "0 B/s");
+        assert_eq!(format_speed(1023.0, Units::Si), "1.02 KB/s");
+        assert_eq!(format_speed(1024.0, Units::Si), "1.02 KB/s");
+        assert_eq!(format_speed(1023.0, Units::Binary), "1023 B/s");
+        assert_eq!(format_speed(1024.0, Units::Binary), "1.00 KiB/s");
+    }
+}
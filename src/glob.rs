@@ -0,0 +1,90 @@
+/// The most URLs one pattern may expand to, so a typo like `[1-99999999]`
+/// fails instead of queueing forever.
+const MAX_URLS: usize = 100_000;
+
+/// Expands curl-style patterns in `url`: `[001-200]` for a numeric range,
+/// zero-padded to the width of its start when that has a leading zero,
+/// `[a-z]` for letters, either with an optional `:STEP`, and `{a,b,c}`
+/// for alternatives. Patterns multiply out in order, the last varying
+/// fastest. A `\` before a bracket or brace makes it literal, and the
+/// brackets around an IPv6 host are left alone.
+pub fn expand(url: &str) -> Result<Vec<String>, String> {
+    let mut urls = vec![String::new()];
+    let mut rest = url;
+    // The brackets of `http://[::1]/` or `http://user@[::1]/`
+    let host_at = url.find("://").map(|at| at + 3);
+    while !rest.is_empty() {
+        let at = url.len() - rest.len();
+        let c = rest.chars().next().unwrap_or_default();
+        let (choices, len) = match c {
+            '\\' if rest[1..].starts_with(['[', ']', '{', '}']) => (vec![rest[1..2].to_string()], 2),
+            '[' if is_ipv6_host(url, at, host_at) => {
+                let len = rest.find(']').map_or(rest.len(), |end| end + 1);
+                (vec![rest[..len].to_string()], len)
+            }
+            '[' => {
+                let end = rest.find(']').ok_or_else(|| format!("{}: unmatched [", url))?;
+                (range(&rest[1..end]).map_err(|e| format!("{}: [{}]: {}", url, &rest[1..end], e))?, end + 1)
+            }
+            '{' => {
+                let end = rest.find('}').ok_or_else(|| format!("{}: unmatched {{", url))?;
+                (rest[1..end].split(',').map(str::to_string).collect(), end + 1)
+            }
+            ']' | '}' => return Err(format!("{}: unmatched {}", url, c)),
+            _ => {
+                let len = rest.find(['\\', '[', ']', '{', '}']).unwrap_or(rest.len()).max(1);
+                (vec![rest[..len].to_string()], len)
+            }
+        };
+        if urls.len().saturating_mul(choices.len()) > MAX_URLS {
+            return Err(format!("{}: expands to more than {} URLs", url, MAX_URLS));
+        }
+        urls = urls.iter().flat_map(|prefix| choices.iter().map(move |choice| format!("{}{}", prefix, choice))).collect();
+        rest = &rest[len..];
+    }
+    Ok(urls)
+}
+
+/// Whether the `[` at `at` opens the host, which `host_at` is the start
+/// of the authority for.
+fn is_ipv6_host(url: &str, at: usize, host_at: Option<usize>) -> bool {
+    let Some(host_at) = host_at else {
+        return false;
+    };
+    at == host_at || (at > host_at && url[host_at..at].ends_with('@') && !url[host_at..at].contains('/'))
+}
+
+/// The values of a range like `1-10`, `001-200:5` or `a-z`.
+fn range(spec: &str) -> Result<Vec<String>, String> {
+    let (bounds, step) = match spec.split_once(':') {
+        Some((bounds, step)) => (bounds, step.parse::<u64>().ok().filter(|&n| n > 0).ok_or("the step must be a positive integer")?),
+        None => (spec, 1),
+    };
+    let (start, end) = bounds.split_once('-').ok_or("expected a range like 1-10 or a-z")?;
+    let step = usize::try_from(step).unwrap_or(usize::MAX);
+
+    if let (Ok(first), Ok(last)) = (start.parse::<u64>(), end.parse::<u64>()) {
+        if first > last {
+            return Err("the range runs backwards".to_string());
+        }
+        if (last - first) / step as u64 >= MAX_URLS as u64 {
+            return Err(format!("more than {} values", MAX_URLS));
+        }
+        let width = if start.len() > 1 && start.starts_with('0') { start.len() } else { 0 };
+        return Ok((first..=last).step_by(step).map(|n| format!("{:0width$}", n, width = width)).collect());
+    }
+
+    let letter = |s: &str| {
+        let mut chars = s.chars();
+        chars.next().filter(|c| c.is_ascii_alphabetic() && chars.next().is_none())
+    };
+    match (letter(start), letter(end)) {
+        (Some(first), Some(last)) if first.is_ascii_lowercase() == last.is_ascii_lowercase() => {
+            if first > last {
+                return Err("the range runs backwards".to_string());
+            }
+            Ok((first..=last).step_by(step).map(String::from).collect())
+        }
+        _ => Err("expected numbers or letters of the same case on both sides".to_string()),
+    }
+}
@@ -0,0 +1,71 @@
+use reqwest::Url;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use tokio::sync::futures::Notified;
+use tokio::sync::{Notify, OwnedSemaphorePermit, Semaphore};
+
+/// A semaphore per host, so no host gets more than `max` downloads at once.
+///
+/// Hosts are keyed by authority (`host:port`); URLs that don't parse aren't
+/// limited.
+pub(crate) struct HostLimiter {
+    max: usize,
+    hosts: Mutex<HashMap<String, Arc<Semaphore>>>,
+    /// Woken whenever a permit is dropped.
+    released: Notify,
+}
+
+/// A download slot on a host, given back on drop.
+pub(crate) struct HostPermit {
+    permit: Option<OwnedSemaphorePermit>,
+    limiter: Arc<HostLimiter>,
+}
+
+impl HostLimiter {
+    pub(crate) fn new(max: usize) -> Self {
+        HostLimiter {
+            max: max.max(1),
+            hosts: Mutex::new(HashMap::new()),
+            released: Notify::new(),
+        }
+    }
+
+    /// Waits for a slot on `url`'s host.
+    pub(crate) async fn acquire(self: &Arc<Self>, url: &str) -> HostPermit {
+        let permit = match self.semaphore(url) {
+            // The semaphores are never closed
+            Some(semaphore) => semaphore.acquire_owned().await.ok(),
+            None => None,
+        };
+        HostPermit { permit, limiter: self.clone() }
+    }
+
+    /// A slot on `url`'s host if one is free right now.
+    pub(crate) fn try_acquire(self: &Arc<Self>, url: &str) -> Option<HostPermit> {
+        let permit = match self.semaphore(url) {
+            Some(semaphore) => Some(semaphore.try_acquire_owned().ok()?),
+            None => None,
+        };
+        Some(HostPermit { permit, limiter: self.clone() })
+    }
+
+    /// Resolves once a permit is dropped after this is called.
+    pub(crate) fn released(&self) -> Notified<'_> {
+        self.released.notified()
+    }
+
+    fn semaphore(&self, url: &str) -> Option<Arc<Semaphore>> {
+        let url = Url::parse(url).ok()?;
+        let host = format!("{}:{}", url.host_str()?, url.port_or_known_default()?);
+        let mut hosts = self.hosts.lock().unwrap_or_else(|e| e.into_inner());
+        Some(hosts.entry(host).or_insert_with(|| Arc::new(Semaphore::new(self.max))).clone())
+    }
+}
+
+impl Drop for HostPermit {
+    fn drop(&mut self) {
+        // Give the slot back before waking anyone to take it
+        self.permit.take();
+        self.limiter.released.notify_waiters();
+    }
+}
@@ -0,0 +1,68 @@
+use reqwest::Url;
+use rs_downloader::check_name;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+
+/// A URL from an input file, with the priority given before it and the
+/// name to save it as, if any.
+pub struct Entry {
+    pub url: String,
+    pub priority: Option<i32>,
+    pub name: Option<String>,
+    /// 1-based, for error messages.
+    pub line: usize,
+}
+
+/// Reads one URL per line from `path`, or from stdin when `path` is `-`.
+/// A line may start with a priority, as in `10 https://...`, and name the
+/// file as `URL<tab>NAME` or `NAME = URL`. Blank lines and lines starting
+/// with `#` are skipped.
+pub fn read_urls(path: &str) -> Result<Vec<Entry>, String> {
+    let reader: Box<dyn BufRead> = if path == "-" {
+        Box::new(BufReader::new(io::stdin()))
+    } else {
+        let file = File::open(path).map_err(|e| format!("{}: {}", path, e))?;
+        Box::new(BufReader::new(file))
+    };
+
+    let mut urls = vec![];
+    for (index, line) in reader.lines().enumerate() {
+        let line = line.map_err(|e| format!("{}: {}", path, e))?;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let (priority, line) = match line.split_once(char::is_whitespace) {
+            Some((priority, url)) if priority.parse::<i32>().is_ok() => (priority.parse().ok(), url.trim_start()),
+            _ => (None, line),
+        };
+        let (line, name) = split_name(line);
+        if let Err(e) = Url::parse(line) {
+            return Err(format!("{}:{}: invalid URL {:?}: {}", path, index + 1, line, e));
+        }
+        if let Some(name) = name {
+            check_name(name).map_err(|e| format!("{}:{}: can't save as {:?}: {}", path, index + 1, name, e))?;
+        }
+        urls.push(Entry {
+            url: line.to_string(),
+            priority,
+            name: name.map(str::to_string),
+            line: index + 1,
+        });
+    }
+    Ok(urls)
+}
+
+/// Splits `URL<tab>NAME` or `NAME = URL` into the URL and name. An `=` only
+/// separates a name when what precedes it isn't part of a URL, since query
+/// strings have them too.
+fn split_name(line: &str) -> (&str, Option<&str>) {
+    if let Some((url, name)) = line.split_once('\t') {
+        return (url.trim(), Some(name.trim()));
+    }
+    match line.split_once('=') {
+        Some((name, url)) if !name.contains("://") && url.contains("://") => (url.trim(), Some(name.trim())),
+        _ => (line, None),
+    }
+}
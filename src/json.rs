@@ -0,0 +1,151 @@
+use crate::error::DownloadError;
+use crate::progress::{Tally, MIN_PROGRESS_INTERVAL};
+use crate::reporter::ProgressReporter;
+use crate::stats::DownloadRecord;
+use std::fmt::Write as _;
+use std::io::{stdout, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+use tokio::time;
+
+/// A [`ProgressReporter`] that prints newline-delimited JSON events to
+/// stdout, for other programs to parse.
+///
+/// Every event is an object with an `"event"` field: `start`, `complete`
+/// and `error` as they happen, and `progress` for each active download
+/// every time [`report_json_progress`] ticks.
+#[derive(Default)]
+pub struct JsonReporter {
+    tally: Mutex<Tally>,
+}
+
+impl JsonReporter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn tally(&self) -> MutexGuard<'_, Tally> {
+        self.tally.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl ProgressReporter for JsonReporter {
+    fn on_start(&self, url: &str, path: &Path, downloaded: u64, total: Option<u64>) {
+        self.tally().start(url, path, downloaded, total);
+        emit(format!(
+            r#"{{"event":"start","url":{},"path":{},"bytes":{},"total":{}}}"#,
+            string(url),
+            string(&path.to_string_lossy()),
+            downloaded,
+            number(total)
+        ));
+    }
+
+    fn on_progress(&self, url: &str, downloaded: u64) {
+        self.tally().progress(url, downloaded);
+    }
+
+    fn on_complete(&self, url: &str, path: &Path) {
+        self.tally().complete(url);
+        let bytes = path.metadata().map(|m| m.len()).unwrap_or(0);
+        emit(format!(
+            r#"{{"event":"complete","url":{},"path":{},"bytes":{}}}"#,
+            string(url),
+            string(&path.to_string_lossy()),
+            bytes
+        ));
+    }
+
+    fn on_error(&self, url: &str, error: &DownloadError) {
+        self.tally().fail(url);
+        let status = match error {
+            DownloadError::HttpStatus(status, _) | DownloadError::Throttled { status, .. } => Some(status.as_u16() as u64),
+            _ => None,
+        };
+        emit(format!(
+            r#"{{"event":"error","url":{},"status":{},"error":{}}}"#,
+            string(url),
+            number(status),
+            string(&error.to_string())
+        ));
+    }
+}
+
+/// Prints a `progress` event for every active download of `reporter` every
+/// `interval` (at least [`MIN_PROGRESS_INTERVAL`]) until aborted. Returns
+/// at once without one, leaving only the start, complete and error events.
+pub async fn report_json_progress(reporter: Arc<JsonReporter>, interval: Option<Duration>) {
+    let Some(interval) = interval else {
+        return;
+    };
+    loop {
+        time::sleep(interval.max(MIN_PROGRESS_INTERVAL)).await;
+        let tally = reporter.tally();
+        for file in tally.stats.files.iter().filter(|f| !f.finished) {
+            emit(format!(
+                r#"{{"event":"progress","url":{},"bytes":{},"total":{},"speed":{:.0}}}"#,
+                string(&file.url),
+                file.bytes,
+                number(Some(file.size).filter(|&size| size > 0)),
+                file.current_speed()
+            ));
+        }
+    }
+}
+
+/// Prints a `skipped` event per file that was already there, then a
+/// `summary` event with the counts.
+pub fn print_json_summary(records: &[DownloadRecord]) {
+    for record in records.iter().filter(|r| r.skipped) {
+        let path = record.path.as_deref().unwrap_or(Path::new(""));
+        emit(format!(
+            r#"{{"event":"skipped","url":{},"path":{}}}"#,
+            string(&record.url),
+            string(&path.to_string_lossy())
+        ));
+    }
+
+    let count = |keep: fn(&DownloadRecord) -> bool| records.iter().filter(|r| keep(r)).count();
+    emit(format!(
+        r#"{{"event":"summary","succeeded":{},"skipped":{},"current":{},"failed":{},"cancelled":{}}}"#,
+        count(|r| r.error.is_none() && !r.skipped),
+        count(|r| r.skipped),
+        count(|r| r.current),
+        count(|r| r.error.is_some() && !r.cancelled),
+        count(|r| r.cancelled)
+    ));
+}
+
+/// Writes one event line, ignoring errors such as a closed pipe rather than
+/// panicking like `println!`.
+fn emit(line: String) {
+    let mut out = stdout().lock();
+    let _ = writeln!(out, "{}", line);
+    let _ = out.flush();
+}
+
+fn number(value: Option<u64>) -> String {
+    value.map_or_else(|| "null".to_string(), |n| n.to_string())
+}
+
+/// `value` as a quoted JSON string.
+fn string(value: &str) -> String {
+    let mut out = String::with_capacity(value.len() + 2);
+    out.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if c.is_control() => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
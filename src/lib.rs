@@ -0,0 +1,50 @@
+mod checksum;
+mod cookies;
+mod date;
+mod decode;
+mod digest;
+mod downloader;
+mod error;
+mod events;
+mod exec;
+mod filename;
+mod format;
+mod host_limit;
+mod json;
+#[cfg(feature = "metrics")]
+mod metrics;
+mod open_files;
+mod progress;
+mod proxy;
+mod rate_limit;
+mod reporter;
+mod resolve;
+mod session;
+mod sidecar;
+mod stats;
+mod template;
+mod tls;
+#[cfg(feature = "progress-ui")]
+mod tui;
+
+pub use date::parse_date;
+pub use downloader::{Downloader, DownloaderBuilder, Probe, Referer, SegmentStrategy};
+pub use error::DownloadError;
+pub use events::DownloadEvent;
+pub use filename::check_name;
+pub use format::{format_bytes, format_duration, format_speed, Units};
+pub use json::{print_json_summary, report_json_progress, JsonReporter};
+#[cfg(feature = "metrics")]
+pub use metrics::serve_metrics;
+pub use progress::{
+    color_enabled, update_progress_and_speed, ProgressConfig, ProgressMode, TerminalReporter,
+    DEFAULT_PROGRESS_INTERVAL, MIN_PROGRESS_INTERVAL,
+};
+pub use rate_limit::parse_rate;
+pub use reporter::ProgressReporter;
+pub use resolve::IpFamily;
+pub use session::session_urls;
+#[cfg(feature = "progress-ui")]
+pub use tui::run_tui;
+pub use stats::{DownloadRecord, DownloadStats, FileProgress, SpeedWindow};
+pub use template::OutputTemplate;
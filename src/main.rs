@@ -1,167 +1,514 @@
-use reqwest::Client;
-use std::fs::File;
-use std::io::Write;
-use std::path::Path;
+mod cli;
+mod config;
+mod glob;
+mod input;
+mod metalink;
+mod notify;
+mod report;
+mod summary;
+
+use rs_downloader::{
+    format_bytes, print_json_summary, report_json_progress, update_progress_and_speed, DownloadError, Downloader, JsonReporter,
+    ProgressConfig, ProgressMode, TerminalReporter,
+};
+use log::{debug, Level, LevelFilter};
 use std::error::Error;
 use std::env;
-use futures_util::StreamExt;
-use tokio::task;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
-use std::time::{Duration, Instant};
-use tokio::time;
-use tokio::sync::Mutex;
-use crossterm::{
-    execute,
-    style::{Color, Print, ResetColor, SetForegroundColor},
-    terminal::{Clear, ClearType},
-    cursor::MoveTo,
-};
-use std::io::stdout;
-
-#[derive(Debug)]
-enum DownloadError {
-    ReqwestError(reqwest::Error),
-    IoError(std::io::Error),
-    Other(String),
-}
+use tokio::{signal, task, time};
+use std::io::{self, Write};
 
-impl std::error::Error for DownloadError {}
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn Error>> {
+    let options = cli::parse_args(env::args());
+    let urls = options.urls;
+    let logging = init_logging(options.verbose, options.quiet || options.json);
 
-impl std::fmt::Display for DownloadError {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            DownloadError::ReqwestError(e) => write!(f, "Reqwest error: {}", e),
-            DownloadError::IoError(e) => write!(f, "IO error: {}", e),
-            DownloadError::Other(s) => write!(f, "Other error: {}", s),
+    let mut builder = Downloader::builder()
+        .max_concurrent(options.max_concurrent)
+        .retries(options.retries)
+        .connections(options.connections)
+        .segment_strategy(options.segment_strategy)
+        .preallocate(options.preallocate)
+        .timestamping(options.timestamping)
+        .force(options.force)
+        .if_changed(options.if_changed)
+        .only_newer(options.newer_only)
+        .decompress(options.decompress)
+        .decompress_gzip(options.decompress_gzip)
+        .fail_on_empty(options.fail_on_empty);
+    if let Some(dir) = options.output_dir {
+        builder = builder.output_dir(dir);
+    }
+    if let Some(n) = options.max_per_host {
+        builder = builder.max_per_host(n);
+    }
+    if let Some(since) = options.if_modified_since {
+        builder = builder.if_modified_since(since);
+    }
+    if options.preserve_path {
+        builder = builder.preserve_path(true);
+    }
+    if let Some(template) = options.output_template {
+        builder = builder.output_template(template);
+    }
+    if let Some(n) = options.max_redirects {
+        builder = builder.max_redirects(n);
+    }
+    if options.timing {
+        builder = builder.timing(true);
+    }
+    if let Some(size) = options.buffer_size {
+        builder = builder.buffer_size(size);
+    }
+    if let Some(n) = options.max_open_files {
+        builder = builder.max_open_files(n);
+    }
+    if let Some(mode) = options.chmod {
+        if !cfg!(unix) {
+            eprintln!("Warning: --chmod only applies on Unix and is ignored here");
         }
+        builder = builder.chmod(mode);
     }
-}
+    if let Some(expected) = options.expect_content_type {
+        builder = builder.expect_content_type(expected);
+    }
+    if let Some(size) = options.max_size {
+        builder = builder.max_size(size);
+    }
+    if let Some(bytes) = options.max_total_bytes {
+        builder = builder.max_total_bytes(bytes);
+    }
+    if let Some(rate) = options.limit_rate {
+        builder = builder.limit_rate(rate);
+    }
+    if let Some(rate) = options.limit_rate_per_file {
+        builder = builder.limit_rate_per_file(rate);
+    }
+    if let Some(timeout) = options.timeout {
+        builder = builder.timeout(timeout);
+    }
+    if let Some(idle_timeout) = options.idle_timeout {
+        builder = builder.idle_timeout(idle_timeout);
+    }
+    if let Some(limit) = options.per_file_timeout {
+        builder = builder.file_timeout(limit);
+    }
+    if let Some(delay) = options.wait {
+        builder = builder.wait(delay, options.random_wait);
+    }
+    if let Some(rate) = options.min_speed {
+        builder = builder.min_speed(rate, options.min_speed_time);
+    }
+    if let Some(user_agent) = options.user_agent {
+        builder = builder.user_agent(user_agent);
+    }
+    if let Some(referer) = options.referer {
+        builder = builder.referer(referer);
+    }
+    if let Some(origin) = options.origin {
+        builder = builder.origin(origin);
+    }
+    if let Some(command) = &options.exec {
+        builder = builder.exec(command);
+    }
+    for (name, value) in options.headers {
+        builder = builder.header(name, value);
+    }
+    if let Some((user, password)) = options.user {
+        builder = if options.digest {
+            builder.digest_auth(user, password.unwrap_or_default())
+        } else {
+            builder.basic_auth(user, password)
+        };
+    }
+    if let Some(token) = options.bearer {
+        builder = builder.bearer_auth(token);
+    }
+    for (name, value) in options.cookies {
+        builder = builder.cookie(name, value);
+    }
+    if let Some(path) = &options.cookie_jar {
+        builder = builder.cookie_jar(path);
+    }
+    if let Some(path) = &options.session {
+        builder = builder.session(path);
+    }
+    if let Some(proxy) = options.proxy {
+        builder = builder.proxy(proxy);
+    }
+    if let Some(family) = options.ip_family {
+        builder = builder.ip_family(family, options.ip_fallback);
+    }
+    if let Some(max) = options.pool_max_idle_per_host {
+        builder = builder.pool_max_idle_per_host(max);
+    }
+    if let Some(timeout) = options.pool_idle_timeout {
+        builder = builder.pool_idle_timeout(timeout);
+    }
+    if let Some(interval) = options.tcp_keepalive {
+        builder = builder.tcp_keepalive(interval);
+    }
+    for path in options.cacerts {
+        builder = builder.ca_cert(path);
+    }
+    if let (Some(cert), Some(key)) = (options.cert, options.key) {
+        builder = builder.client_cert(cert, key);
+    }
+    if options.notify && !cfg!(feature = "notify") {
+        eprintln!("Warning: this build doesn't include desktop notifications, so --notify does nothing");
+    }
+    if options.insecure {
+        eprintln!("Warning: --insecure turns off certificate checks, so downloads can be read or tampered with in transit");
+        builder = builder.danger_accept_invalid_certs(true);
+    }
+    if let Some(name) = options.output_name {
+        builder = builder.file_name(urls[0].clone(), name);
+    }
+    for (url, name) in options.file_names {
+        builder = builder.file_name(url, name);
+    }
+    if let Some(hex) = options.sha256 {
+        for url in &urls {
+            builder = builder.sha256(url.clone(), hex.clone());
+        }
+    }
+    for (url, priority) in options.priorities {
+        builder = builder.priority(url, priority);
+    }
+    for (url, mirror) in options.mirrors {
+        builder = builder.mirror(url, mirror);
+    }
+    if let Some(statuses) = options.retry_statuses {
+        builder = builder.retry_on_status(statuses);
+    }
+    for (url, hex) in options.checksums {
+        builder = builder.sha256(url, hex);
+    }
+    for (url, size) in options.sizes {
+        builder = builder.expected_size(url, size);
+    }
+    if options.verify_sidecar || options.require_checksum {
+        builder = builder.verify_sidecar(options.require_checksum);
+    }
+    let terminal = Arc::new(TerminalReporter::new());
+    let json = Arc::new(JsonReporter::new());
+    if options.json {
+        builder = builder.reporter(json.clone());
+    } else if !options.quiet && !options.to_stdout {
+        builder = builder.reporter(terminal.clone());
+    }
+    let downloader = builder.build().unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    });
 
-impl From<reqwest::Error> for DownloadError {
-    fn from(err: reqwest::Error) -> Self {
-        DownloadError::ReqwestError(err)
+    if options.to_stdout {
+        match downloader.download_to_stdout(&urls[0]).await {
+            Ok(_) => {}
+            // The reader went away, as with `| head`; nothing to report
+            Err(DownloadError::IoError(e)) if e.kind() == io::ErrorKind::BrokenPipe => std::process::exit(1),
+            Err(e) => {
+                eprintln!("{}: {}", urls[0], e);
+                save_state(&downloader, options.cookie_jar.as_deref(), options.session.as_deref());
+                std::process::exit(1);
+            }
+        }
+        save_state(&downloader, options.cookie_jar.as_deref(), options.session.as_deref());
+        return Ok(());
     }
-}
 
-impl From<std::io::Error> for DownloadError {
-    fn from(err: std::io::Error) -> Self {
-        DownloadError::IoError(err)
+    if options.dry_run {
+        let probes = downloader.probe_many(&urls).await;
+        summary::print_plan(&urls, &probes, options.units);
+        let failures = probes.iter().filter(|p| p.is_err()).count();
+        std::process::exit(failures.min(255) as i32);
     }
-}
 
-struct DownloadStats {
-    total_bytes: u64,
-    total_size: u64,
-    start_time: Instant,
-}
+    if options.spider {
+        let probes = downloader.probe_many(&urls).await;
+        summary::print_spider(&urls, &probes);
+        let broken = probes.iter().filter(|p| p.is_err()).count();
+        std::process::exit(broken.min(255) as i32);
+    }
 
-async fn download_file(client: &Client, url: &str, file_path: &Path, stats: Arc<Mutex<DownloadStats>>) -> Result<(), DownloadError> {
-    let response = client.get(url).send().await?;
-    let total_size = response.content_length().unwrap_or(0);
+    if options.head_first {
+        let probes = downloader.probe_many(&urls).await;
+        let failures = probes.iter().filter(|p| p.is_err()).count();
+        summary::print_unreachable(&urls, &probes, options.fail_fast);
+        if failures > 0 && options.fail_fast {
+            save_state(&downloader, options.cookie_jar.as_deref(), options.session.as_deref());
+            std::process::exit(failures.min(255) as i32);
+        }
+    }
 
-    {
-        let mut stats = stats.lock().await;
-        stats.total_size += total_size;
+    if options.continue_partial && !options.json && !options.quiet {
+        let partials: Vec<_> = urls
+            .iter()
+            .filter_map(|url| downloader.partial(url).map(|(path, len)| (url.as_str(), path, len)))
+            .collect();
+        summary::print_continuing(&partials, urls.len(), options.units);
     }
 
-    let mut file = File::create(file_path)?;
-    let mut stream = response.bytes_stream();
-    while let Some(item) = stream.next().await {
-        let chunk = item?;
-        file.write_all(&chunk)?;
-        
-        let mut stats = stats.lock().await;
-        stats.total_bytes += chunk.len() as u64;
+    let metrics_handle = match &options.metrics_addr {
+        Some(addr) => serve_metrics(addr, &downloader),
+        None => None,
+    };
+
+    let mut progress_config = ProgressConfig {
+        units: options.units,
+        interval: options.progress_interval,
+        verbose: options.verbose > 0,
+        ..ProgressConfig::detect()
+    };
+    match options.progress_mode {
+        Some(mode) => progress_config.mode = mode,
+        // Log lines would land in the middle of the redrawn block
+        None if logging => progress_config.mode = ProgressMode::Plain,
+        None => {}
+    }
+    if options.progress_mode == Some(ProgressMode::Interactive) && !cfg!(feature = "progress-ui") {
+        eprintln!("Warning: this build can't redraw progress in place, so --progress interactive shows plain progress");
     }
+    if options.tui && !cfg!(feature = "progress-ui") {
+        eprintln!("Warning: this build doesn't include the full-screen display, so --tui shows plain progress");
+    }
+    // Without a terminal to take over, --tui falls back to plain lines
+    let tui = cfg!(feature = "progress-ui") && options.tui && progress_config.mode == ProgressMode::Interactive;
+    let progress_handle = if options.json {
+        Some(task::spawn(report_json_progress(json, options.progress_interval)))
+    } else if options.quiet {
+        None
+    } else if tui {
+        Some(spawn_tui(terminal, progress_config))
+    } else {
+        let idle = options.pool_max_idle_per_host.unwrap_or(10);
+        let _ = writeln!(io::stdout(), "Maximum idle connections per host: {}", idle);
 
-    Ok(())
-}
+        Some(task::spawn(async move {
+            // Progress just stops if stdout goes away; the summary still reports the outcome
+            if let Err(e) = update_progress_and_speed(terminal, progress_config).await {
+                debug!("Stopped showing progress: {}", e);
+            }
+        }))
+    };
 
-async fn update_progress_and_speed(stats: Arc<Mutex<DownloadStats>>) {
-    loop {
-        time::sleep(Duration::from_millis(500)).await;
-        let stats = stats.lock().await;
-        let elapsed = stats.start_time.elapsed().as_secs_f64();
-        let speed = (stats.total_bytes as f64) / elapsed / 1_000_000.0; // MB/s
-        
-        let progress = if stats.total_size > 0 {
-            (stats.total_bytes as f64 / stats.total_size as f64) * 100.0
-        } else {
-            0.0
-        };
-        
-        execute!(
-            stdout(),
-            MoveTo(0, 0),
-            Clear(ClearType::CurrentLine),
-            SetForegroundColor(Color::Green),
-            Print(format!("Total progress: {:.2}%", progress)),
-            ResetColor,
-            MoveTo(0, 1),
-            Clear(ClearType::CurrentLine),
-            SetForegroundColor(Color::Blue),
-            Print(format!("Current download speed: {:.2} MB/s", speed)),
-            ResetColor
-        ).unwrap();
-        
-        stdout().flush().unwrap();
+    let canceller = downloader.clone();
+    let interrupt_handle = task::spawn(async move {
+        if signal::ctrl_c().await.is_ok() {
+            eprintln!("Interrupted, cancelling downloads (press Ctrl-C again to quit now)");
+            canceller.cancel();
+            if signal::ctrl_c().await.is_ok() {
+                if tui {
+                    // Exiting skips the TUI's own cleanup
+                    leave_tui();
+                }
+                std::process::exit(130);
+            }
+        }
+    });
+
+    let timed_out = Arc::new(AtomicBool::new(false));
+    let timer_handle = options.max_total_time.map(|limit| {
+        let (canceller, timed_out) = (downloader.clone(), timed_out.clone());
+        task::spawn(async move {
+            time::sleep(limit).await;
+            eprintln!("Reached --max-total-time of {:?}, cancelling downloads", limit);
+            timed_out.store(true, Ordering::Relaxed);
+            canceller.cancel();
+        })
+    });
+
+    let mut results = downloader.download_many(&urls).await;
+    if options.retry_failed {
+        retry_failed(&downloader, &urls, &mut results, options.json).await;
+    }
+    save_state(&downloader, options.cookie_jar.as_deref(), options.session.as_deref());
+    interrupt_handle.abort();
+    if let Some(handle) = timer_handle {
+        handle.abort();
+    }
+    if let Some(handle) = metrics_handle {
+        handle.abort();
     }
-}
 
-#[tokio::main]
-async fn main() -> Result<(), Box<dyn Error>> {
-    let args: Vec<String> = env::args().collect();
-    if args.len() < 2 {
-        eprintln!("Usage: {} <url1> [url2] [url3] ...", args[0]);
-        std::process::exit(1);
+    // Stop the progress update task
+    if let Some(handle) = progress_handle {
+        handle.abort();
+        // Dropping the task is what restores the screen
+        let _ = handle.await;
+        if !options.json && !tui && progress_config.mode == ProgressMode::Interactive {
+            clear_file_lines()?;
+        }
     }
 
-    let client = Client::builder()
-        .pool_max_idle_per_host(10)
-        .build()?;
+    let stats = downloader.stats();
+    let stats = stats.lock().await;
+    let failures = results.iter().filter(|r| r.is_err()).count();
+    if options.json {
+        print_json_summary(&stats.records);
+    } else if options.quiet {
+        if failures > 0 {
+            summary::print_failures(&stats.records);
+        }
+    } else {
+        if failures == 0 {
+            println!("All downloads completed.");
+        }
+        summary::print_summary(&stats.records, stats.start_time.elapsed(), options.units);
+    }
+
+    if let Some(path) = &options.report {
+        if let Err(e) = report::write_csv(path, &stats.records) {
+            eprintln!("Couldn't write the report to {}: {}", path, e);
+        }
+    }
 
-    println!("Maximum idle connections per host: 10");
+    let cancelled = stats.records.iter().any(|r| r.cancelled);
+    if options.notify && !cancelled {
+        notify::notify_finished(&stats.records, options.units);
+    }
 
-    let stats = Arc::new(Mutex::new(DownloadStats {
-        total_bytes: 0,
-        total_size: 0,
-        start_time: Instant::now(),
-    }));
+    if cancelled {
+        let timed_out = timed_out.load(Ordering::Relaxed);
+        let capped = downloader.byte_limit_hit();
+        if !options.json {
+            let heading = match (timed_out, capped) {
+                (true, _) => "Cut off by --max-total-time",
+                (false, true) => "Cut off by --max-total-bytes",
+                (false, false) => "Cancelled",
+            };
+            summary::print_cancelled(&stats.records, heading);
+            if capped {
+                println!(
+                    "Received {} this run, over the {} allowed.",
+                    format_bytes(stats.received, options.units),
+                    format_bytes(options.max_total_bytes.unwrap_or_default(), options.units)
+                );
+            }
+        }
+        // 124 is what timeout(1) exits with
+        std::process::exit(if timed_out || capped { 124 } else { 130 });
+    }
+    if failures > 0 {
+        // Exit codes wrap at 256, so cap rather than risk reporting success
+        std::process::exit(failures.min(255) as i32);
+    }
 
-    let progress_stats = stats.clone();
-    let progress_handle = task::spawn(async move {
-        update_progress_and_speed(progress_stats).await;
-    });
+    Ok(())
+}
 
-    let mut handles = vec![];
+/// Writes the cookies back to --cookie-jar and the last progress to
+/// --session, for those given.
+fn save_state(downloader: &Downloader, cookie_jar: Option<&str>, session: Option<&str>) {
+    if let Err(e) = downloader.save_cookies() {
+        eprintln!("Couldn't save cookies to {}: {}", cookie_jar.unwrap_or_default(), e);
+    }
+    if let Err(e) = downloader.save_session() {
+        eprintln!("Couldn't save the session to {}: {}", session.unwrap_or_default(), e);
+    }
+}
 
-    for url in args.into_iter().skip(1) {
-        let file_name = url.split('/').last().unwrap_or("downloaded_file").to_string();
-        let file_path = Path::new(&file_name).to_path_buf();
+/// Downloads the URLs that failed once more, replacing their results and
+/// their records from the first pass so the summary shows the outcome.
+async fn retry_failed(downloader: &Downloader, urls: &[String], results: &mut [Result<PathBuf, DownloadError>], json: bool) {
+    let failed: Vec<usize> = (0..urls.len())
+        .filter(|&i| matches!(&results[i], Err(e) if !matches!(e, DownloadError::Cancelled)))
+        .collect();
+    if failed.is_empty() {
+        return;
+    }
 
-        let client = client.clone();
-        let stats = stats.clone();
-        
-        let handle = task::spawn(async move {
-            download_file(&client, &url, &file_path, stats).await
-        });
-        handles.push(handle);
+    if !json {
+        eprintln!("Retrying {} failed download(s)", failed.len());
     }
+    let retry_urls: Vec<String> = failed.iter().map(|&i| urls[i].clone()).collect();
+    downloader
+        .stats()
+        .lock()
+        .await
+        .records
+        .retain(|r| r.error.is_none() || r.cancelled || !retry_urls.contains(&r.url));
+    for (index, result) in failed.into_iter().zip(downloader.download_many(&retry_urls).await) {
+        results[index] = result;
+    }
+}
 
-    for handle in handles {
-        handle.await??;
+/// Sends log output to stderr, from `RUST_LOG` if set or else from the
+/// number of -v flags. Without either, the downloader's notes like
+/// "Skipping ..." are shown, or only its warnings when `quiet`. Returns
+/// whether debug output will be logged.
+fn init_logging(verbose: u8, quiet: bool) -> bool {
+    let mut builder = env_logger::Builder::new();
+    if let Ok(filter) = env::var("RUST_LOG") {
+        builder.parse_filters(&filter);
+    } else {
+        let level = match verbose {
+            0 if quiet => LevelFilter::Warn,
+            0 => LevelFilter::Info,
+            1 => LevelFilter::Debug,
+            _ => LevelFilter::Trace,
+        };
+        builder.filter_module("rs_downloader", level);
     }
+    // Notes and warnings read as messages rather than log lines
+    builder.format(|out, record| match record.level() {
+        Level::Info => writeln!(out, "{}", record.args()),
+        Level::Warn => writeln!(out, "Warning: {}", record.args()),
+        level => writeln!(out, "[{} {} {}] {}", out.timestamp(), level, record.target(), record.args()),
+    });
+    builder.init();
+    log::max_level() > LevelFilter::Info
+}
 
-    // Stop the progress update task
-    progress_handle.abort();
+#[cfg(feature = "progress-ui")]
+fn spawn_tui(reporter: Arc<TerminalReporter>, config: ProgressConfig) -> task::JoinHandle<()> {
+    task::spawn(rs_downloader::run_tui(reporter, config))
+}
+
+#[cfg(not(feature = "progress-ui"))]
+fn spawn_tui(reporter: Arc<TerminalReporter>, config: ProgressConfig) -> task::JoinHandle<()> {
+    task::spawn(async move {
+        let _ = update_progress_and_speed(reporter, config).await;
+    })
+}
+
+#[cfg(feature = "progress-ui")]
+fn leave_tui() {
+    use crossterm::{cursor::Show, execute, terminal::LeaveAlternateScreen};
+    let _ = execute!(io::stdout(), LeaveAlternateScreen, Show);
+}
 
-    execute!(
-        stdout(),
-        MoveTo(0, 3),
-        Clear(ClearType::FromCursorDown)
-    )?;
+#[cfg(not(feature = "progress-ui"))]
+fn leave_tui() {}
 
-    println!("All downloads completed.");
+/// Clears the per-file lines the interactive display leaves below the totals.
+#[cfg(feature = "progress-ui")]
+fn clear_file_lines() -> io::Result<()> {
+    use crossterm::{cursor::MoveTo, execute, terminal::{Clear, ClearType}};
+    execute!(io::stdout(), MoveTo(0, 2), Clear(ClearType::FromCursorDown))
+}
 
+#[cfg(not(feature = "progress-ui"))]
+fn clear_file_lines() -> io::Result<()> {
     Ok(())
-}
\ No newline at end of file
+}
+
+/// Starts the --metrics-addr endpoint, exiting if it can't listen.
+#[cfg(feature = "metrics")]
+fn serve_metrics(addr: &str, downloader: &Downloader) -> Option<task::JoinHandle<()>> {
+    let handle = rs_downloader::serve_metrics(addr, downloader.stats()).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    });
+    debug!("Serving metrics at http://{}/metrics", addr);
+    Some(handle)
+}
+
+#[cfg(not(feature = "metrics"))]
+fn serve_metrics(_addr: &str, _downloader: &Downloader) -> Option<task::JoinHandle<()>> {
+    eprintln!("Warning: this build doesn't include the metrics endpoint, so --metrics-addr is ignored");
+    None
+}
@@ -0,0 +1,338 @@
+use reqwest::Url;
+use std::fs;
+
+/// A file described by a metalink: where to save it, the URLs it can be
+/// fetched from, best first, and what to check it against.
+pub struct MetalinkFile {
+    pub name: String,
+    pub urls: Vec<String>,
+    pub size: Option<u64>,
+    /// Lowercase hex.
+    pub sha256: Option<String>,
+    /// The names of the other whole-file hash types listed, which can't be
+    /// checked.
+    pub other_hashes: Vec<String>,
+}
+
+/// Reads the files from a Metalink 4 (`.meta4`, RFC 5854) or Metalink 3
+/// (`.metalink`) document. Only `http` and `https` URLs are kept, ordered
+/// by their `priority` (4) or `preference` (3).
+pub fn read(path: &str) -> Result<Vec<MetalinkFile>, String> {
+    let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+    let root = parse(&text).map_err(|e| format!("{}: malformed metalink: {}", path, e))?;
+    if root.name != "metalink" {
+        return Err(format!("{}: not a metalink (the root element is <{}>)", path, root.name));
+    }
+
+    let mut files = vec![];
+    for file in root.descendants("file") {
+        let name = file.attr("name").map(str::trim).filter(|name| !name.is_empty());
+        let name = name.ok_or_else(|| format!("{}: a <file> has no name", path))?;
+
+        let mut urls: Vec<(i64, usize, String)> = vec![];
+        for (index, url) in file.descendants("url").into_iter().enumerate() {
+            let text = url.text();
+            let Ok(parsed) = Url::parse(&text) else {
+                return Err(format!("{}: {}: invalid URL {:?}", path, name, text));
+            };
+            if !matches!(parsed.scheme(), "http" | "https") {
+                continue;
+            }
+            // Lower priorities go first in Metalink 4, higher preferences in 3
+            let rank = match (url.attr("priority"), url.attr("preference")) {
+                (Some(priority), _) => priority.trim().parse().unwrap_or(i64::MAX),
+                (None, Some(preference)) => -preference.trim().parse().unwrap_or(0),
+                (None, None) => i64::MAX,
+            };
+            urls.push((rank, index, text));
+        }
+        if urls.is_empty() {
+            return Err(format!("{}: {} has no http or https URL", path, name));
+        }
+        urls.sort();
+
+        let size = match file.children("size").first() {
+            Some(size) => Some(size.text().parse().map_err(|_| format!("{}: {}: invalid <size>", path, name))?),
+            None => None,
+        };
+
+        let mut sha256 = None;
+        let mut other_hashes = vec![];
+        // Piece hashes sit inside <pieces>, so only direct children count
+        let hashes = file.children("hash").into_iter().chain(file.children("verification").into_iter().flat_map(|v| v.children("hash")));
+        for hash in hashes {
+            let kind = hash.attr("type").unwrap_or_default().to_ascii_lowercase();
+            match kind.as_str() {
+                "sha-256" | "sha256" => {
+                    let hex = hash.text().to_ascii_lowercase();
+                    if hex.len() != 64 || !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+                        return Err(format!("{}: {}: invalid SHA-256 {:?}", path, name, hex));
+                    }
+                    sha256 = Some(hex);
+                }
+                _ => other_hashes.push(kind),
+            }
+        }
+
+        files.push(MetalinkFile {
+            name: name.to_string(),
+            urls: urls.into_iter().map(|(_, _, url)| url).collect(),
+            size,
+            sha256,
+            other_hashes,
+        });
+    }
+    if files.is_empty() {
+        return Err(format!("{}: the metalink lists no files", path));
+    }
+    Ok(files)
+}
+
+/// An XML element, named without its namespace prefix.
+struct Element {
+    name: String,
+    attrs: Vec<(String, String)>,
+    children: Vec<Node>,
+}
+
+enum Node {
+    Element(Element),
+    Text(String),
+}
+
+impl Element {
+    fn attr(&self, name: &str) -> Option<&str> {
+        self.attrs.iter().find(|(key, _)| key == name).map(|(_, value)| value.as_str())
+    }
+
+    fn children(&self, name: &str) -> Vec<&Element> {
+        self.children
+            .iter()
+            .filter_map(|node| match node {
+                Node::Element(element) if element.name == name => Some(element),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Every element named `name` below this one, in document order.
+    fn descendants(&self, name: &str) -> Vec<&Element> {
+        let mut found = vec![];
+        for node in &self.children {
+            if let Node::Element(element) = node {
+                if element.name == name {
+                    found.push(element);
+                }
+                found.extend(element.descendants(name));
+            }
+        }
+        found
+    }
+
+    /// The element's text, trimmed.
+    fn text(&self) -> String {
+        let mut text = String::new();
+        for node in &self.children {
+            if let Node::Text(t) = node {
+                text.push_str(t);
+            }
+        }
+        text.trim().to_string()
+    }
+}
+
+/// Parses `text` as an XML document, returning its root element. Enough
+/// of XML for metalinks: comments, processing instructions, CDATA, the
+/// predefined and numeric entities, and a DOCTYPE without an internal
+/// subset.
+fn parse(text: &str) -> Result<Element, String> {
+    let mut parser = Parser { text, pos: 0 };
+    parser.skip_misc()?;
+    if !parser.rest().starts_with('<') {
+        return Err(parser.error("expected the root element"));
+    }
+    let root = parser.element()?;
+    parser.skip_misc()?;
+    if !parser.rest().is_empty() {
+        return Err(parser.error("content after the root element"));
+    }
+    Ok(root)
+}
+
+struct Parser<'a> {
+    text: &'a str,
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn rest(&self) -> &'a str {
+        &self.text[self.pos..]
+    }
+
+    fn error(&self, message: &str) -> String {
+        let line = self.text[..self.pos].matches('\n').count() + 1;
+        format!("line {}: {}", line, message)
+    }
+
+    /// Moves past `end`, failing with `what` if it never comes.
+    fn skip_past(&mut self, end: &str, what: &str) -> Result<(), String> {
+        match self.rest().find(end) {
+            Some(at) => {
+                self.pos += at + end.len();
+                Ok(())
+            }
+            None => Err(self.error(&format!("unterminated {}", what))),
+        }
+    }
+
+    /// Skips whitespace, comments, processing instructions and any DOCTYPE.
+    fn skip_misc(&mut self) -> Result<(), String> {
+        loop {
+            let trimmed = self.rest().trim_start();
+            self.pos = self.text.len() - trimmed.len();
+            if trimmed.starts_with("<!--") {
+                self.skip_past("-->", "comment")?;
+            } else if trimmed.starts_with("<?") {
+                self.skip_past("?>", "processing instruction")?;
+            } else if trimmed.starts_with("<!DOCTYPE") {
+                if trimmed[..trimmed.find('>').unwrap_or(trimmed.len())].contains('[') {
+                    return Err(self.error("DOCTYPE internal subsets aren't supported"));
+                }
+                self.skip_past(">", "DOCTYPE")?;
+            } else {
+                return Ok(());
+            }
+        }
+    }
+
+    fn name(&mut self) -> Result<String, String> {
+        let len = self
+            .rest()
+            .find(|c: char| c.is_whitespace() || matches!(c, '>' | '/' | '='))
+            .unwrap_or(self.rest().len());
+        if len == 0 {
+            return Err(self.error("expected a name"));
+        }
+        let name = &self.rest()[..len];
+        self.pos += len;
+        Ok(name.rsplit(':').next().unwrap_or(name).to_string())
+    }
+
+    fn skip_whitespace(&mut self) {
+        let trimmed = self.rest().trim_start();
+        self.pos = self.text.len() - trimmed.len();
+    }
+
+    /// An element, starting at its `<`.
+    fn element(&mut self) -> Result<Element, String> {
+        self.pos += 1;
+        let raw_start = self.pos;
+        let name = self.name()?;
+        let raw_name = self.text[raw_start..self.pos].to_string();
+        let mut attrs = vec![];
+        loop {
+            self.skip_whitespace();
+            if let Some(rest) = self.rest().strip_prefix("/>") {
+                self.pos = self.text.len() - rest.len();
+                return Ok(Element { name, attrs, children: vec![] });
+            }
+            if self.rest().starts_with('>') {
+                self.pos += 1;
+                break;
+            }
+            if self.rest().is_empty() {
+                return Err(self.error(&format!("unterminated <{}>", raw_name)));
+            }
+            let key = self.name()?;
+            self.skip_whitespace();
+            if !self.rest().starts_with('=') {
+                return Err(self.error(&format!("expected = after attribute {}", key)));
+            }
+            self.pos += 1;
+            self.skip_whitespace();
+            let quote = match self.rest().chars().next() {
+                Some(quote @ ('"' | '\'')) => quote,
+                _ => return Err(self.error(&format!("expected a quoted value for attribute {}", key))),
+            };
+            self.pos += 1;
+            let Some(len) = self.rest().find(quote) else {
+                return Err(self.error(&format!("unterminated value for attribute {}", key)));
+            };
+            let value = self.unescape(&self.rest()[..len])?;
+            self.pos += len + 1;
+            attrs.push((key, value));
+        }
+
+        let mut children = vec![];
+        loop {
+            let rest = self.rest();
+            if rest.is_empty() {
+                return Err(self.error(&format!("<{}> is never closed", raw_name)));
+            }
+            if let Some(rest) = rest.strip_prefix("</") {
+                self.pos = self.text.len() - rest.len();
+                let close_start = self.pos;
+                self.name()?;
+                let close = &self.text[close_start..self.pos];
+                if close != raw_name {
+                    return Err(self.error(&format!("</{}> closes <{}>", close, raw_name)));
+                }
+                self.skip_whitespace();
+                if !self.rest().starts_with('>') {
+                    return Err(self.error(&format!("expected > after </{}", close)));
+                }
+                self.pos += 1;
+                return Ok(Element { name, attrs, children });
+            } else if rest.starts_with("<!--") {
+                self.skip_past("-->", "comment")?;
+            } else if rest.starts_with("<?") {
+                self.skip_past("?>", "processing instruction")?;
+            } else if let Some(cdata) = rest.strip_prefix("<![CDATA[") {
+                let Some(len) = cdata.find("]]>") else {
+                    return Err(self.error("unterminated CDATA section"));
+                };
+                children.push(Node::Text(cdata[..len].to_string()));
+                self.pos += "<![CDATA[".len() + len + "]]>".len();
+            } else if rest.starts_with('<') {
+                children.push(Node::Element(self.element()?));
+            } else {
+                let len = rest.find('<').unwrap_or(rest.len());
+                let text = self.unescape(&rest[..len])?;
+                self.pos += len;
+                children.push(Node::Text(text));
+            }
+        }
+    }
+
+    /// Replaces the entity references in `raw`.
+    fn unescape(&self, raw: &str) -> Result<String, String> {
+        let mut out = String::with_capacity(raw.len());
+        let mut rest = raw;
+        while let Some(amp) = rest.find('&') {
+            out.push_str(&rest[..amp]);
+            rest = &rest[amp + 1..];
+            let Some(semi) = rest.find(';') else {
+                return Err(self.error("unterminated entity reference"));
+            };
+            let entity = &rest[..semi];
+            let c = match entity {
+                "lt" => Some('<'),
+                "gt" => Some('>'),
+                "amp" => Some('&'),
+                "quot" => Some('"'),
+                "apos" => Some('\''),
+                _ => match entity.strip_prefix("#x").or_else(|| entity.strip_prefix("#X")) {
+                    Some(hex) => u32::from_str_radix(hex, 16).ok().and_then(char::from_u32),
+                    None => entity.strip_prefix('#').and_then(|n| n.parse().ok()).and_then(char::from_u32),
+                },
+            };
+            let Some(c) = c else {
+                return Err(self.error(&format!("unknown entity &{};", entity)));
+            };
+            out.push(c);
+            rest = &rest[semi + 1..];
+        }
+        out.push_str(rest);
+        Ok(out)
+    }
+}
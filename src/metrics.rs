@@ -0,0 +1,67 @@
+use crate::stats::{DownloadRecord, DownloadStats};
+use hyper::header::{HeaderValue, CONTENT_TYPE};
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use log::debug;
+use std::convert::Infallible;
+use std::fmt::Write;
+use std::net::TcpListener;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+use tokio::task::{self, JoinHandle};
+
+/// Serves `stats` in the Prometheus text format at `http://ADDR/metrics`
+/// until the returned task is aborted. Fails at once if `addr`, a
+/// `host:port`, can't be listened on.
+pub fn serve_metrics(addr: &str, stats: Arc<Mutex<DownloadStats>>) -> Result<JoinHandle<()>, String> {
+    let listen_error = |e: &dyn std::fmt::Display| format!("Couldn't serve metrics on {}: {}", addr, e);
+    let listener = TcpListener::bind(addr).map_err(|e| listen_error(&e))?;
+    listener.set_nonblocking(true).map_err(|e| listen_error(&e))?;
+    let server = Server::from_tcp(listener).map_err(|e| listen_error(&e))?;
+
+    let make_service = make_service_fn(move |_| {
+        let stats = stats.clone();
+        async move { Ok::<_, Infallible>(service_fn(move |request| respond(request, stats.clone()))) }
+    });
+    Ok(task::spawn(async move {
+        if let Err(e) = server.serve(make_service).await {
+            debug!("Stopped serving metrics: {}", e);
+        }
+    }))
+}
+
+async fn respond(request: Request<Body>, stats: Arc<Mutex<DownloadStats>>) -> Result<Response<Body>, Infallible> {
+    let (status, body) = if request.uri().path() != "/metrics" {
+        (StatusCode::NOT_FOUND, "Metrics are at /metrics\n".to_string())
+    } else if !matches!(*request.method(), Method::GET | Method::HEAD) {
+        (StatusCode::METHOD_NOT_ALLOWED, "Only GET and HEAD are allowed\n".to_string())
+    } else {
+        (StatusCode::OK, render(&*stats.lock().await))
+    };
+    let mut response = Response::new(Body::from(body));
+    *response.status_mut() = status;
+    let content_type = if status == StatusCode::OK { "text/plain; version=0.0.4" } else { "text/plain" };
+    response.headers_mut().insert(CONTENT_TYPE, HeaderValue::from_static(content_type));
+    Ok(response)
+}
+
+fn render(stats: &DownloadStats) -> String {
+    let count = |keep: fn(&DownloadRecord) -> bool| stats.records.iter().filter(|r| keep(r)).count() as f64;
+    let metrics = [
+        ("received_bytes_total", "counter", "Bytes received over the network, failed attempts included.", stats.received as f64),
+        ("active_downloads", "gauge", "Downloads in progress.", stats.files.iter().filter(|f| !f.finished).count() as f64),
+        ("downloads_completed_total", "counter", "Files downloaded.", count(|r| r.error.is_none() && !r.skipped)),
+        ("downloads_skipped_total", "counter", "URLs skipped as already downloaded or unchanged.", count(|r| r.skipped)),
+        ("downloads_failed_total", "counter", "Downloads that failed, not counting cancelled ones.", count(|r| r.error.is_some() && !r.cancelled)),
+        ("speed_bytes_per_second", "gauge", "Combined speed over the last few seconds.", stats.current_speed()),
+    ];
+
+    let mut out = String::new();
+    for (name, kind, help, value) in metrics {
+        // Writing to a String can't fail
+        let _ = writeln!(out, "# HELP rs_downloader_{} {}", name, help);
+        let _ = writeln!(out, "# TYPE rs_downloader_{} {}", name, kind);
+        let _ = writeln!(out, "rs_downloader_{} {}", name, value);
+    }
+    out
+}
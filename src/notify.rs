@@ -0,0 +1,40 @@
+use rs_downloader::{DownloadRecord, Units};
+
+/// Sends a desktop notification with how many files finished and their
+/// total size, and a second one if any failed. Does nothing when there's
+/// no notification service, e.g. on a headless machine.
+#[cfg(feature = "notify")]
+pub fn notify_finished(records: &[DownloadRecord], units: Units) {
+    use log::debug;
+    use notify_rust::Notification;
+    use rs_downloader::format_bytes;
+
+    let succeeded: Vec<&DownloadRecord> = records.iter().filter(|r| r.error.is_none() && !r.skipped).collect();
+    let skipped = records.iter().filter(|r| r.skipped).count();
+    let failed: Vec<&DownloadRecord> = records.iter().filter(|r| r.error.is_some() && !r.cancelled).collect();
+
+    let total: u64 = succeeded.iter().map(|r| r.bytes).sum();
+    let mut body = format!("{} downloaded, {}", succeeded.len(), format_bytes(total, units));
+    if skipped > 0 {
+        body.push_str(&format!(", {} skipped", skipped));
+    }
+    let mut notifications = vec![("Downloads finished".to_string(), body)];
+
+    if !failed.is_empty() {
+        let mut body: Vec<String> = failed.iter().take(3).map(|r| r.url.clone()).collect();
+        if failed.len() > 3 {
+            body.push(format!("and {} more", failed.len() - 3));
+        }
+        notifications.push((format!("{} download(s) failed", failed.len()), body.join("\n")));
+    }
+
+    for (summary, body) in notifications {
+        if let Err(e) = Notification::new().appname("rs-downloader").summary(&summary).body(&body).show() {
+            debug!("Couldn't show a desktop notification: {}", e);
+            return;
+        }
+    }
+}
+
+#[cfg(not(feature = "notify"))]
+pub fn notify_finished(_records: &[DownloadRecord], _units: Units) {}
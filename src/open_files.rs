@@ -0,0 +1,127 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tokio::task;
+
+/// Caps how many files downloads hold open at once, separately from how
+/// many downloads run.
+#[derive(Clone)]
+pub(crate) struct OpenFiles(Arc<Semaphore>);
+
+/// A file opened through [`OpenFiles`], whose handle is given back on drop.
+pub(crate) struct OpenFile {
+    pub(crate) file: File,
+    _permit: OwnedSemaphorePermit,
+}
+
+impl OpenFiles {
+    pub(crate) fn new(max: usize) -> Self {
+        OpenFiles(Arc::new(Semaphore::new(max.max(1))))
+    }
+
+    /// Opens `path` with `options` once a handle is free.
+    pub(crate) async fn open(&self, path: &Path, options: &OpenOptions) -> io::Result<OpenFile> {
+        // The semaphore is never closed
+        let permit = self.0.clone().acquire_owned().await.map_err(io::Error::other)?;
+        Ok(OpenFile { file: options.open(path)?, _permit: permit })
+    }
+
+    fn contended(&self) -> bool {
+        self.0.available_permits() == 0
+    }
+}
+
+/// Writes into a file from a position on through a buffer of `capacity`
+/// bytes. The file is closed after a flush whenever every handle is taken,
+/// and reopened for the next one, so a writer only keeps its handle while
+/// nobody else could be waiting for it.
+///
+/// Full buffers are written on the blocking thread pool, so a slow disk
+/// doesn't stall the runtime, and [`write_all`](Self::write_all) waits for
+/// the write to finish. Callers awaiting it before reading the next chunk
+/// therefore hold at most `capacity` bytes plus that chunk, however far the
+/// network gets ahead of the disk.
+pub(crate) struct PartWriter {
+    path: PathBuf,
+    position: u64,
+    buffer: Vec<u8>,
+    capacity: usize,
+    files: OpenFiles,
+    open: Option<OpenFile>,
+    /// Written at the end without seeking, reopened for appending.
+    append: bool,
+}
+
+impl PartWriter {
+    /// Writes into `file`, an open handle to `path`, from `position` on.
+    pub(crate) fn new(file: OpenFile, path: &Path, position: u64, capacity: usize, files: &OpenFiles) -> Self {
+        PartWriter {
+            path: path.to_path_buf(),
+            position,
+            buffer: Vec::with_capacity(capacity),
+            capacity,
+            files: files.clone(),
+            open: Some(file),
+            append: false,
+        }
+    }
+
+    /// Writes into `file`, a fresh handle to `path`, from its start on
+    /// without ever seeking, reopening it for appending when needed.
+    pub(crate) fn appending(file: OpenFile, path: &Path, capacity: usize, files: &OpenFiles) -> Self {
+        PartWriter { append: true, ..PartWriter::new(file, path, 0, capacity, files) }
+    }
+
+    pub(crate) async fn write_all(&mut self, data: &[u8]) -> io::Result<()> {
+        self.buffer.extend_from_slice(data);
+        if self.buffer.len() >= self.capacity {
+            self.flush().await?;
+        }
+        Ok(())
+    }
+
+    /// Where the data that has reached the file ends. Bytes still in the
+    /// buffer aren't counted, and are lost if the writer is dropped.
+    pub(crate) fn position(&self) -> u64 {
+        self.position
+    }
+
+    /// Writes out what is buffered and closes the file.
+    pub(crate) async fn finish(mut self) -> io::Result<()> {
+        self.flush().await?;
+        self.open = None;
+        Ok(())
+    }
+
+    async fn flush(&mut self) -> io::Result<()> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+        let mut open = match self.open.take() {
+            Some(open) => open,
+            None => self.files.open(&self.path, OpenOptions::new().write(true).append(self.append)).await?,
+        };
+        let buffer = std::mem::take(&mut self.buffer);
+        let position = (!self.append).then_some(self.position);
+        let (open, buffer) = task::spawn_blocking(move || {
+            if let Some(position) = position {
+                open.file.seek(SeekFrom::Start(position))?;
+            }
+            open.file.write_all(&buffer)?;
+            Ok::<_, io::Error>((open, buffer))
+        })
+        .await
+        .map_err(io::Error::other)??;
+
+        self.position += buffer.len() as u64;
+        // Handing the buffer back keeps its allocation for the next one
+        self.buffer = buffer;
+        self.buffer.clear();
+        if !self.files.contended() {
+            self.open = Some(open);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,480 @@
+use crate::error::DownloadError;
+use crate::format::{format_bytes, format_speed, Units};
+use crate::reporter::ProgressReporter;
+use crate::stats::{DownloadStats, FileProgress};
+use std::collections::HashMap;
+use std::env;
+use std::io::{stdout, IsTerminal, Write};
+#[cfg(feature = "progress-ui")]
+use std::io::Stdout;
+use std::path::Path;
+use std::sync::{Arc, Mutex as StdMutex, MutexGuard};
+use std::time::Duration;
+use tokio::sync::Notify;
+use tokio::time;
+#[cfg(feature = "progress-ui")]
+use crossterm::{
+    queue,
+    style::{Color, Print, ResetColor, SetForegroundColor},
+    terminal::{self, Clear, ClearType},
+    cursor::MoveTo,
+};
+
+/// Row where the per-file lines start, below the aggregate progress and speed.
+#[cfg(feature = "progress-ui")]
+const FIRST_FILE_ROW: u16 = 2;
+/// Redraw this often unless configured otherwise.
+pub const DEFAULT_PROGRESS_INTERVAL: Duration = Duration::from_millis(500);
+/// Shorter intervals are raised to this, since redrawing faster only burns CPU.
+pub const MIN_PROGRESS_INTERVAL: Duration = Duration::from_millis(50);
+/// The frames of the spinner shown for a download without a length.
+#[cfg(feature = "progress-ui")]
+const SPINNER: [char; 4] = ['|', '/', '-', '\\'];
+
+/// How progress is drawn.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ProgressMode {
+    /// Redraw the progress lines in place by moving the cursor.
+    Interactive,
+    /// Append one plain line per update, for log files and pipes.
+    Plain,
+}
+
+impl ProgressMode {
+    /// Interactive when stdout is a terminal, plain otherwise.
+    pub fn detect() -> Self {
+        if stdout().is_terminal() {
+            ProgressMode::Interactive
+        } else {
+            ProgressMode::Plain
+        }
+    }
+}
+
+/// How the progress task draws its output.
+#[derive(Clone, Copy, Debug)]
+pub struct ProgressConfig {
+    pub mode: ProgressMode,
+    pub color: bool,
+    pub units: Units,
+    /// Time between redraws, at least [`MIN_PROGRESS_INTERVAL`]. `None`
+    /// redraws only when a download starts or finishes.
+    pub interval: Option<Duration>,
+    /// Follow each plain progress line with one per active download and
+    /// its speed, for spotting a slow server.
+    pub verbose: bool,
+}
+
+impl ProgressConfig {
+    /// Picks the mode and colour from the environment, with SI units and
+    /// the default interval.
+    pub fn detect() -> Self {
+        ProgressConfig {
+            mode: ProgressMode::detect(),
+            color: color_enabled(),
+            units: Units::default(),
+            interval: Some(DEFAULT_PROGRESS_INTERVAL),
+            verbose: false,
+        }
+    }
+}
+
+/// Whether to colour output: only on a terminal, and never when the
+/// `NO_COLOR` environment variable is set to a non-empty value.
+pub fn color_enabled() -> bool {
+    stdout().is_terminal() && env::var_os("NO_COLOR").is_none_or(|v| v.is_empty())
+}
+
+/// A [`ProgressReporter`] that draws download progress on stdout.
+///
+/// It keeps its own tally from the events it receives; run
+/// [`update_progress_and_speed`] alongside the downloads to draw it.
+#[derive(Default)]
+pub struct TerminalReporter {
+    tally: StdMutex<Tally>,
+    /// Woken when a download starts or finishes.
+    changed: Notify,
+}
+
+/// Progress rebuilt from [`ProgressReporter`] events, for reporters that
+/// draw it periodically.
+#[derive(Default)]
+pub(crate) struct Tally {
+    pub(crate) stats: DownloadStats,
+    /// Index into `stats.files` of the current attempt for each URL.
+    active: HashMap<String, usize>,
+    /// Downloads of each URL queued but not yet started, so their sizes
+    /// are still unknown.
+    queued: HashMap<String, usize>,
+    /// Sizes found by probing, which count for queued downloads until
+    /// they start.
+    probed: HashMap<String, u64>,
+    /// The part of `stats.total_bytes` from downloads without a length.
+    unsized_bytes: u64,
+    /// Where the spinner of each active download without a length is.
+    #[cfg(feature = "progress-ui")]
+    spinners: HashMap<String, Spinner>,
+}
+
+#[cfg(feature = "progress-ui")]
+struct Spinner {
+    frame: usize,
+    /// The download's bytes when the spinner last turned.
+    bytes: u64,
+}
+
+impl Tally {
+    pub(crate) fn queue(&mut self, url: &str) {
+        *self.queued.entry(url.to_string()).or_default() += 1;
+    }
+
+    pub(crate) fn probed(&mut self, url: &str, size: Option<u64>) {
+        match size {
+            Some(size) => self.probed.insert(url.to_string(), size),
+            None => self.probed.remove(url),
+        };
+    }
+
+    pub(crate) fn start(&mut self, url: &str, path: &Path, downloaded: u64, total: Option<u64>) {
+        if !self.active.contains_key(url) {
+            self.unqueue(url);
+        }
+        // A retry replaces whatever the failed attempt had counted
+        self.finish(url, false);
+
+        let total = total.unwrap_or(0);
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        self.stats.total_size += total;
+        self.stats.total_bytes += downloaded;
+        if total == 0 {
+            self.unsized_bytes += downloaded;
+        }
+        self.stats.files.push(FileProgress::new(url, &name, downloaded, total));
+        self.active.insert(url.to_string(), self.stats.files.len() - 1);
+    }
+
+    pub(crate) fn progress(&mut self, url: &str, downloaded: u64) {
+        let Some(&index) = self.active.get(url) else {
+            return;
+        };
+
+        let file = &mut self.stats.files[index];
+        let delta = downloaded.saturating_sub(file.bytes);
+        file.bytes = downloaded;
+        file.window.record(downloaded);
+
+        self.stats.total_bytes += delta;
+        if file.size == 0 {
+            self.unsized_bytes += delta;
+        }
+    }
+
+    pub(crate) fn complete(&mut self, url: &str) {
+        if !self.active.contains_key(url) {
+            // Skipped, so it never started
+            self.unqueue(url);
+        }
+        self.finish(url, true);
+    }
+
+    /// Marks a URL's current attempt as the one it gave up on.
+    pub(crate) fn fail(&mut self, url: &str) {
+        match self.active.get(url) {
+            Some(&index) => self.stats.files[index].failed = true,
+            None => self.unqueue(url),
+        }
+        self.finish(url, false);
+    }
+
+    /// Takes a URL's current attempt out of the active list, and out of the
+    /// totals too unless it completed.
+    fn finish(&mut self, url: &str, keep_bytes: bool) {
+        if let Some(index) = self.active.remove(url) {
+            let file = &mut self.stats.files[index];
+            file.finished = true;
+            if !keep_bytes {
+                self.stats.total_size -= file.size;
+                self.stats.total_bytes -= file.bytes;
+                if file.size == 0 {
+                    self.unsized_bytes -= file.bytes;
+                }
+            }
+        }
+    }
+
+    fn unqueue(&mut self, url: &str) {
+        if let Some(count) = self.queued.get_mut(url) {
+            *count -= 1;
+            if *count == 0 {
+                self.queued.remove(url);
+            }
+        }
+    }
+
+    /// How much of the bytes with a known size have arrived, or `None`
+    /// while some queued download's size is still unknown, since dividing
+    /// by a partial total would overstate it.
+    pub(crate) fn percent(&self) -> Option<f64> {
+        let total = self.stats.total_size + self.queued_size()?;
+        if total == 0 {
+            return None;
+        }
+        Some((((self.stats.total_bytes - self.unsized_bytes) as f64 / total as f64) * 100.0).min(100.0))
+    }
+
+    /// The probed sizes of the queued downloads, or `None` if any wasn't
+    /// probed.
+    fn queued_size(&self) -> Option<u64> {
+        self.queued.iter().map(|(url, &count)| self.probed.get(url).map(|size| size * count as u64)).sum()
+    }
+
+    /// The sized downloads' combined size, counting queued ones that were
+    /// probed.
+    #[cfg(feature = "progress-ui")]
+    pub(crate) fn expected_size(&self) -> u64 {
+        let queued: u64 = self.queued.iter().filter_map(|(url, &count)| self.probed.get(url).map(|size| size * count as u64)).sum();
+        self.stats.total_size + queued
+    }
+
+    /// The aggregate percentage to `precision` decimals, or `?%` with how
+    /// many sizes are still unknown, followed by the bytes of downloads
+    /// that have no size.
+    pub(crate) fn describe_progress(&self, precision: usize, units: Units) -> String {
+        let unknown: usize = self.queued.iter().filter(|(url, _)| !self.probed.contains_key(*url)).map(|(_, count)| count).sum();
+        let mut text = match self.percent() {
+            Some(percent) => format!("{:.*}%", precision, percent),
+            None if unknown == 0 => "?%".to_string(),
+            None => format!("?% ({} still to start)", unknown),
+        };
+        if self.unsized_bytes > 0 {
+            text.push_str(&format!(", plus {} so far of unknown size", format_bytes(self.unsized_bytes, units)));
+        }
+        text
+    }
+
+    /// Turns the spinner of each download without a length that received
+    /// bytes since the last frame, so a stalled one stands still.
+    #[cfg(feature = "progress-ui")]
+    pub(crate) fn tick(&mut self) {
+        self.spinners.retain(|url, _| self.active.contains_key(url));
+        for (url, &index) in &self.active {
+            let file = &self.stats.files[index];
+            if file.size > 0 {
+                continue;
+            }
+            let spinner = self.spinners.entry(url.clone()).or_insert(Spinner { frame: 0, bytes: file.bytes });
+            if spinner.bytes != file.bytes {
+                spinner.frame += 1;
+                spinner.bytes = file.bytes;
+            }
+        }
+    }
+
+    /// How many times `url`'s spinner has turned.
+    #[cfg(feature = "progress-ui")]
+    pub(crate) fn spinner_frame(&self, url: &str) -> usize {
+        self.spinners.get(url).map_or(0, |spinner| spinner.frame)
+    }
+
+    /// Bytes that count towards [`Tally::percent`].
+    #[cfg(feature = "progress-ui")]
+    pub(crate) fn sized_bytes(&self) -> u64 {
+        self.stats.total_bytes - self.unsized_bytes
+    }
+}
+
+impl TerminalReporter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub(crate) fn tally(&self) -> MutexGuard<'_, Tally> {
+        self.tally.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Waits until the next redraw is due: after `interval`, or without one
+    /// until a download starts or finishes.
+    pub(crate) async fn next_frame(&self, interval: Option<Duration>) {
+        match interval {
+            Some(interval) => time::sleep(interval.max(MIN_PROGRESS_INTERVAL)).await,
+            None => self.changed.notified().await,
+        }
+    }
+}
+
+impl ProgressReporter for TerminalReporter {
+    fn on_start(&self, url: &str, path: &Path, downloaded: u64, total: Option<u64>) {
+        self.tally().start(url, path, downloaded, total);
+        self.changed.notify_one();
+    }
+
+    fn on_progress(&self, url: &str, downloaded: u64) {
+        self.tally().progress(url, downloaded);
+    }
+
+    fn on_queued(&self, url: &str) {
+        self.tally().queue(url);
+    }
+
+    fn on_probed(&self, url: &str, size: Option<u64>) {
+        self.tally().probed(url, size);
+    }
+
+    fn on_complete(&self, url: &str, _path: &Path) {
+        self.tally().complete(url);
+        self.changed.notify_one();
+    }
+
+    fn on_error(&self, url: &str, _error: &DownloadError) {
+        self.tally().fail(url);
+        self.changed.notify_one();
+    }
+}
+
+/// Draws `reporter`'s progress every `config.interval` until aborted:
+/// aggregate progress and speed, plus a line per active download in
+/// interactive mode, or in plain mode with `config.verbose`.
+///
+/// Without the `progress-ui` feature this is the plain output on stderr,
+/// whatever the mode.
+///
+/// Returns the error if the output can't be written to, e.g. a closed
+/// pipe; the downloads carry on regardless.
+pub async fn update_progress_and_speed(reporter: Arc<TerminalReporter>, config: ProgressConfig) -> std::io::Result<()> {
+    loop {
+        reporter.next_frame(config.interval).await;
+        draw(&mut reporter.tally(), config)?;
+    }
+}
+
+#[cfg(feature = "progress-ui")]
+fn draw(tally: &mut Tally, config: ProgressConfig) -> std::io::Result<()> {
+    tally.tick();
+    let ProgressConfig { mode, color, units, verbose, .. } = config;
+    let stats = &tally.stats;
+    let speed = format_speed(stats.current_speed(), units);
+    let progress = tally.describe_progress(2, units);
+
+    let mut out = stdout();
+    match mode {
+        ProgressMode::Interactive => {
+            queue!(out, MoveTo(0, 0), Clear(ClearType::CurrentLine))?;
+            print_colored(&mut out, Color::Green, &format!("Total progress: {}", progress), color)?;
+            queue!(out, MoveTo(0, 1), Clear(ClearType::CurrentLine))?;
+            print_colored(&mut out, Color::Blue, &format!("Current download speed: {}", speed), color)?;
+
+            let active: Vec<&FileProgress> = stats.files.iter().filter(|f| !f.finished).collect();
+            render_files(&mut out, &active, tally, units)?;
+        }
+        ProgressMode::Plain => {
+            writeln!(out, "Total progress: {}, {}", progress, speed)?;
+            if verbose {
+                write_file_speeds(&mut out, stats, units)?;
+            }
+        }
+    }
+
+    out.flush()
+}
+
+#[cfg(not(feature = "progress-ui"))]
+fn draw(tally: &mut Tally, config: ProgressConfig) -> std::io::Result<()> {
+    let speed = format_speed(tally.stats.current_speed(), config.units);
+    let mut out = std::io::stderr();
+    writeln!(out, "Total progress: {}, {}", tally.describe_progress(2, config.units), speed)?;
+    if config.verbose {
+        write_file_speeds(&mut out, &tally.stats, config.units)?;
+    }
+    Ok(())
+}
+
+/// One indented line per active download with its own speed, which the
+/// aggregate speed is the sum of.
+fn write_file_speeds(out: &mut impl Write, stats: &DownloadStats, units: Units) -> std::io::Result<()> {
+    for file in stats.files.iter().filter(|f| !f.finished) {
+        let done = match file.size {
+            0 => format!("{} so far", format_bytes(file.bytes, units)),
+            size => format!("{}/{}", format_bytes(file.bytes, units), format_bytes(size, units)),
+        };
+        writeln!(out, "  {}: {} ({}) from {}", file.name, format_speed(file.current_speed(), units), done, file.url)?;
+    }
+    Ok(())
+}
+
+#[cfg(feature = "progress-ui")]
+pub(crate) fn print_colored(out: &mut Stdout, color: Color, text: &str, enabled: bool) -> std::io::Result<()> {
+    if enabled {
+        queue!(out, SetForegroundColor(color), Print(text), ResetColor)
+    } else {
+        queue!(out, Print(text))
+    }
+}
+
+/// Draws one line per download, collapsing whatever doesn't fit in the
+/// terminal into a trailing "... and N more" line. Downloads without a
+/// length get a spinner and their bytes so far instead of a percentage.
+#[cfg(feature = "progress-ui")]
+fn render_files(out: &mut Stdout, files: &[&FileProgress], tally: &Tally, units: Units) -> std::io::Result<()> {
+    let (width, height) = terminal::size().unwrap_or((80, 24));
+    // Keep the last row free so the cursor doesn't force a scroll
+    let rows = height.saturating_sub(FIRST_FILE_ROW + 1) as usize;
+    let shown = if files.len() > rows { rows.saturating_sub(1) } else { files.len() };
+
+    let mut row = FIRST_FILE_ROW;
+    for file in &files[..shown] {
+        let line = if file.size == 0 {
+            format!(
+                "{}: {} {} so far {}",
+                file.name,
+                SPINNER[tally.spinner_frame(&file.url) % SPINNER.len()],
+                format_bytes(file.bytes, units),
+                format_speed(file.current_speed(), units),
+            )
+        } else {
+            format!(
+                "{}: {:.2}% ({}/{}) {} ETA {}",
+                file.name,
+                file.percent(),
+                format_bytes(file.bytes, units),
+                format_bytes(file.size, units),
+                format_speed(file.current_speed(), units),
+                format_eta(file.eta()),
+            )
+        };
+        queue!(
+            out,
+            MoveTo(0, row),
+            Clear(ClearType::CurrentLine),
+            Print(truncate(&line, width as usize))
+        )?;
+        row += 1;
+    }
+    if shown < files.len() && rows > 0 {
+        queue!(
+            out,
+            MoveTo(0, row),
+            Clear(ClearType::CurrentLine),
+            Print(format!("... and {} more", files.len() - shown))
+        )?;
+        row += 1;
+    }
+    queue!(out, MoveTo(0, row), Clear(ClearType::FromCursorDown))
+}
+
+/// Formats a remaining time as `HH:MM:SS`, or `--:--:--` when unknown.
+#[cfg(feature = "progress-ui")]
+pub(crate) fn format_eta(eta: Option<Duration>) -> String {
+    match eta {
+        Some(eta) => {
+            // Round up so a download never shows 00:00:00 while bytes remain
+            let secs = eta.as_secs() + u64::from(eta.subsec_nanos() > 0);
+            format!("{:02}:{:02}:{:02}", secs / 3600, secs / 60 % 60, secs % 60)
+        }
+        None => "--:--:--".to_string(),
+    }
+}
+
+#[cfg(feature = "progress-ui")]
+pub(crate) fn truncate(line: &str, width: usize) -> String {
+    line.chars().take(width).collect()
+}
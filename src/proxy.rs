@@ -0,0 +1,45 @@
+use crate::error::DownloadError;
+use reqwest::Url;
+use std::env;
+
+/// Variables reqwest reads on its own when no proxy is configured, checked
+/// up front because it silently ignores ones it can't parse.
+const PROXY_VARS: [&str; 6] = ["HTTP_PROXY", "http_proxy", "HTTPS_PROXY", "https_proxy", "ALL_PROXY", "all_proxy"];
+
+/// Parses a proxy URL, defaulting to `http://` when no scheme is given the
+/// way curl and reqwest do. Credentials may be embedded as `user:pass@`.
+pub(crate) fn parse(proxy: &str) -> Result<Url, DownloadError> {
+    validate(proxy).map_err(DownloadError::Config)
+}
+
+/// Fails on the first proxy environment variable that isn't a valid proxy URL.
+pub(crate) fn check_env() -> Result<(), DownloadError> {
+    for var in PROXY_VARS {
+        if let Ok(value) = env::var(var) {
+            if !value.is_empty() {
+                validate(&value).map_err(|e| DownloadError::Config(format!("{}: {}", var, e)))?;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn validate(proxy: &str) -> Result<Url, String> {
+    let invalid = |reason: &str| format!("invalid proxy URL {:?}: {}", proxy, reason);
+
+    let url = if proxy.contains("://") {
+        Url::parse(proxy)
+    } else {
+        Url::parse(&format!("http://{}", proxy))
+    }
+    .map_err(|e| invalid(&e.to_string()))?;
+
+    match url.scheme() {
+        "http" | "https" | "socks5" | "socks5h" => {}
+        scheme => return Err(invalid(&format!("unsupported scheme {}", scheme))),
+    }
+    if url.host_str().is_none_or(str::is_empty) {
+        return Err(invalid("missing host"));
+    }
+    Ok(url)
+}
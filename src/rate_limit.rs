@@ -0,0 +1,70 @@
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tokio::time;
+
+/// Token bucket shared by every download that should count against the same cap.
+///
+/// The bucket holds at most one second's worth of bytes. Chunks larger than
+/// the remaining tokens push the bucket into debt, and the caller sleeps
+/// until that debt would have been paid off.
+pub(crate) struct RateLimiter {
+    bytes_per_sec: f64,
+    bucket: Mutex<Bucket>,
+}
+
+struct Bucket {
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl RateLimiter {
+    pub(crate) fn new(bytes_per_sec: u64) -> Self {
+        let bytes_per_sec = bytes_per_sec.max(1) as f64;
+        RateLimiter {
+            bytes_per_sec,
+            bucket: Mutex::new(Bucket {
+                tokens: bytes_per_sec,
+                last_refill: Instant::now(),
+            }),
+        }
+    }
+
+    /// Takes `bytes` tokens, sleeping if the bucket is overdrawn.
+    pub(crate) async fn acquire(&self, bytes: u64) {
+        let wait = {
+            let mut bucket = self.bucket.lock().unwrap_or_else(|e| e.into_inner());
+            let now = Instant::now();
+            let refill = now.duration_since(bucket.last_refill).as_secs_f64() * self.bytes_per_sec;
+            bucket.tokens = (bucket.tokens + refill).min(self.bytes_per_sec);
+            bucket.last_refill = now;
+            bucket.tokens -= bytes as f64;
+
+            if bucket.tokens < 0.0 {
+                Duration::from_secs_f64(-bucket.tokens / self.bytes_per_sec)
+            } else {
+                Duration::ZERO
+            }
+        };
+
+        if !wait.is_zero() {
+            time::sleep(wait).await;
+        }
+    }
+}
+
+/// Parses a rate like `500k`, `2m`, `1g` or plain bytes into bytes per second.
+/// Suffixes are binary (`k` = 1024), matching curl and wget.
+pub fn parse_rate(value: &str) -> Option<u64> {
+    let value = value.trim();
+    let (number, multiplier) = match value.chars().last()?.to_ascii_lowercase() {
+        'k' => (&value[..value.len() - 1], 1024),
+        'm' => (&value[..value.len() - 1], 1024 * 1024),
+        'g' => (&value[..value.len() - 1], 1024 * 1024 * 1024),
+        _ => (value, 1),
+    };
+    let number: f64 = number.trim().parse().ok()?;
+    if !number.is_finite() || number <= 0.0 {
+        return None;
+    }
+    Some((number * multiplier as f64) as u64).filter(|&rate| rate > 0)
+}
@@ -0,0 +1,42 @@
+use rs_downloader::DownloadRecord;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+
+/// Writes one CSV row per download to `path`, with a header row.
+pub fn write_csv(path: &str, records: &[DownloadRecord]) -> io::Result<()> {
+    let mut out = BufWriter::new(File::create(path)?);
+    writeln!(out, "url,final_path,status,bytes,duration_secs,avg_speed,error_message")?;
+    for record in records {
+        let status = if record.cancelled {
+            "cancelled"
+        } else if record.error.is_some() {
+            "failed"
+        } else if record.skipped {
+            "skipped"
+        } else {
+            "downloaded"
+        };
+        let path = record.path.as_ref().map(|p| p.to_string_lossy().into_owned()).unwrap_or_default();
+        writeln!(
+            out,
+            "{},{},{},{},{:.3},{:.0},{}",
+            field(&record.url),
+            field(&path),
+            status,
+            record.bytes,
+            record.elapsed.as_secs_f64(),
+            record.speed(),
+            field(record.error.as_deref().unwrap_or_default()),
+        )?;
+    }
+    out.flush()
+}
+
+/// Quotes `value` if it has a comma, quote or line break, doubling any quotes (RFC 4180).
+fn field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
@@ -0,0 +1,30 @@
+use crate::error::DownloadError;
+use std::path::Path;
+
+/// Receives download events, for driving a custom UI or collecting metrics.
+///
+/// Methods are called from the download tasks, so they should return
+/// quickly. Every method defaults to doing nothing.
+pub trait ProgressReporter: Send + Sync {
+    /// `url` is waiting its turn in `Downloader::download_many`. Until
+    /// `on_start` its size is unknown.
+    fn on_queued(&self, _url: &str) {}
+
+    /// `Downloader::probe` found `url`'s size ahead of downloading it, or
+    /// `None` if the server didn't say.
+    fn on_probed(&self, _url: &str, _size: Option<u64>) {}
+
+    /// A download attempt got its response headers. `downloaded` is what an
+    /// earlier attempt already left on disk and `total` is `None` when the
+    /// server didn't send a length. A retry calls this again for the same URL.
+    fn on_start(&self, _url: &str, _path: &Path, _downloaded: u64, _total: Option<u64>) {}
+
+    /// `downloaded` bytes of `url` are now on disk, counting resumed bytes.
+    fn on_progress(&self, _url: &str, _downloaded: u64) {}
+
+    /// `url` finished and was saved to `path`.
+    fn on_complete(&self, _url: &str, _path: &Path) {}
+
+    /// `url` failed for good, after any retries.
+    fn on_error(&self, _url: &str, _error: &DownloadError) {}
+}
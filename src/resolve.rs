@@ -0,0 +1,92 @@
+use hyper::client::connect::dns::Name;
+use log::warn;
+use reqwest::dns::{Addrs, Resolve, Resolving};
+use std::collections::HashMap;
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex as StdMutex};
+use std::time::{Duration, Instant};
+use tokio::net::lookup_host;
+
+/// Which IP version to connect over.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IpFamily {
+    V4,
+    V6,
+}
+
+impl IpFamily {
+    fn matches(self, ip: IpAddr) -> bool {
+        match self {
+            IpFamily::V4 => ip.is_ipv4(),
+            IpFamily::V6 => ip.is_ipv6(),
+        }
+    }
+
+    fn other(self) -> IpFamily {
+        match self {
+            IpFamily::V4 => IpFamily::V6,
+            IpFamily::V6 => IpFamily::V4,
+        }
+    }
+}
+
+impl fmt::Display for IpFamily {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IpFamily::V4 => write!(f, "IPv4"),
+            IpFamily::V6 => write!(f, "IPv6"),
+        }
+    }
+}
+
+/// Resolves host names like the system resolver, noting how long each
+/// lookup took. With a `family`, only its addresses are used, or with
+/// `fallback` those first and the other family's after them.
+pub(crate) struct Resolver {
+    pub(crate) family: Option<(IpFamily, bool)>,
+    pub(crate) lookups: Lookups,
+}
+
+/// How long the latest lookup of each host took, until taken.
+#[derive(Clone, Default)]
+pub(crate) struct Lookups(Arc<StdMutex<HashMap<String, Duration>>>);
+
+impl Lookups {
+    pub(crate) fn take(&self, host: &str) -> Option<Duration> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner()).remove(host)
+    }
+
+    fn record(&self, host: &str, took: Duration) {
+        self.0.lock().unwrap_or_else(|e| e.into_inner()).insert(host.to_string(), took);
+    }
+}
+
+impl Resolve for Resolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        let (family, lookups) = (self.family, self.lookups.clone());
+        Box::pin(async move {
+            let host = name.as_str();
+            let started = Instant::now();
+            let addrs: Vec<SocketAddr> = lookup_host((host, 0)).await?.collect();
+            lookups.record(host, started.elapsed());
+            let Some((family, fallback)) = family else {
+                let addrs: Addrs = Box::new(addrs.into_iter());
+                return Ok(addrs);
+            };
+
+            let (preferred, other): (Vec<SocketAddr>, Vec<SocketAddr>) = addrs.into_iter().partition(|addr| family.matches(addr.ip()));
+
+            if preferred.is_empty() {
+                if !fallback || other.is_empty() {
+                    return Err(format!("{} has no {} address", host, family).into());
+                }
+                warn!("{} has no {} address, using {}", host, family, family.other());
+            }
+            // Connecting moves on to the other family's addresses if these fail
+            let other = if fallback { other } else { vec![] };
+            let addrs: Addrs = Box::new(preferred.into_iter().chain(other));
+            Ok(addrs)
+        })
+    }
+}
@@ -0,0 +1,237 @@
+use crate::error::DownloadError;
+use crate::reporter::ProgressReporter;
+use crate::sidecar::Validators;
+use fs2::FileExt;
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex as StdMutex;
+use std::time::{Duration, Instant};
+
+/// Progress changes are written out at most this often; starts, finishes
+/// and failures are written at once.
+const SAVE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// A JSON manifest of every URL of a long job: its status, where it is
+/// saved, how much of it is down and what it was served with, so a later
+/// run can pick the job up from anywhere.
+///
+/// A `.lock` file next to it, locked while the session is open, keeps two
+/// runs from sharing it, and each save replaces it whole through a rename,
+/// so it is never seen half-written.
+pub(crate) struct Session {
+    path: PathBuf,
+    state: StdMutex<State>,
+    /// Held for the lock.
+    _lock: File,
+}
+
+struct State {
+    manifest: Manifest,
+    /// Index into `manifest.downloads` for each URL.
+    index: HashMap<String, usize>,
+    /// Changed since the last save.
+    dirty: bool,
+    saved: Instant,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct Manifest {
+    downloads: Vec<Entry>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Entry {
+    url: String,
+    status: Status,
+    /// Absolute, once the download has started.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    path: Option<PathBuf>,
+    #[serde(default)]
+    bytes: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    total: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    etag: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    last_modified: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Status {
+    Queued,
+    Downloading,
+    Complete,
+    Failed,
+}
+
+impl Session {
+    /// Opens the session at `path`, starting an empty one if the file
+    /// doesn't exist yet.
+    pub(crate) fn open(path: &Path) -> Result<Self, String> {
+        let lock_path = lock_path(path);
+        let lock = File::create(&lock_path).map_err(|e| format!("{}: {}", lock_path.display(), e))?;
+        lock.try_lock_exclusive()
+            .map_err(|_| format!("{} is in use by another run", path.display()))?;
+
+        let manifest = read(path)?;
+        let index = manifest.downloads.iter().enumerate().map(|(i, entry)| (entry.url.clone(), i)).collect();
+        Ok(Session {
+            path: path.to_path_buf(),
+            state: StdMutex::new(State { manifest, index, dirty: false, saved: Instant::now() }),
+            _lock: lock,
+        })
+    }
+
+    /// Where an earlier run saved, or started saving, `url`.
+    pub(crate) fn file_path(&self, url: &str) -> Option<PathBuf> {
+        let state = self.lock();
+        state.index.get(url).and_then(|&i| state.manifest.downloads[i].path.clone())
+    }
+
+    /// What `url` was being served with when it was last downloaded.
+    pub(crate) fn validators(&self, url: &str) -> Option<Validators> {
+        let state = self.lock();
+        let entry = &state.manifest.downloads[*state.index.get(url)?];
+        let validators = Validators { etag: entry.etag.clone(), last_modified: entry.last_modified.clone() };
+        (validators.etag.is_some() || validators.last_modified.is_some()).then_some(validators)
+    }
+
+    pub(crate) fn set_validators(&self, url: &str, validators: &Validators) {
+        self.update(url, true, |entry| {
+            entry.etag = validators.etag.clone();
+            entry.last_modified = validators.last_modified.clone();
+        });
+    }
+
+    /// Writes the manifest if anything changed since it was last written.
+    pub(crate) fn save(&self) -> io::Result<()> {
+        let mut state = self.lock();
+        if !state.dirty {
+            return Ok(());
+        }
+        let json = serde_json::to_string_pretty(&state.manifest).map_err(io::Error::other)?;
+        let mut temp_path = self.path.as_os_str().to_owned();
+        temp_path.push(".tmp");
+        let mut temp = File::create(&temp_path)?;
+        temp.write_all(json.as_bytes())?;
+        temp.sync_all()?;
+        fs::rename(&temp_path, &self.path)?;
+        state.dirty = false;
+        state.saved = Instant::now();
+        Ok(())
+    }
+
+    /// Applies `change` to `url`'s entry, adding one if needed, then saves
+    /// if `now` or it's been a while.
+    fn update(&self, url: &str, now: bool, change: impl FnOnce(&mut Entry)) {
+        {
+            let mut state = self.lock();
+            let state = &mut *state;
+            let i = *state.index.entry(url.to_string()).or_insert_with(|| {
+                state.manifest.downloads.push(Entry {
+                    url: url.to_string(),
+                    status: Status::Queued,
+                    path: None,
+                    bytes: 0,
+                    total: None,
+                    etag: None,
+                    last_modified: None,
+                    error: None,
+                });
+                state.manifest.downloads.len() - 1
+            });
+            change(&mut state.manifest.downloads[i]);
+            state.dirty = true;
+            if !now && state.saved.elapsed() < SAVE_INTERVAL {
+                return;
+            }
+        }
+        if let Err(e) = self.save() {
+            warn!("Couldn't save the session to {}: {}", self.path.display(), e);
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl ProgressReporter for Session {
+    fn on_queued(&self, url: &str) {
+        // A URL finished in an earlier run keeps its status until it's checked
+        self.update(url, false, |entry| {
+            if entry.status == Status::Failed {
+                entry.status = Status::Queued;
+            }
+        });
+    }
+
+    fn on_start(&self, url: &str, path: &Path, downloaded: u64, total: Option<u64>) {
+        let path = std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf());
+        self.update(url, true, |entry| {
+            entry.status = Status::Downloading;
+            entry.path = Some(path);
+            entry.bytes = downloaded;
+            entry.total = total;
+            entry.error = None;
+        });
+    }
+
+    fn on_progress(&self, url: &str, downloaded: u64) {
+        self.update(url, false, |entry| entry.bytes = downloaded);
+    }
+
+    fn on_complete(&self, url: &str, path: &Path) {
+        let path = std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf());
+        let bytes = fs::metadata(&path).map(|m| m.len()).ok();
+        self.update(url, true, |entry| {
+            entry.status = Status::Complete;
+            entry.bytes = bytes.unwrap_or(entry.bytes);
+            entry.total = bytes.or(entry.total);
+            entry.path = Some(path);
+            entry.error = None;
+        });
+    }
+
+    fn on_error(&self, url: &str, error: &DownloadError) {
+        let error = error.to_string();
+        self.update(url, true, |entry| {
+            entry.status = Status::Failed;
+            entry.error = Some(error);
+        });
+    }
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        let _ = self.save();
+    }
+}
+
+/// The URLs in the session file at `path`, in the order they were added,
+/// for picking up a job without listing them again. None if the file
+/// doesn't exist yet.
+pub fn session_urls(path: &Path) -> Result<Vec<String>, String> {
+    Ok(read(path)?.downloads.into_iter().map(|entry| entry.url).collect())
+}
+
+fn read(path: &Path) -> Result<Manifest, String> {
+    match fs::read_to_string(path) {
+        Ok(text) => serde_json::from_str(&text).map_err(|e| format!("{}: {}", path.display(), e)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Manifest::default()),
+        Err(e) => Err(format!("{}: {}", path.display(), e)),
+    }
+}
+
+fn lock_path(path: &Path) -> PathBuf {
+    let mut lock_path = path.as_os_str().to_owned();
+    lock_path.push(".lock");
+    PathBuf::from(lock_path)
+}
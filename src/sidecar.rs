@@ -0,0 +1,92 @@
+use reqwest::header::{HeaderMap, ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, IF_RANGE, LAST_MODIFIED};
+use reqwest::RequestBuilder;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// The `ETag` and `Last-Modified` a file was served with, kept in a
+/// `<file>.meta` sidecar so the next run can ask whether it changed. A
+/// `.part` file gets one too, so resuming it can check the same.
+pub(crate) struct Validators {
+    pub(crate) etag: Option<String>,
+    pub(crate) last_modified: Option<String>,
+}
+
+impl Validators {
+    pub(crate) fn from_headers(headers: &HeaderMap) -> Self {
+        let get = |name| headers.get(name).and_then(|v| v.to_str().ok()).map(str::to_string);
+        Validators {
+            etag: get(ETAG),
+            last_modified: get(LAST_MODIFIED),
+        }
+    }
+
+    /// Reads the sidecar for `file_path`, if there is one with anything in it.
+    pub(crate) fn load(file_path: &Path) -> Option<Self> {
+        let contents = fs::read_to_string(path(file_path)).ok()?;
+        let mut validators = Validators { etag: None, last_modified: None };
+        for line in contents.lines() {
+            match line.split_once(": ") {
+                Some(("ETag", value)) => validators.etag = Some(value.to_string()),
+                Some(("Last-Modified", value)) => validators.last_modified = Some(value.to_string()),
+                _ => {}
+            }
+        }
+        (validators.etag.is_some() || validators.last_modified.is_some()).then_some(validators)
+    }
+
+    /// Writes the sidecar for `file_path`, or removes a stale one when the
+    /// server sent neither header.
+    pub(crate) fn save(&self, file_path: &Path) -> io::Result<()> {
+        let mut contents = String::new();
+        if let Some(etag) = &self.etag {
+            contents.push_str(&format!("ETag: {}\n", etag));
+        }
+        if let Some(last_modified) = &self.last_modified {
+            contents.push_str(&format!("Last-Modified: {}\n", last_modified));
+        }
+
+        if contents.is_empty() {
+            remove(file_path)
+        } else {
+            fs::write(path(file_path), contents)
+        }
+    }
+
+    /// Makes `request` conditional, so an unchanged file comes back as a 304.
+    pub(crate) fn apply(&self, mut request: RequestBuilder) -> RequestBuilder {
+        if let Some(etag) = &self.etag {
+            request = request.header(IF_NONE_MATCH, etag);
+        }
+        if let Some(last_modified) = &self.last_modified {
+            request = request.header(IF_MODIFIED_SINCE, last_modified);
+        }
+        request
+    }
+
+    /// Makes a range `request` conditional on the file being unchanged, so a
+    /// changed file comes back whole with a 200 instead of a 206 that would
+    /// be appended to the old bytes. Only a strong `ETag` may be used here,
+    /// then `Last-Modified`.
+    pub(crate) fn apply_if_range(&self, request: RequestBuilder) -> RequestBuilder {
+        let strong_etag = self.etag.as_deref().filter(|etag| !etag.starts_with("W/"));
+        match strong_etag.or(self.last_modified.as_deref()) {
+            Some(validator) => request.header(IF_RANGE, validator),
+            None => request,
+        }
+    }
+}
+
+/// Removes the sidecar for `file_path`, if there is one.
+pub(crate) fn remove(file_path: &Path) -> io::Result<()> {
+    match fs::remove_file(path(file_path)) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+fn path(file_path: &Path) -> PathBuf {
+    let mut name = file_path.as_os_str().to_owned();
+    name.push(".meta");
+    PathBuf::from(name)
+}
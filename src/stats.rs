@@ -0,0 +1,196 @@
+use std::collections::VecDeque;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+/// How far back the recent speed looks.
+const SPEED_WINDOW: Duration = Duration::from_secs(3);
+/// Minimum spacing between samples, so fast streams don't flood the buffer.
+const SAMPLE_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Aggregate byte counts across every download sharing a `Downloader`,
+/// plus one entry per download attempt and one record per finished download.
+pub struct DownloadStats {
+    pub total_bytes: u64,
+    pub total_size: u64,
+    /// Bytes received over the network this run, including those of
+    /// attempts that failed, but not resumed bytes already on disk.
+    pub received: u64,
+    pub start_time: Instant,
+    pub files: Vec<FileProgress>,
+    pub records: Vec<DownloadRecord>,
+}
+
+impl DownloadStats {
+    pub fn new() -> Self {
+        DownloadStats {
+            total_bytes: 0,
+            total_size: 0,
+            received: 0,
+            start_time: Instant::now(),
+            files: Vec::new(),
+            records: Vec::new(),
+        }
+    }
+
+    /// Combined speed over the last few seconds in bytes per second: the
+    /// sum of each unfinished download's [`FileProgress::current_speed`].
+    pub fn current_speed(&self) -> f64 {
+        self.files.iter().filter(|file| !file.finished).map(FileProgress::current_speed).sum()
+    }
+
+    /// Combined speed since the stats were created, in bytes per second.
+    pub fn average_speed(&self) -> f64 {
+        let elapsed = self.start_time.elapsed().as_secs_f64();
+        if elapsed > 0.0 {
+            self.total_bytes as f64 / elapsed
+        } else {
+            0.0
+        }
+    }
+}
+
+impl Default for DownloadStats {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Outcome of one call to `Downloader::download`, in completion order.
+pub struct DownloadRecord {
+    pub url: String,
+    /// Where the file was saved; `None` if the download failed.
+    pub path: Option<PathBuf>,
+    /// Size of the saved file.
+    pub bytes: u64,
+    /// Time from starting the first attempt to finishing the last, excluding queueing.
+    pub elapsed: Duration,
+    pub error: Option<String>,
+    /// The file already existed, so nothing was downloaded.
+    pub skipped: bool,
+    /// Skipped because the server's copy wasn't newer or had not changed.
+    pub current: bool,
+    /// The download was cancelled before it finished.
+    pub cancelled: bool,
+    /// The .part file left on disk by a failed or cancelled download, which
+    /// the next run will resume from.
+    pub partial: Option<PathBuf>,
+}
+
+impl DownloadRecord {
+    /// Average speed in bytes per second.
+    pub fn speed(&self) -> f64 {
+        let elapsed = self.elapsed.as_secs_f64();
+        if elapsed > 0.0 {
+            self.bytes as f64 / elapsed
+        } else {
+            0.0
+        }
+    }
+}
+
+/// Progress of a single download. `size` is 0 when the server sent no length.
+pub struct FileProgress {
+    pub url: String,
+    pub name: String,
+    pub bytes: u64,
+    pub size: u64,
+    /// Bytes already on disk from an earlier attempt when this one started.
+    pub resumed_from: u64,
+    pub start_time: Instant,
+    pub finished: bool,
+    /// The download gave up during this attempt.
+    pub failed: bool,
+    pub window: SpeedWindow,
+}
+
+impl FileProgress {
+    pub fn new(url: &str, name: &str, resumed_from: u64, size: u64) -> Self {
+        FileProgress {
+            url: url.to_string(),
+            name: name.to_string(),
+            bytes: resumed_from,
+            size,
+            resumed_from,
+            start_time: Instant::now(),
+            finished: false,
+            failed: false,
+            window: SpeedWindow::new(resumed_from),
+        }
+    }
+
+    pub fn percent(&self) -> f64 {
+        if self.size > 0 {
+            // A server can send more than it announced
+            ((self.bytes as f64 / self.size as f64) * 100.0).min(100.0)
+        } else {
+            0.0
+        }
+    }
+
+    /// Average speed of this attempt in bytes per second, excluding resumed bytes.
+    pub fn speed(&self) -> f64 {
+        let elapsed = self.start_time.elapsed().as_secs_f64();
+        if elapsed > 0.0 {
+            (self.bytes - self.resumed_from) as f64 / elapsed
+        } else {
+            0.0
+        }
+    }
+
+    /// Speed over the last few seconds in bytes per second.
+    pub fn current_speed(&self) -> f64 {
+        self.window.rate(self.bytes)
+    }
+
+    /// Time left at the current speed, or `None` when the size is unknown
+    /// or nothing is arriving.
+    pub fn eta(&self) -> Option<Duration> {
+        let speed = self.current_speed();
+        if self.size == 0 || speed <= 0.0 {
+            return None;
+        }
+        let remaining = self.size.saturating_sub(self.bytes);
+        Duration::try_from_secs_f64(remaining as f64 / speed).ok()
+    }
+}
+
+/// Ring buffer of `(time, cumulative bytes)` samples for a moving-average speed.
+pub struct SpeedWindow {
+    samples: VecDeque<(Instant, u64)>,
+}
+
+impl SpeedWindow {
+    pub fn new(bytes: u64) -> Self {
+        let mut samples = VecDeque::new();
+        samples.push_back((Instant::now(), bytes));
+        SpeedWindow { samples }
+    }
+
+    /// Records the cumulative byte count, dropping samples that have aged out
+    /// while keeping one just outside the window as the baseline.
+    pub fn record(&mut self, bytes: u64) {
+        let now = Instant::now();
+        if let Some(&(last, _)) = self.samples.back() {
+            if now.duration_since(last) < SAMPLE_INTERVAL {
+                return;
+            }
+        }
+        self.samples.push_back((now, bytes));
+        while self.samples.len() > 2 && now.duration_since(self.samples[1].0) > SPEED_WINDOW {
+            self.samples.pop_front();
+        }
+    }
+
+    /// Bytes per second between the oldest sample and `bytes` now.
+    pub fn rate(&self, bytes: u64) -> f64 {
+        let Some(&(since, base)) = self.samples.front() else {
+            return 0.0;
+        };
+        let elapsed = since.elapsed().as_secs_f64();
+        if elapsed > 0.0 {
+            bytes.saturating_sub(base) as f64 / elapsed
+        } else {
+            0.0
+        }
+    }
+}
@@ -0,0 +1,190 @@
+use rs_downloader::{format_bytes, format_duration, format_speed, DownloadError, DownloadRecord, Probe, Units};
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// Prints a table of every finished download, a grand total and the
+/// success/failure counts.
+pub fn print_summary(records: &[DownloadRecord], elapsed: Duration, units: Units) {
+    let succeeded: Vec<&DownloadRecord> = records
+        .iter()
+        .filter(|r| r.error.is_none() && !r.skipped)
+        .collect();
+
+    if !succeeded.is_empty() {
+        let name_width = succeeded
+            .iter()
+            .map(|r| display_name(r).chars().count())
+            .max()
+            .unwrap_or(0)
+            .max("File".len());
+
+        println!("{:<name_width$}  {:>11}  {:>10}  {:>13}", "File", "Size", "Time", "Speed");
+        for record in &succeeded {
+            println!(
+                "{:<name_width$}  {:>11}  {:>10}  {:>13}",
+                display_name(record),
+                format_bytes(record.bytes, units),
+                format_duration(record.elapsed),
+                format_speed(record.speed(), units),
+            );
+        }
+
+        let total_bytes: u64 = succeeded.iter().map(|r| r.bytes).sum();
+        let total_speed = total_bytes as f64 / elapsed.as_secs_f64().max(f64::EPSILON);
+        println!(
+            "{:<name_width$}  {:>11}  {:>10}  {:>13}",
+            "Total",
+            format_bytes(total_bytes, units),
+            format_duration(elapsed),
+            format_speed(total_speed, units),
+        );
+    }
+
+    let skipped: Vec<&DownloadRecord> = records.iter().filter(|r| r.skipped).collect();
+    if !skipped.is_empty() {
+        println!();
+        println!("Skipped (already exists or unchanged):");
+        for record in skipped {
+            println!("  {}", display_name(record));
+        }
+    }
+
+    print_failures(records);
+}
+
+/// Lists failed downloads with their errors, then the success/failure counts.
+pub fn print_failures(records: &[DownloadRecord]) {
+    let skipped = records.iter().filter(|r| r.skipped).count();
+    let cancelled = records.iter().filter(|r| r.cancelled).count();
+    let mut failures = 0;
+    for record in records.iter().filter(|r| !r.cancelled) {
+        if let Some(error) = &record.error {
+            if failures == 0 {
+                println!();
+                println!("Failed:");
+            }
+            println!("  {}: {}", record.url, error);
+            failures += 1;
+        }
+    }
+    let current = records.iter().filter(|r| r.current).count();
+    print!("{} succeeded, {} skipped", records.len() - skipped - failures - cancelled, skipped);
+    if current > 0 {
+        print!(" ({} already current)", current);
+    }
+    print!(", {} failed", failures);
+    if cancelled > 0 {
+        print!(", {} cancelled", cancelled);
+    }
+    println!(".");
+}
+
+/// Lists cancelled downloads and whether a resumable .part file was kept.
+pub fn print_cancelled(records: &[DownloadRecord], heading: &str) {
+    println!();
+    println!("{}:", heading);
+    for record in records.iter().filter(|r| r.cancelled) {
+        match &record.partial {
+            Some(part) => println!("  {}: partial data kept in {}, run again to resume", record.url, part.display()),
+            None => println!("  {}: no partial data kept", record.url),
+        }
+    }
+}
+
+/// Lists the `(url, part file, bytes)` downloads about to be resumed, out
+/// of `total` URLs.
+pub fn print_continuing(partials: &[(&str, PathBuf, u64)], total: usize, units: Units) {
+    if partials.is_empty() {
+        println!("No partial downloads to continue; starting all {} fresh.", total);
+        return;
+    }
+    println!("Continuing {} partial download(s):", partials.len());
+    for (url, part, len) in partials {
+        println!("  {}: {} in {}", url, format_bytes(*len, units), part.display());
+    }
+    if total > partials.len() {
+        println!("{} more start fresh.", total - partials.len());
+    }
+}
+
+fn display_name(record: &DownloadRecord) -> String {
+    record
+        .path
+        .as_ref()
+        .and_then(|p| p.file_name())
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_else(|| record.url.clone())
+}
+
+/// Prints where each URL would be saved and how big it is, the total, and
+/// any URLs that couldn't be probed.
+pub fn print_plan(urls: &[String], probes: &[Result<Probe, DownloadError>], units: Units) {
+    let found: Vec<&Probe> = probes.iter().filter_map(|p| p.as_ref().ok()).collect();
+    let name_width = found
+        .iter()
+        .map(|p| p.path.display().to_string().chars().count())
+        .max()
+        .unwrap_or(0)
+        .max("File".len());
+
+    if !found.is_empty() {
+        println!("{:<name_width$}  {:>11}  URL", "File", "Size");
+        for probe in &found {
+            let size = probe.size.map_or_else(|| "unknown".to_string(), |size| format_bytes(size, units));
+            println!("{:<name_width$}  {:>11}  {}", probe.path.display().to_string(), size, probe.url);
+        }
+
+        let total: u64 = found.iter().filter_map(|p| p.size).sum();
+        println!("{:<name_width$}  {:>11}", "Total", format_bytes(total, units));
+        let unknown = found.iter().filter(|p| p.size.is_none()).count();
+        if unknown > 0 {
+            println!("{} of {} files have an unknown size and aren't counted.", unknown, found.len());
+        }
+    }
+
+    let mut failures = urls.iter().zip(probes).filter_map(|(url, p)| p.as_ref().err().map(|e| (url, e)));
+    if let Some((url, error)) = failures.next() {
+        println!();
+        println!("Failed:");
+        println!("  {}: {}", url, error);
+        for (url, error) in failures {
+            println!("  {}: {}", url, error);
+        }
+    }
+}
+
+/// Prints whether each URL answered with a success status, then how many
+/// did and didn't.
+pub fn print_spider(urls: &[String], probes: &[Result<Probe, DownloadError>]) {
+    println!("{:<6}  {:>6}  URL", "Result", "Status");
+    for (url, probe) in urls.iter().zip(probes) {
+        match probe {
+            Ok(probe) => println!("{:<6}  {:>6}  {}", "ok", probe.status.as_u16(), url),
+            Err(e) => {
+                let status = match e {
+                    DownloadError::HttpStatus(status, _) | DownloadError::Throttled { status, .. } => status.as_u16().to_string(),
+                    _ => "-".to_string(),
+                };
+                println!("{:<6}  {:>6}  {}: {}", "broken", status, url, e);
+            }
+        }
+    }
+    let broken = probes.iter().filter(|p| p.is_err()).count();
+    println!();
+    println!("{} ok, {} broken.", probes.len() - broken, broken);
+}
+
+/// Prints the URLs that `--head-first` couldn't reach, on stderr.
+pub fn print_unreachable(urls: &[String], probes: &[Result<Probe, DownloadError>], aborting: bool) {
+    let failures: Vec<_> = urls.iter().zip(probes).filter_map(|(url, p)| p.as_ref().err().map(|e| (url, e))).collect();
+    if failures.is_empty() {
+        return;
+    }
+    eprintln!("Unreachable:");
+    for (url, error) in &failures {
+        eprintln!("  {}: {}", url, error);
+    }
+    if aborting {
+        eprintln!("{} of {} URLs failed the check; nothing was downloaded.", failures.len(), urls.len());
+    }
+}
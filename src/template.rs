@@ -0,0 +1,149 @@
+use crate::filename;
+use reqwest::Url;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The placeholders an [`OutputTemplate`] can use.
+const FIELDS: [(&str, Field); 7] = [
+    ("filename", Field::Filename),
+    ("stem", Field::Stem),
+    ("ext", Field::Ext),
+    ("host", Field::Host),
+    ("path", Field::Path),
+    ("index", Field::Index),
+    ("date", Field::Date),
+];
+
+/// Where downloads are saved under the output directory, as a pattern like
+/// `{index}-{filename}` or `{host}/{date}/{filename}`, with `/` between
+/// directories:
+///
+/// - `{filename}`: the name the file would otherwise get, from the URL,
+///   `Content-Disposition` or a redirect
+/// - `{stem}` and `{ext}`: that name without its extension, and the
+///   extension without its dot (empty if there is none)
+/// - `{host}`: the URL's host, with the port if it has one
+/// - `{path}`: the directories of the URL's path, as with `--preserve-path`
+/// - `{index}`: the URL's place in the batch, from 1
+/// - `{date}`: the UTC date the template was parsed, as `YYYY-MM-DD`
+///
+/// `{{` and `}}` stand for literal braces. Each directory and the name are
+/// sanitized like other file names, and `.` and `..` are dropped, so the
+/// result stays inside the output directory.
+#[derive(Clone, Debug)]
+pub struct OutputTemplate {
+    parts: Vec<Part>,
+    date: String,
+}
+
+#[derive(Clone, Debug)]
+enum Part {
+    Text(String),
+    Field(Field),
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Field {
+    Filename,
+    Stem,
+    Ext,
+    Host,
+    Path,
+    Index,
+    Date,
+}
+
+impl FromStr for OutputTemplate {
+    type Err = String;
+
+    fn from_str(pattern: &str) -> Result<Self, String> {
+        let mut parts = vec![];
+        let mut text = String::new();
+        let mut chars = pattern.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    text.push('{');
+                }
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    text.push('}');
+                }
+                '{' => {
+                    let mut name = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(c) => name.push(c),
+                            None => return Err(format!("unclosed {{{} (write {{{{ for a literal brace)", name)),
+                        }
+                    }
+                    let Some(&(_, field)) = FIELDS.iter().find(|(known, _)| *known == name) else {
+                        let known: Vec<String> = FIELDS.iter().map(|(known, _)| format!("{{{}}}", known)).collect();
+                        return Err(format!("unknown placeholder {{{}}} (expected one of {})", name, known.join(", ")));
+                    };
+                    if !text.is_empty() {
+                        parts.push(Part::Text(std::mem::take(&mut text)));
+                    }
+                    parts.push(Part::Field(field));
+                }
+                '}' => return Err("unmatched } (write }} for a literal brace)".to_string()),
+                c => text.push(c),
+            }
+        }
+        if !text.is_empty() {
+            parts.push(Part::Text(text));
+        }
+        if parts.is_empty() {
+            return Err("the template is empty".to_string());
+        }
+        Ok(OutputTemplate { parts, date: today() })
+    }
+}
+
+impl OutputTemplate {
+    /// The relative path `url` is saved at, `file_name` being the name it
+    /// would get without a template and `index` its place in the batch.
+    /// Falls back to `file_name` if nothing is left after sanitizing.
+    pub(crate) fn expand(&self, url: &str, file_name: &str, index: usize) -> PathBuf {
+        let parsed = Url::parse(url).ok();
+        let name = Path::new(file_name);
+        let mut expanded = String::new();
+        for part in &self.parts {
+            match part {
+                Part::Text(text) => expanded.push_str(text),
+                Part::Field(Field::Filename) => expanded.push_str(file_name),
+                Part::Field(Field::Stem) => expanded.push_str(&name.file_stem().unwrap_or_default().to_string_lossy()),
+                Part::Field(Field::Ext) => expanded.push_str(&name.extension().unwrap_or_default().to_string_lossy()),
+                Part::Field(Field::Host) => expanded.push_str(&parsed.as_ref().map(filename::host).unwrap_or_default()),
+                Part::Field(Field::Path) => expanded.push_str(&parsed.as_ref().map(filename::dirs).unwrap_or_default().join("/")),
+                Part::Field(Field::Index) => expanded.push_str(&index.to_string()),
+                Part::Field(Field::Date) => expanded.push_str(&self.date),
+            }
+        }
+
+        let path: PathBuf = expanded.split(['/', '\\']).filter_map(|segment| filename::sanitize(segment.trim())).collect();
+        if path.as_os_str().is_empty() {
+            return PathBuf::from(file_name);
+        }
+        path
+    }
+}
+
+/// Today's date in UTC as `YYYY-MM-DD`.
+fn today() -> String {
+    let days = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs() as i64 / 86_400;
+    // Days since 1970-01-01 to a civil date (Howard Hinnant's civil_from_days)
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 { month_index + 3 } else { month_index - 9 };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
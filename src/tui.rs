@@ -0,0 +1,180 @@
+use crate::format::{format_bytes, format_speed, Units};
+use crate::progress::{format_eta, print_colored, truncate, ProgressConfig, Tally, TerminalReporter};
+use crate::stats::{DownloadStats, FileProgress};
+use std::collections::HashMap;
+use std::io::{stdout, Write};
+use std::sync::Arc;
+use crossterm::{
+    cursor::{Hide, MoveTo, Show},
+    execute, queue,
+    style::Color,
+    terminal::{self, Clear, ClearType, EnterAlternateScreen, LeaveAlternateScreen},
+};
+
+/// Width of each progress bar, not counting its brackets.
+const BAR_WIDTH: usize = 20;
+/// Rows taken by the header and the aggregate footer.
+const CHROME_ROWS: u16 = 3;
+
+/// Draws `reporter`'s progress full-screen every `config.interval` until aborted: a
+/// line per download with its own bar, speed and status, and an aggregate
+/// footer. The terminal is restored when the task is dropped.
+///
+/// Downloads that don't fit scroll, keeping the oldest still running in
+/// view. The layout follows the terminal's size as it is resized.
+pub async fn run_tui(reporter: Arc<TerminalReporter>, config: ProgressConfig) {
+    let Ok(_screen) = Screen::enter() else {
+        return;
+    };
+    let mut size = None;
+    loop {
+        let current = terminal::size().unwrap_or((80, 24));
+        if size != Some(current) {
+            // Stale characters would survive a resize otherwise
+            let _ = execute!(stdout(), Clear(ClearType::All));
+            size = Some(current);
+        }
+        let _ = draw(&mut reporter.tally(), config, current);
+        reporter.next_frame(config.interval).await;
+    }
+}
+
+/// The alternate screen with the cursor hidden, left again on drop.
+struct Screen;
+
+impl Screen {
+    fn enter() -> std::io::Result<Self> {
+        execute!(stdout(), EnterAlternateScreen, Hide)?;
+        Ok(Screen)
+    }
+}
+
+impl Drop for Screen {
+    fn drop(&mut self) {
+        let _ = execute!(stdout(), Show, LeaveAlternateScreen);
+    }
+}
+
+fn draw(tally: &mut Tally, config: ProgressConfig, (width, height): (u16, u16)) -> std::io::Result<()> {
+    tally.tick();
+    let width = width as usize;
+    let stats = &tally.stats;
+    let files = latest_attempts(stats);
+    let active = files.iter().filter(|f| !f.finished).count();
+    let failed = files.iter().filter(|f| f.failed).count();
+    let done = files.len() - active - failed;
+
+    let mut out = stdout();
+    queue!(out, MoveTo(0, 0), Clear(ClearType::CurrentLine))?;
+    let header = format!("{} downloading, {} done, {} failed", active, done, failed);
+    print_colored(&mut out, Color::Green, &truncate(&header, width), config.color)?;
+
+    // Keep the last row free so the cursor doesn't force a scroll
+    let rows = height.saturating_sub(CHROME_ROWS) as usize;
+    let first_active = files.iter().position(|f| !f.finished).unwrap_or(files.len());
+    let offset = first_active.min(files.len().saturating_sub(rows));
+    let shown = &files[offset..files.len().min(offset + rows)];
+
+    for (row, file) in shown.iter().enumerate() {
+        queue!(out, MoveTo(0, row as u16 + 1), Clear(ClearType::CurrentLine))?;
+        let (status, color) = status(file);
+        // Without a length there's no percentage to show, only what has arrived
+        let (bar, amount) = match (file.size, file.finished) {
+            (0, false) => (bouncing_bar(tally.spinner_frame(&file.url)), format_bytes(file.bytes, config.units)),
+            (0, true) => (bar(u64::from(!file.failed), 1), format_bytes(file.bytes, config.units)),
+            (size, _) => (bar(file.bytes, size), format!("{:.1}%", file.percent())),
+        };
+        let line = format!(
+            "{} {} {:>10} {:>12}  {}",
+            name_column(&file.name, width),
+            bar,
+            amount,
+            format_speed(if file.finished { 0.0 } else { file.current_speed() }, config.units),
+            status,
+        );
+        print_colored(&mut out, color, &truncate(&line, width), config.color)?;
+    }
+    for row in shown.len()..rows {
+        queue!(out, MoveTo(0, row as u16 + 1), Clear(ClearType::CurrentLine))?;
+    }
+
+    let footer_row = rows as u16 + 1;
+    queue!(out, MoveTo(0, footer_row), Clear(ClearType::CurrentLine))?;
+    let hidden = files.len() - shown.len();
+    let footer = footer(tally, config.units, offset, hidden - offset);
+    print_colored(&mut out, Color::Blue, &truncate(&footer, width), config.color)?;
+    queue!(out, MoveTo(0, footer_row + 1), Clear(ClearType::CurrentLine))?;
+
+    out.flush()
+}
+
+/// The most recent attempt at each URL, in the order they first started.
+fn latest_attempts(stats: &DownloadStats) -> Vec<&FileProgress> {
+    let mut order: Vec<&str> = vec![];
+    let mut latest: HashMap<&str, &FileProgress> = HashMap::new();
+    for file in &stats.files {
+        if latest.insert(&file.url, file).is_none() {
+            order.push(&file.url);
+        }
+    }
+    order.into_iter().map(|url| latest[url]).collect()
+}
+
+fn status(file: &FileProgress) -> (String, Color) {
+    if file.failed {
+        ("failed".to_string(), Color::Red)
+    } else if file.finished {
+        ("done".to_string(), Color::Green)
+    } else if file.size == 0 {
+        ("downloading".to_string(), Color::Reset)
+    } else {
+        (format!("ETA {}", format_eta(file.eta())), Color::Reset)
+    }
+}
+
+/// The name padded or cut to whatever the rest of the line leaves.
+fn name_column(name: &str, width: usize) -> String {
+    // Bar, percentage or bytes, speed and the longest status around the name
+    let rest = BAR_WIDTH + 2 + 1 + 11 + 13 + 2 + 12;
+    let name_width = width.saturating_sub(rest).max(12);
+    let name = truncate(name, name_width);
+    format!("{:<name_width$}", name)
+}
+
+/// `[#####     ]` filled to the downloaded fraction, or empty when the size
+/// is unknown.
+fn bar(bytes: u64, size: u64) -> String {
+    let filled = if size > 0 {
+        ((bytes.min(size) as f64 / size as f64) * BAR_WIDTH as f64) as usize
+    } else {
+        0
+    };
+    format!("[{}{}]", "#".repeat(filled), " ".repeat(BAR_WIDTH - filled))
+}
+
+/// `[   <=>   ]` with the `<=>` one step further along for each `frame`,
+/// bouncing between the ends, for a download of unknown size.
+fn bouncing_bar(frame: usize) -> String {
+    let travel = BAR_WIDTH - 3;
+    let step = frame % (2 * travel);
+    let at = if step <= travel { step } else { 2 * travel - step };
+    format!("[{}<=>{}]", " ".repeat(at), " ".repeat(travel - at))
+}
+
+fn footer(tally: &Tally, units: Units, above: usize, below: usize) -> String {
+    let stats = &tally.stats;
+    let mut footer = format!(
+        "Total {} of {} so far: {}, at {}",
+        format_bytes(tally.sized_bytes(), units),
+        format_bytes(tally.expected_size(), units),
+        tally.describe_progress(1, units),
+        format_speed(stats.current_speed(), units),
+    );
+    if above > 0 {
+        footer.push_str(&format!(", {} more above", above));
+    }
+    if below > 0 {
+        footer.push_str(&format!(", {} more below", below));
+    }
+    footer
+}
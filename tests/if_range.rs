@@ -0,0 +1,79 @@
+mod common;
+
+use common::{response, scratch_dir, serve};
+use rs_downloader::Downloader;
+
+/// The first 5 of 10 promised bytes, leaving a .part file behind.
+fn cut_short(etag: &str) -> Vec<u8> {
+    format!("HTTP/1.1 200 OK\r\nContent-Length: 10\r\nETag: {}\r\nConnection: close\r\n\r\nold-c", etag).into_bytes()
+}
+
+#[tokio::test]
+async fn a_changed_file_restarts_from_zero() {
+    let (base, received) = serve(|index, request| match (index, request.header("If-Range")) {
+        (0, _) => cut_short("\"v1\""),
+        // The file changed, so the range is refused and the new one sent whole
+        (_, Some("\"v1\"")) => response("200 OK", &[("ETag", "\"v2\"".to_string())], b"new-content"),
+        _ => response("400 Bad Request", &[], b""),
+    })
+    .await;
+    let dir = scratch_dir("if-range-changed");
+    let url = format!("{}/file.txt", base);
+    // Unbuffered, so the bytes of the cut-short attempt reach the .part file
+    let downloader = Downloader::builder().buffer_size(1).output_dir(&dir).build().unwrap();
+
+    assert!(downloader.download(&url).await.is_err());
+    assert_eq!(std::fs::read(dir.join("file.txt.part")).unwrap(), b"old-c");
+    let path = downloader.download(&url).await.unwrap();
+
+    assert_eq!(std::fs::read(&path).unwrap(), b"new-content");
+    let received = received.lock().unwrap();
+    assert_eq!(received[1].header("Range"), Some("bytes=5-"));
+}
+
+#[tokio::test]
+async fn an_unchanged_file_is_appended_to() {
+    let (base, received) = serve(|index, request| match (index, request.header("If-Range")) {
+        (0, _) => cut_short("\"v1\""),
+        (_, Some("\"v1\"")) => response(
+            "206 Partial Content",
+            &[("ETag", "\"v1\"".to_string()), ("Content-Range", "bytes 5-9/10".to_string())],
+            b"ontent",
+        ),
+        _ => response("400 Bad Request", &[], b""),
+    })
+    .await;
+    let dir = scratch_dir("if-range-unchanged");
+    let url = format!("{}/file.txt", base);
+    // Unbuffered, so the bytes of the cut-short attempt reach the .part file
+    let downloader = Downloader::builder().buffer_size(1).output_dir(&dir).build().unwrap();
+
+    assert!(downloader.download(&url).await.is_err());
+    let path = downloader.download(&url).await.unwrap();
+
+    assert_eq!(std::fs::read(&path).unwrap(), b"old-content");
+    assert_eq!(received.lock().unwrap().len(), 2);
+}
+
+#[tokio::test]
+async fn a_preallocated_file_resumes_after_what_was_written() {
+    let body = b"0123456789";
+    let (base, _) = serve(move |index, request| {
+        let from = request.header("Range").and_then(|range| range.strip_prefix("bytes=")?.strip_suffix('-')?.parse::<usize>().ok());
+        match (index, from) {
+            (0, _) => b"HTTP/1.1 200 OK\r\nContent-Length: 10\r\nConnection: close\r\n\r\n01234".to_vec(),
+            (_, Some(from)) => response("206 Partial Content", &[("Content-Range", format!("bytes {}-9/10", from))], &body[from..]),
+            (_, None) => response("200 OK", &[], body),
+        }
+    })
+    .await;
+    let dir = scratch_dir("if-range-preallocated");
+    let url = format!("{}/file.txt", base);
+    // Buffered, so the cut-short attempt's bytes never leave the writer
+    let downloader = Downloader::builder().preallocate(true).output_dir(&dir).build().unwrap();
+
+    assert!(downloader.download(&url).await.is_err());
+    let path = downloader.download(&url).await.unwrap();
+
+    assert_eq!(std::fs::read(&path).unwrap(), body);
+}
@@ -0,0 +1,41 @@
+mod common;
+
+use common::{response, scratch_dir, serve};
+use rs_downloader::{DownloadError, Downloader};
+use std::time::{Duration, Instant, SystemTime};
+
+#[tokio::test]
+async fn waits_out_retry_after_then_downloads() {
+    let (base, received) = serve(|index, _| match index {
+        0 => response("429 Too Many Requests", &[("Retry-After", "1".to_string())], b""),
+        // The date form, a second or two ahead
+        1 => {
+            let date = httpdate::fmt_http_date(SystemTime::now() + Duration::from_secs(1));
+            response("429 Too Many Requests", &[("Retry-After", date)], b"")
+        }
+        _ => response("200 OK", &[], b"finally"),
+    })
+    .await;
+    let dir = scratch_dir("retry-after");
+    let downloader = Downloader::builder().retries(3).output_dir(&dir).build().unwrap();
+
+    let started = Instant::now();
+    let path = downloader.download(&format!("{}/file.txt", base)).await.unwrap();
+
+    assert_eq!(std::fs::read(&path).unwrap(), b"finally");
+    assert_eq!(received.lock().unwrap().len(), 3);
+    // One second for the first 429, and at least most of one for the second
+    assert!(started.elapsed() >= Duration::from_millis(1500), "{:?}", started.elapsed());
+}
+
+#[tokio::test]
+async fn throttling_counts_against_the_retries() {
+    let (base, received) = serve(|_, _| response("429 Too Many Requests", &[("Retry-After", "0".to_string())], b"")).await;
+    let dir = scratch_dir("retry-after-exhausted");
+    let downloader = Downloader::builder().retries(2).output_dir(&dir).build().unwrap();
+
+    let result = downloader.download(&format!("{}/file.txt", base)).await;
+
+    assert!(matches!(result, Err(DownloadError::Throttled { .. })), "{:?}", result.err());
+    assert_eq!(received.lock().unwrap().len(), 3);
+}